@@ -0,0 +1,31 @@
+//! Builds the user programs in `user/`, for `src/programs.rs` to embed.
+//!
+//! They're built by a separate cargo run, with a target directory of their
+//! own so it doesn't wait on the lock of the run building the kernel. The
+//! target and `build-std` come from `.cargo/config.toml` either way.
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    let root = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let target_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("user");
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+
+    let status = Command::new(cargo)
+        .current_dir(&root)
+        .args(&["build", "--release", "--package", "mooos-user", "--bins"])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        // set for the kernel build, and would override the config's
+        .env_remove("CARGO_ENCODED_RUSTFLAGS")
+        .env_remove("RUSTFLAGS")
+        .status()
+        .expect("failed to run cargo for the user programs");
+    assert!(status.success(), "building the user programs failed");
+
+    let bin = target_dir.join("x86_64-mooos").join("release");
+    println!("cargo:rustc-env=MOOOS_USER_BIN={}", bin.display());
+    println!("cargo:rerun-if-changed=user");
+}
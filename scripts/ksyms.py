@@ -0,0 +1,107 @@
+#!/usr/bin/env python3
+"""Embed the kernel's symbol table into its own `.ksyms` section.
+
+usage: ksyms.py <kernel elf>
+
+Reads the function symbols with `nm`, demangles them, packs them into the
+blob format described in `src/symbols.rs` and writes it back into the ELF
+with `objcopy --update-section`. The blob is padded to the size of the
+reserved section so no addresses move.
+"""
+
+import re
+import struct
+import subprocess
+import sys
+import tempfile
+
+MAGIC = b"KSYM"
+HEADER_SIZE = 16
+ENTRY_SIZE = 16
+SECTION = ".ksyms"
+
+# legacy rust mangling leaves a `::h<16 hex digits>` hash on every symbol
+HASH_SUFFIX = re.compile(r"::h[0-9a-f]{16}$")
+
+
+def tool(name):
+    """Prefer the llvm flavour of binutils when installed (rustup's llvm-tools)."""
+    for candidate in ("llvm-" + name, name):
+        try:
+            subprocess.run([candidate, "--version"], capture_output=True, check=True)
+            return candidate
+        except (OSError, subprocess.CalledProcessError):
+            continue
+    sys.exit("ksyms: neither llvm-{0} nor {0} found".format(name))
+
+
+def section_size(elf):
+    out = subprocess.run(
+        [tool("objdump"), "-h", elf], capture_output=True, check=True, text=True
+    ).stdout
+    for line in out.splitlines():
+        fields = line.split()
+        if len(fields) > 2 and fields[1] == SECTION:
+            return int(fields[2], 16)
+    sys.exit("ksyms: {} has no {} section".format(elf, SECTION))
+
+
+def symbols(elf):
+    out = subprocess.run(
+        [tool("nm"), "--defined-only", "--numeric-sort", "--print-size", "--demangle", elf],
+        capture_output=True,
+        check=True,
+        text=True,
+    ).stdout
+    for line in out.splitlines():
+        fields = line.split(maxsplit=3)
+        # "<addr> <size> <type> <name>" or, without a size, "<addr> <type> <name>"
+        if len(fields) == 4:
+            addr, size, kind, name = fields
+        elif len(fields) == 3:
+            addr, kind, name = fields
+            size = "0"
+        else:
+            continue
+        if kind not in "tTwW":
+            continue
+        yield int(addr, 16), int(size, 16), HASH_SUFFIX.sub("", name)
+
+
+def pack(syms):
+    syms = sorted(syms)
+    strings = bytearray()
+    entries = bytearray()
+    for addr, size, name in syms:
+        entries += struct.pack("<QII", addr, min(size, 0xFFFFFFFF), len(strings))
+        strings += name.encode() + b"\0"
+    strings_offset = HEADER_SIZE + len(entries)
+    header = MAGIC + struct.pack("<III", len(syms), strings_offset, 0)
+    return header + entries + strings
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+    elf = sys.argv[1]
+
+    capacity = section_size(elf)
+    blob = pack(symbols(elf))
+    if len(blob) > capacity:
+        sys.exit(
+            "ksyms: symbol table is {} bytes but only {} are reserved, "
+            "raise symbols::CAPACITY".format(len(blob), capacity)
+        )
+    blob += bytes(capacity - len(blob))
+
+    with tempfile.NamedTemporaryFile() as f:
+        f.write(blob)
+        f.flush()
+        subprocess.run(
+            [tool("objcopy"), "--update-section", "{}={}".format(SECTION, f.name), elf],
+            check=True,
+        )
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,6 @@
+#!/bin/sh
+# cargo runner: embed the symbol table into the kernel, then boot it in QEMU.
+set -e
+
+python3 "$(dirname "$0")/ksyms.py" "$1"
+exec bootimage runner "$@"
@@ -0,0 +1,135 @@
+//! Just enough ACPI to find tables by signature.
+//!
+//! The RSDP is found by scanning the BIOS areas, and the tables themselves are
+//! read through the bootloader's mapping of physical memory, so this only
+//! works after `memory::init`.
+
+use crate::memory::phys_to_virt;
+use core::mem;
+use core::ptr;
+use x86_64::PhysAddr;
+
+/// Root System Description Pointer. Revision 2 and up append the XSDT fields.
+#[allow(dead_code)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Size of the revision 0 part of the RSDP.
+const RSDP_V1_LENGTH: usize = 20;
+
+/// Header shared by every system description table.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// How ACPI describes where a register lives.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SYSTEM_MEMORY: u8 = 0;
+    pub const SYSTEM_IO: u8 = 1;
+}
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0
+}
+
+unsafe fn phys_slice(addr: u64, len: usize) -> &'static [u8] {
+    core::slice::from_raw_parts(phys_to_virt(PhysAddr::new(addr)).as_ptr(), len)
+}
+
+/// Looks for the RSDP on a 16 byte boundary in `[start, start + len)`.
+fn scan_for_rsdp(start: u64, len: u64) -> Option<&'static Rsdp> {
+    (start..start + len).step_by(16).find_map(|addr| {
+        let bytes = unsafe { phys_slice(addr, RSDP_V1_LENGTH) };
+        if &bytes[..8] == b"RSD PTR " && checksum_ok(bytes) {
+            Some(unsafe { &*(bytes.as_ptr() as *const Rsdp) })
+        } else {
+            None
+        }
+    })
+}
+
+fn find_rsdp() -> Option<&'static Rsdp> {
+    // the first KiB of the EBDA, whose segment the BIOS leaves at 0x40e
+    let ebda_segment = phys_to_virt(PhysAddr::new(0x40e)).as_ptr::<u16>();
+    let ebda = u64::from(unsafe { ptr::read_unaligned(ebda_segment) }) << 4;
+    if ebda != 0 {
+        if let Some(rsdp) = scan_for_rsdp(ebda, 1024) {
+            return Some(rsdp);
+        }
+    }
+    // then the BIOS read-only area
+    scan_for_rsdp(0xe_0000, 0x2_0000)
+}
+
+/// Returns the table with the given signature, if the firmware provides one.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    let rsdp = find_rsdp()?;
+
+    // the XSDT holds 64 bit pointers, the older RSDT 32 bit ones
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address, 8)
+    } else {
+        (u64::from(rsdp.rsdt_address), 4)
+    };
+
+    let root = table_at(root)?;
+    let header_size = mem::size_of::<SdtHeader>();
+    let entries = (root.length as usize).saturating_sub(header_size) / entry_size;
+    let first_entry = root as *const SdtHeader as usize + header_size;
+
+    (0..entries).find_map(|i| {
+        let entry = (first_entry + i * entry_size) as *const u8;
+        let addr = unsafe {
+            if entry_size == 8 {
+                ptr::read_unaligned(entry as *const u64)
+            } else {
+                u64::from(ptr::read_unaligned(entry as *const u32))
+            }
+        };
+        table_at(addr).filter(|table| &table.signature == signature)
+    })
+}
+
+fn table_at(addr: u64) -> Option<&'static SdtHeader> {
+    let header = unsafe { &*phys_to_virt(PhysAddr::new(addr)).as_ptr::<SdtHeader>() };
+    let length = header.length as usize;
+    if length < mem::size_of::<SdtHeader>() {
+        return None;
+    }
+    let bytes = unsafe { phys_slice(addr, length) };
+    if checksum_ok(bytes) {
+        Some(header)
+    } else {
+        None
+    }
+}
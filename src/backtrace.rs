@@ -0,0 +1,146 @@
+//! Stack walking by following the saved frame pointer chain.
+//!
+//! The kernel is built with `-C force-frame-pointers=yes` (see
+//! `.cargo/config.toml`), so every function starts with `push rbp; mov rbp, rsp`.
+//! That turns the stack into a linked list: `[rbp]` holds the caller's `rbp`
+//! and `[rbp + 8]` the return address into the caller.
+
+use crate::symbols;
+use crate::{println, serial_println};
+use core::arch::asm;
+use core::fmt;
+use x86_64::VirtAddr;
+
+/// Stop after this many frames in case the chain loops or runs off the stack.
+const MAX_DEPTH: usize = 64;
+
+/// Largest gap between two consecutive frame pointers we still believe in.
+const MAX_FRAME_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub rbp: u64,
+    pub return_address: u64,
+}
+
+impl Frame {
+    /// The function this frame returns into.
+    ///
+    /// The return address points just past the `call`, which may already be
+    /// the start of the next function, so we look up the byte before it.
+    pub fn symbol(&self) -> Option<symbols::Symbol> {
+        let mut symbol = symbols::lookup(self.return_address - 1)?;
+        symbol.offset += 1;
+        Some(symbol)
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#018x}", self.return_address)?;
+        match self.symbol() {
+            Some(symbol) => write!(f, " - {}", symbol),
+            None => write!(f, " - <unknown>"),
+        }
+    }
+}
+
+pub struct Frames {
+    rbp: u64,
+    depth: usize,
+}
+
+impl Iterator for Frames {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        if self.depth >= MAX_DEPTH || !plausible(self.rbp) {
+            return None;
+        }
+
+        let (caller_rbp, return_address) = unsafe {
+            let ptr = self.rbp as *const u64;
+            (ptr.read(), ptr.add(1).read())
+        };
+        if return_address == 0 {
+            return None;
+        }
+
+        let frame = Frame { rbp: self.rbp, return_address };
+        // the stack grows down, so callers must sit at higher addresses
+        self.rbp = if caller_rbp > self.rbp && caller_rbp - self.rbp <= MAX_FRAME_SIZE {
+            caller_rbp
+        } else {
+            0
+        };
+        self.depth += 1;
+        Some(frame)
+    }
+}
+
+fn plausible(rbp: u64) -> bool {
+    rbp != 0 && rbp % 8 == 0 && VirtAddr::try_new(rbp).is_ok()
+}
+
+/// Returns the frame pointer of the calling function.
+#[inline(always)]
+pub fn current_rbp() -> u64 {
+    let rbp: u64;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+    rbp
+}
+
+/// Walks the stack starting at the frame `rbp` points to.
+///
+/// Reading the chain dereferences whatever `rbp` points to, so a corrupted
+/// stack can still fault, but the walk gives up on anything that doesn't
+/// look like a frame further up the same stack.
+pub fn frames_from(rbp: u64) -> Frames {
+    Frames { rbp, depth: 0 }
+}
+
+/// Walks the stack of the calling function.
+#[inline(always)]
+pub fn frames() -> Frames {
+    frames_from(current_rbp())
+}
+
+/// Prints a symbolized backtrace to both the VGA buffer and serial.
+#[inline(always)]
+pub fn print() {
+    print_from(current_rbp());
+}
+
+pub fn print_from(rbp: u64) {
+    println!("backtrace:");
+    serial_println!("backtrace:");
+    for (i, frame) in frames_from(rbp).enumerate() {
+        println!("{:>4}: {}", i, frame);
+        serial_println!("{:>4}: {}", i, frame);
+    }
+}
+
+/// Prints a symbolized backtrace to serial only, for test kernels.
+#[inline(always)]
+pub fn serial_print() {
+    serial_print_from(current_rbp());
+}
+
+pub fn serial_print_from(rbp: u64) {
+    serial_println!("backtrace:");
+    for (i, frame) in frames_from(rbp).enumerate() {
+        serial_println!("{:>4}: {}", i, frame);
+    }
+}
+
+#[test_case]
+fn test_frames_walks_up() {
+    let mut frames = frames();
+    let first = frames.next().expect("no frames");
+    assert!(first.return_address != 0);
+    for frame in frames {
+        assert!(frame.rbp > first.rbp);
+    }
+}
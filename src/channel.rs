@@ -0,0 +1,232 @@
+//! Channels: pairs of endpoints passing fixed-size messages both ways.
+//!
+//! Each message carries `MESSAGE_SIZE` bytes and optionally a handle, any
+//! open `File`, which the receiver gets a reference to. That includes other
+//! endpoints, so a service can hand a client a private channel. Endpoints
+//! are `File`s themselves, which is how the `channel`, `send` and `recv`
+//! system calls find them in file tables, but kernel threads use them
+//! directly.
+//!
+//! Sending blocks while the other side has `CHANNEL_CAPACITY` messages
+//! waiting; receiving blocks while there are none, or fails right away for
+//! `try_recv`. Either wait ends with `Error::Interrupted` if the calling
+//! process is interrupted (see `WaitQueue::wait_until_interruptible`).
+//! Async tasks wait for messages with `recv_async` instead; sending has no
+//! async version and blocks them while the queue is full. Once an endpoint goes, sends to it fail with
+//! `Error::BrokenPipe`, and so do receives from it after the messages it
+//! sent are used up. Messages still queued for an endpoint go with it.
+//!
+//! A message can't carry an endpoint of its own channel: queued on that
+//! channel, it would keep it open for good, so `send` refuses it with
+//! `Error::InvalidArgument`.
+
+use crate::file::File;
+use crate::sync::{Condvar, Interrupted, Mutex};
+use crate::syscall::Error;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::mem;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_util::task::AtomicWaker;
+
+/// Bytes in a message.
+pub const MESSAGE_SIZE: usize = 64;
+/// Messages an endpoint can have waiting before senders to it block.
+pub const CHANNEL_CAPACITY: usize = 16;
+
+pub struct Message {
+    pub data: [u8; MESSAGE_SIZE],
+    /// A file handed over with the data.
+    pub handle: Option<Arc<dyn File>>,
+}
+
+impl Message {
+    /// A message with `data` at the start and zeroes after it.
+    ///
+    /// Panics if `data` is longer than `MESSAGE_SIZE`.
+    pub fn new(data: &[u8]) -> Self {
+        let mut message = Message { data: [0; MESSAGE_SIZE], handle: None };
+        message.data[..data.len()].copy_from_slice(data);
+        message
+    }
+
+    pub fn with_handle(mut self, handle: Arc<dyn File>) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+}
+
+/// A message as the `send` and `recv` system calls take it from user code.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct RawMessage {
+    pub data: [u8; MESSAGE_SIZE],
+    /// The handle's file descriptor, or -1 for none.
+    pub handle: i64,
+}
+
+struct State {
+    /// Messages waiting for each endpoint.
+    queues: [VecDeque<Message>; 2],
+    open: [bool; 2],
+}
+
+struct Channel {
+    state: Mutex<State>,
+    /// Notified when a queue gets a message, or its sender closes.
+    arrived: [Condvar; 2],
+    /// Notified when a queue has room, or its receiver closes.
+    left: [Condvar; 2],
+    /// Woken with `arrived`, for the task waiting in `recv_async`.
+    arrived_async: [AtomicWaker; 2],
+}
+
+/// Makes a channel and returns its two endpoints.
+pub fn channel() -> (Endpoint, Endpoint) {
+    let channel = Arc::new(Channel {
+        state: Mutex::new(State {
+            queues: [VecDeque::new(), VecDeque::new()],
+            open: [true, true],
+        }),
+        arrived: [Condvar::new(), Condvar::new()],
+        left: [Condvar::new(), Condvar::new()],
+        arrived_async: [AtomicWaker::new(), AtomicWaker::new()],
+    });
+    (Endpoint { channel: channel.clone(), side: 0 }, Endpoint { channel, side: 1 })
+}
+
+pub struct Endpoint {
+    channel: Arc<Channel>,
+    /// Which queue this endpoint receives from; it sends to the other.
+    side: usize,
+}
+
+impl Endpoint {
+    fn peer(&self) -> usize {
+        1 - self.side
+    }
+
+    /// Queues `message` for the other endpoint, blocking while its queue is
+    /// full.
+    pub fn send(&self, message: Message) -> Result<(), Error> {
+        let channel = &self.channel;
+        let peer = self.peer();
+        let handle = message.handle.as_ref().and_then(|handle| handle.as_endpoint());
+        if handle.map_or(false, |endpoint| Arc::ptr_eq(&endpoint.channel, channel)) {
+            return Err(Error::InvalidArgument);
+        }
+        let state = channel.state.lock();
+        let mut state = channel.left[peer]
+            .wait_while_interruptible(state, |state| {
+                state.queues[peer].len() == CHANNEL_CAPACITY && state.open[peer]
+            })
+            .map_err(|Interrupted| Error::Interrupted)?;
+        if !state.open[peer] {
+            // the message, and its handle, go once the lock is released
+            drop(state);
+            return Err(Error::BrokenPipe);
+        }
+        state.queues[peer].push_back(message);
+        drop(state);
+        channel.arrived[peer].notify_one();
+        channel.arrived_async[peer].wake();
+        Ok(())
+    }
+
+    /// Takes the next message, blocking until there is one.
+    pub fn recv(&self) -> Result<Message, Error> {
+        self.receive(true)
+    }
+
+    /// Takes the next message, failing with `Error::TryAgain` if there
+    /// isn't one yet.
+    pub fn try_recv(&self) -> Result<Message, Error> {
+        self.receive(false)
+    }
+
+    /// Takes the next message once there is one, from an async task. Only
+    /// one task at a time should wait on an endpoint; a second one replaces
+    /// the first's waker.
+    pub fn recv_async(&self) -> Recv<'_> {
+        Recv { endpoint: self }
+    }
+
+    fn receive(&self, block: bool) -> Result<Message, Error> {
+        let channel = &self.channel;
+        let side = self.side;
+        let peer = self.peer();
+        let mut state = channel.state.lock();
+        if block {
+            state = channel.arrived[side]
+                .wait_while_interruptible(state, |state| {
+                    state.queues[side].is_empty() && state.open[peer]
+                })
+                .map_err(|Interrupted| Error::Interrupted)?;
+        }
+        let message = match state.queues[side].pop_front() {
+            Some(message) => message,
+            None if state.open[peer] => return Err(Error::TryAgain),
+            None => return Err(Error::BrokenPipe),
+        };
+        drop(state);
+        channel.left[side].notify_one();
+        Ok(message)
+    }
+
+    /// Puts `message` back at the front of this endpoint's queue, for a
+    /// receiver that couldn't take it after all. The queue may end up one
+    /// over `CHANNEL_CAPACITY`.
+    pub(crate) fn unreceive(&self, message: Message) {
+        self.channel.state.lock().queues[self.side].push_front(message);
+        self.channel.arrived[self.side].notify_one();
+        self.channel.arrived_async[self.side].wake();
+    }
+}
+
+/// Future returned by `Endpoint::recv_async`.
+pub struct Recv<'a> {
+    endpoint: &'a Endpoint,
+}
+
+impl Future for Recv<'_> {
+    type Output = Result<Message, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<Message, Error>> {
+        let endpoint = self.endpoint;
+        match endpoint.try_recv() {
+            Err(Error::TryAgain) => {}
+            result => return Poll::Ready(result),
+        }
+        // register before checking again, or a message sent in between
+        // would not wake us
+        endpoint.channel.arrived_async[endpoint.side].register(cx.waker());
+        match endpoint.try_recv() {
+            Err(Error::TryAgain) => Poll::Pending,
+            result => Poll::Ready(result),
+        }
+    }
+}
+
+impl File for Endpoint {
+    fn as_endpoint(&self) -> Option<&Endpoint> {
+        Some(self)
+    }
+}
+
+impl Drop for Endpoint {
+    fn drop(&mut self) {
+        let channel = &self.channel;
+        let mut state = channel.state.lock();
+        state.open[self.side] = false;
+        // dropped unlocked, since their handles may be endpoints of
+        // channels whose queues lead back to this one
+        let unreceived = mem::take(&mut state.queues[self.side]);
+        drop(state);
+        channel.arrived[self.peer()].notify_all();
+        channel.arrived_async[self.peer()].wake();
+        channel.left[self.side].notify_all();
+        drop(unreceived);
+    }
+}
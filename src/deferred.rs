@@ -0,0 +1,81 @@
+//! Deferred interrupt work, a.k.a. bottom halves.
+//!
+//! An interrupt handler should do as little as possible: grab whatever the
+//! hardware has for it, queue a work item with `schedule`, acknowledge the
+//! interrupt and return. The work items run later from the `Softirq::Work`
+//! softirq, in normal context with interrupts enabled, where they are free
+//! to take locks, allocate and print.
+//!
+//! A work item is just a function pointer and a word of data, so queueing
+//! one never allocates.
+
+use crate::softirq::{self, Softirq};
+use crate::sync::ArrayQueue;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const QUEUE_SIZE: usize = 256;
+
+#[derive(Clone, Copy)]
+struct Work {
+    func: fn(usize),
+    data: usize,
+}
+
+static QUEUE: ArrayQueue<Work, QUEUE_SIZE> = ArrayQueue::new();
+
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Returned by `schedule` when too much work is already waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+/// Queues `func(data)` to run soon from softirq context.
+///
+/// Safe to call from interrupt handlers. If the queue is full the work is
+/// dropped, counted in `dropped`, and `QueueFull` returned.
+pub fn schedule(func: fn(usize), data: usize) -> Result<(), QueueFull> {
+    let result = QUEUE.push(Work { func, data }).map_err(|_| {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        QueueFull
+    });
+    softirq::raise(Softirq::Work);
+    result
+}
+
+/// Number of work items lost to a full queue since boot.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Runs queued work items. This is the `Softirq::Work` handler.
+///
+/// Only drains what was queued when it started, so work items that keep
+/// scheduling more work can't keep it going forever; the softirq is raised
+/// again for whatever is left.
+pub(crate) fn run_pending() {
+    for _ in 0..QUEUE.len() {
+        match QUEUE.pop() {
+            Some(work) => (work.func)(work.data),
+            None => return,
+        }
+    }
+    if !QUEUE.is_empty() {
+        softirq::raise(Softirq::Work);
+    }
+}
+
+#[test_case]
+fn test_work_runs_in_order() {
+    static LOG: AtomicU64 = AtomicU64::new(0);
+    fn record(digit: usize) {
+        let log = LOG.load(Ordering::Relaxed);
+        LOG.store(log * 10 + digit as u64, Ordering::Relaxed);
+    }
+
+    for digit in 1..=3 {
+        schedule(record, digit).unwrap();
+    }
+    assert_eq!(LOG.load(Ordering::Relaxed), 0);
+    softirq::run_pending();
+    assert_eq!(LOG.load(Ordering::Relaxed), 123);
+}
@@ -0,0 +1,231 @@
+//! ELF64 parsing, just enough to load executables.
+//!
+//! `Elf::parse` checks the file header and every program header against the
+//! size of the image up front, so nothing read from the image afterwards can
+//! point outside of it. Only statically linked little-endian x86_64
+//! executables are accepted; section headers are ignored.
+
+use core::convert::TryInto;
+
+/// Program header type of a segment to load.
+pub const PT_LOAD: u32 = 1;
+/// Program header type of the program header table itself.
+pub const PT_PHDR: u32 = 6;
+
+/// Segment permission bits.
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3e;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The image ends before a header or segment it describes.
+    Truncated,
+    BadMagic,
+    /// Not 64-bit, not little endian, or an unknown version.
+    UnsupportedFormat,
+    /// Not an executable, e.g. a shared library or object file.
+    NotExecutable,
+    WrongMachine,
+    /// A program header that makes no sense, like a segment whose size in
+    /// the file exceeds its size in memory.
+    BadProgramHeader,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    fn parse(bytes: &[u8]) -> Self {
+        ProgramHeader {
+            kind: u32_at(bytes, 0),
+            flags: u32_at(bytes, 4),
+            offset: u64_at(bytes, 8),
+            vaddr: u64_at(bytes, 16),
+            filesz: u64_at(bytes, 32),
+            memsz: u64_at(bytes, 40),
+            align: u64_at(bytes, 48),
+        }
+    }
+
+    fn validate(&self, image_len: usize) -> Result<(), ElfError> {
+        let file_end = self.offset.checked_add(self.filesz).ok_or(ElfError::BadProgramHeader)?;
+        if file_end > image_len as u64 {
+            return Err(ElfError::Truncated);
+        }
+        if self.filesz > self.memsz || self.vaddr.checked_add(self.memsz).is_none() {
+            return Err(ElfError::BadProgramHeader);
+        }
+        // loading maps whole pages, so the file and memory layouts have to
+        // agree on the offset into the page
+        if self.align > 1 && (!self.align.is_power_of_two() || (self.vaddr ^ self.offset) % self.align != 0) {
+            return Err(ElfError::BadProgramHeader);
+        }
+        Ok(())
+    }
+}
+
+pub struct Elf<'a> {
+    image: &'a [u8],
+    entry: u64,
+    phoff: usize,
+    phentsize: usize,
+    phnum: usize,
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(image: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        if image.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        if &image[0..4] != MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if image[4] != CLASS_64 || image[5] != DATA_LITTLE_ENDIAN || image[6] != VERSION_CURRENT {
+            return Err(ElfError::UnsupportedFormat);
+        }
+        if u16_at(image, 16) != TYPE_EXECUTABLE {
+            return Err(ElfError::NotExecutable);
+        }
+        if u16_at(image, 18) != MACHINE_X86_64 {
+            return Err(ElfError::WrongMachine);
+        }
+
+        let elf = Elf {
+            image,
+            entry: u64_at(image, 24),
+            phoff: u64_at(image, 32).try_into().map_err(|_| ElfError::Truncated)?,
+            phentsize: usize::from(u16_at(image, 54)),
+            phnum: usize::from(u16_at(image, 56)),
+        };
+        if elf.phentsize < PROGRAM_HEADER_SIZE {
+            return Err(ElfError::BadProgramHeader);
+        }
+        let table_end = elf
+            .phnum
+            .checked_mul(elf.phentsize)
+            .and_then(|size| size.checked_add(elf.phoff))
+            .ok_or(ElfError::Truncated)?;
+        if table_end > image.len() {
+            return Err(ElfError::Truncated);
+        }
+        for header in elf.program_headers() {
+            header.validate(image.len())?;
+        }
+        Ok(elf)
+    }
+
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Where the program header table starts in the file.
+    pub fn program_header_offset(&self) -> u64 {
+        self.phoff as u64
+    }
+
+    pub fn program_header_size(&self) -> usize {
+        self.phentsize
+    }
+
+    pub fn program_header_count(&self) -> usize {
+        self.phnum
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let image = self.image;
+        let (phoff, phentsize) = (self.phoff, self.phentsize);
+        (0..self.phnum).map(move |i| ProgramHeader::parse(&image[phoff + i * phentsize..]))
+    }
+
+    /// The part of the file `header` loads from.
+    pub fn segment_data(&self, header: &ProgramHeader) -> &'a [u8] {
+        // checked by `parse`
+        &self.image[header.offset as usize..(header.offset + header.filesz) as usize]
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+fn test_header() -> [u8; HEADER_SIZE + PROGRAM_HEADER_SIZE] {
+    let mut image = [0; HEADER_SIZE + PROGRAM_HEADER_SIZE];
+    image[0..4].copy_from_slice(MAGIC);
+    image[4] = CLASS_64;
+    image[5] = DATA_LITTLE_ENDIAN;
+    image[6] = VERSION_CURRENT;
+    image[16..18].copy_from_slice(&TYPE_EXECUTABLE.to_le_bytes());
+    image[18..20].copy_from_slice(&MACHINE_X86_64.to_le_bytes());
+    image[24..32].copy_from_slice(&0x1000_0000_0000u64.to_le_bytes());
+    image[32..40].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+    image[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    image[56..58].copy_from_slice(&1u16.to_le_bytes());
+    // one PT_LOAD covering the headers
+    let ph = &mut image[HEADER_SIZE..];
+    ph[0..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+    ph[4..8].copy_from_slice(&(PF_R | PF_X).to_le_bytes());
+    ph[16..24].copy_from_slice(&0x1000_0000_0000u64.to_le_bytes());
+    ph[32..40].copy_from_slice(&((HEADER_SIZE + PROGRAM_HEADER_SIZE) as u64).to_le_bytes());
+    ph[40..48].copy_from_slice(&0x2000u64.to_le_bytes());
+    image
+}
+
+#[test_case]
+fn test_parses_program_headers() {
+    let image = test_header();
+    let elf = Elf::parse(&image).expect("parse failed");
+    assert_eq!(elf.entry(), 0x1000_0000_0000);
+    // no heap in the unit test kernel, so no collecting them
+    assert_eq!(elf.program_headers().count(), 1);
+    let header = elf.program_headers().next().expect("no program header");
+    assert_eq!(header.kind, PT_LOAD);
+    assert_eq!(header.memsz, 0x2000);
+    assert_eq!(elf.segment_data(&header).len(), image.len());
+}
+
+#[test_case]
+fn test_rejects_bad_images() {
+    let image = test_header();
+    assert_eq!(Elf::parse(&image[..HEADER_SIZE + 8]).err(), Some(ElfError::Truncated));
+
+    let mut bad = image;
+    bad[0] = 0;
+    assert_eq!(Elf::parse(&bad).err(), Some(ElfError::BadMagic));
+
+    let mut bad = image;
+    bad[16] = 3; // shared object
+    assert_eq!(Elf::parse(&bad).err(), Some(ElfError::NotExecutable));
+
+    // more in the file than in memory
+    let mut bad = image;
+    bad[HEADER_SIZE + 40..HEADER_SIZE + 48].copy_from_slice(&1u64.to_le_bytes());
+    assert_eq!(Elf::parse(&bad).err(), Some(ElfError::BadProgramHeader));
+}
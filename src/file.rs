@@ -0,0 +1,141 @@
+//! Open files and the per-process table of them.
+//!
+//! A file is anything implementing `File`; user code refers to it by its
+//! index in the process's `FileTable`. The table starts out with the
+//! console: keyboard input on 0, the screen on 1 and the serial port on 2.
+
+use crate::channel::Endpoint;
+use crate::syscall::Error;
+use crate::{keyboard, print, serial_print};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Most files a process can have open at once.
+pub const MAX_FILES: usize = 64;
+
+pub trait File: Send + Sync {
+    /// Reads into `buf`, blocking until at least one byte is available or
+    /// the end is reached, and returns the number of bytes read.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let _ = buf;
+        Err(Error::BadFileDescriptor)
+    }
+
+    /// Writes from `buf` and returns the number of bytes written.
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        let _ = buf;
+        Err(Error::BadFileDescriptor)
+    }
+
+    /// The channel endpoint this is, if it is one.
+    fn as_endpoint(&self) -> Option<&Endpoint> {
+        None
+    }
+}
+
+/// Keyboard input, as decoded by `keyboard::print_keypresses`.
+pub struct Keyboard;
+
+impl File for Keyboard {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        keyboard::read(buf).ok_or(Error::Interrupted)
+    }
+}
+
+/// The VGA text console.
+pub struct Screen;
+
+impl File for Screen {
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        print!("{}", String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+}
+
+/// The first serial port.
+pub struct Serial;
+
+impl File for Serial {
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        serial_print!("{}", String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+}
+
+#[derive(Clone)]
+pub struct FileTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+
+impl FileTable {
+    pub fn new() -> Self {
+        FileTable { files: Vec::new() }
+    }
+
+    /// A table with the console open on 0, 1 and 2.
+    pub fn with_console() -> Self {
+        let mut table = FileTable::new();
+        table.files.push(Some(Arc::new(Keyboard)));
+        table.files.push(Some(Arc::new(Screen)));
+        table.files.push(Some(Arc::new(Serial)));
+        table
+    }
+
+    pub fn get(&self, fd: u64) -> Result<Arc<dyn File>, Error> {
+        self.files
+            .get(fd as usize)
+            .and_then(Option::clone)
+            .ok_or(Error::BadFileDescriptor)
+    }
+
+    /// Adds `file` at the lowest free index and returns that.
+    pub fn open(&mut self, file: Arc<dyn File>) -> Result<u64, Error> {
+        if let Some(fd) = self.files.iter().position(Option::is_none) {
+            self.files[fd] = Some(file);
+            return Ok(fd as u64);
+        }
+        if self.files.len() >= MAX_FILES {
+            return Err(Error::TooManyFiles);
+        }
+        self.files.push(Some(file));
+        Ok(self.files.len() as u64 - 1)
+    }
+
+    /// Drops the table's reference to file `fd`.
+    pub fn close(&mut self, fd: u64) -> Result<(), Error> {
+        match self.files.get_mut(fd as usize) {
+            Some(file @ Some(_)) => {
+                *file = None;
+                Ok(())
+            }
+            _ => Err(Error::BadFileDescriptor),
+        }
+    }
+
+    /// Makes `new` refer to file `fd` too, closing whatever it was first,
+    /// and returns it.
+    pub fn dup2(&mut self, fd: u64, new: u64) -> Result<u64, Error> {
+        let file = self.get(fd)?;
+        let index = new as usize;
+        if index >= MAX_FILES {
+            return Err(Error::BadFileDescriptor);
+        }
+        if self.files.len() <= index {
+            self.files.resize(index + 1, None);
+        }
+        self.files[index] = Some(file);
+        Ok(new)
+    }
+
+    /// Closes everything.
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+impl Default for FileTable {
+    fn default() -> Self {
+        FileTable::new()
+    }
+}
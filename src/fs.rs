@@ -0,0 +1,34 @@
+//! A flat, read-only file system in memory, where `exec` finds executables.
+//!
+//! Files are byte slices registered under a path, typically images built
+//! into the kernel. There are no directories: "/bin/hello" is just a name.
+
+use crate::sync::RwLock;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref FILES: RwLock<BTreeMap<String, &'static [u8]>> = RwLock::new(BTreeMap::new());
+}
+
+/// Makes `data` available under `path`, replacing whatever was there.
+pub fn install(path: &str, data: &'static [u8]) {
+    FILES.write().insert(path.to_string(), data);
+}
+
+/// Removes the file at `path`, returning whether there was one.
+pub fn remove(path: &str) -> bool {
+    FILES.write().remove(path).is_some()
+}
+
+/// The contents of the file at `path`.
+pub fn lookup(path: &str) -> Option<&'static [u8]> {
+    FILES.read().get(path).copied()
+}
+
+/// The paths of all files, in order.
+pub fn paths() -> Vec<String> {
+    FILES.read().keys().cloned().collect()
+}
@@ -1,46 +1,113 @@
 use x86_64::VirtAddr;
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::structures::gdt::{GlobalDescriptorTable, Descriptor, SegmentSelector};
+use x86_64::PrivilegeLevel;
 use lazy_static::lazy_static;
+use core::ptr::{addr_of, addr_of_mut};
 
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
 
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
+/// Filled in by `init` before the GDT points the CPU at it; after that only
+/// `set_kernel_stack` touches it.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+/// Kernel stack for entries from ring 3 while no kernel thread with a stack
+/// of its own is running, i.e. on the boot thread.
+const BOOT_KERNEL_STACK_SIZE: usize = 4096 * 5;
+static mut BOOT_KERNEL_STACK: [u8; BOOT_KERNEL_STACK_SIZE] = [0; BOOT_KERNEL_STACK_SIZE];
+
+fn init_tss() {
+    let tss = unsafe { &mut *addr_of_mut!(TSS) };
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+
+        let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+        let stack_end = stack_start + STACK_SIZE;
+        stack_end
+    };
+    // an NMI can arrive at any instruction, including while the stack
+    // pointer is bogus, so it gets a known good stack of its own
+    tss.interrupt_stack_table[NMI_IST_INDEX as usize] = {
+        const STACK_SIZE: usize = 4096 * 5;
+        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+
+        let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
+        stack_start + STACK_SIZE
     };
+    set_kernel_stack(boot_kernel_stack());
+}
+
+/// Top of the kernel stack used by the boot thread for entries from ring 3.
+pub fn boot_kernel_stack() -> VirtAddr {
+    let start = VirtAddr::from_ptr(unsafe { addr_of!(BOOT_KERNEL_STACK) });
+    (start + BOOT_KERNEL_STACK_SIZE).align_down(16u64)
+}
+
+/// Copy of the TSS's ring 0 stack pointer for the `syscall` entry stub,
+/// which gets no stack switch from the CPU.
+#[no_mangle]
+static mut MOOOS_KERNEL_STACK_TOP: u64 = 0;
+
+/// Sets the stack the kernel switches to when an interrupt, exception or
+/// system call arrives while running in ring 3.
+///
+/// The thread scheduler calls this on every switch, so that each thread
+/// enters the kernel on its own stack. `top` should be 16 byte aligned. Call
+/// with interrupts disabled.
+pub fn set_kernel_stack(top: VirtAddr) {
+    unsafe {
+        (*addr_of_mut!(TSS)).privilege_stack_table[0] = top;
+        MOOOS_KERNEL_STACK_TOP = top.as_u64();
+    }
 }
 
 lazy_static! {
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
-        let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        (gdt, Selectors { code_selector, tss_selector})
+        // `syscall` and `sysret` expect kernel code, kernel data, then user
+        // data and user code, in that order
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+        let tss = gdt.add_entry(Descriptor::tss_segment(unsafe { &*addr_of!(TSS) }));
+        (gdt, Selectors {
+            kernel_code,
+            kernel_data,
+            user_data: SegmentSelector::new(user_data.index(), PrivilegeLevel::Ring3),
+            user_code: SegmentSelector::new(user_code.index(), PrivilegeLevel::Ring3),
+            tss,
+        })
     };
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    /// Requested privilege level 3, ready to load from ring 3.
+    pub user_data: SegmentSelector,
+    /// Requested privilege level 3, ready to load from ring 3.
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
 }
 
 pub fn init() {
     use x86_64::instructions::tables::load_tss;
-    use x86_64::instructions::segmentation::{CS, Segment};
+    use x86_64::instructions::segmentation::{CS, DS, ES, SS, Segment};
+    init_tss();
     GDT.0.load();
     unsafe {
-        CS::set_reg(GDT.1.code_selector);
-        load_tss(GDT.1.tss_selector);
+        CS::set_reg(GDT.1.kernel_code);
+        SS::set_reg(GDT.1.kernel_data);
+        DS::set_reg(GDT.1.kernel_data);
+        ES::set_reg(GDT.1.kernel_data);
+        load_tss(GDT.1.tss);
     }
 }
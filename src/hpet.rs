@@ -0,0 +1,287 @@
+//! High Precision Event Timer.
+//!
+//! `init` finds the HPET through its ACPI table, maps the register block and
+//! starts the main counter. After that the counter can be read as a precise
+//! clock, and the comparators can fire one-shot or periodic interrupts.
+//!
+//! With the PIC there is no interrupt routing to speak of, so the comparators
+//! only reach the CPU in legacy replacement mode, where timer 0 replaces the
+//! PIT on IRQ 0 and timer 1 replaces the RTC on IRQ 8. `use_as_system_timer`
+//! switches to that mode. The other comparators can still be sent to an I/O
+//! APIC input with `set_route`, which is how the watchdog gets its NMI.
+
+use crate::acpi::{self, GenericAddress, SdtHeader};
+use crate::memory;
+use core::ptr;
+use core::time::Duration;
+use spin::Once;
+use x86_64::structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+const REG_CAPABILITIES: usize = 0x000;
+const REG_CONFIG: usize = 0x010;
+const REG_INTERRUPT_STATUS: usize = 0x020;
+const REG_MAIN_COUNTER: usize = 0x0f0;
+
+const fn reg_timer_config(timer: u8) -> usize {
+    0x100 + 0x20 * timer as usize
+}
+
+const fn reg_timer_comparator(timer: u8) -> usize {
+    0x108 + 0x20 * timer as usize
+}
+
+/// capabilities: the main counter is 64 bits wide
+const CAP_COUNTER_64BIT: u64 = 1 << 13;
+/// capabilities: legacy replacement routing is supported
+const CAP_LEGACY_ROUTE: u64 = 1 << 15;
+
+/// general config: run the main counter and let timers interrupt
+const CONFIG_ENABLE: u64 = 1 << 0;
+/// general config: timer 0 and 1 take over IRQ 0 and IRQ 8
+const CONFIG_LEGACY_ROUTE: u64 = 1 << 1;
+
+/// timer config: level rather than edge triggered
+const TIMER_LEVEL_TRIGGERED: u64 = 1 << 1;
+/// timer config: fire when the comparator matches
+const TIMER_INTERRUPT_ENABLE: u64 = 1 << 2;
+/// timer config: reload the comparator after every match
+const TIMER_PERIODIC: u64 = 1 << 3;
+/// timer capability: periodic mode is supported
+const TIMER_PERIODIC_CAPABLE: u64 = 1 << 4;
+/// timer config: the next comparator write sets the counter value rather
+/// than the period
+const TIMER_SET_VALUE: u64 = 1 << 6;
+/// timer config: the I/O APIC input the timer is routed to
+const TIMER_ROUTE_SHIFT: u64 = 9;
+const TIMER_ROUTE_MASK: u64 = 0x1f << TIMER_ROUTE_SHIFT;
+
+/// Size of the register block.
+const REGISTERS_SIZE: u64 = 1024;
+
+#[allow(dead_code)]
+#[repr(C, packed)]
+struct HpetTable {
+    header: SdtHeader,
+    event_timer_block_id: u32,
+    base_address: GenericAddress,
+    hpet_number: u8,
+    minimum_tick: u16,
+    page_protection: u8,
+}
+
+#[derive(Debug)]
+pub enum HpetError {
+    /// The firmware didn't provide an HPET table.
+    NotPresent,
+    /// The registers aren't memory mapped, or the table is otherwise bogus.
+    Unsupported,
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for HpetError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        HpetError::Map(error)
+    }
+}
+
+pub struct Hpet {
+    registers: VirtAddr,
+    /// Length of one main counter tick in femtoseconds.
+    period_fs: u64,
+    timers: u8,
+    counter_64bit: bool,
+    legacy_route: bool,
+}
+
+static HPET: Once<Hpet> = Once::new();
+
+impl Hpet {
+    fn register(&self, offset: usize) -> *mut u64 {
+        (self.registers + offset).as_mut_ptr()
+    }
+
+    fn read(&self, offset: usize) -> u64 {
+        unsafe { ptr::read_volatile(self.register(offset)) }
+    }
+
+    fn write(&self, offset: usize, value: u64) {
+        unsafe { ptr::write_volatile(self.register(offset), value) }
+    }
+
+    /// Current value of the main counter.
+    pub fn counter(&self) -> u64 {
+        self.read(REG_MAIN_COUNTER)
+    }
+
+    /// Main counter ticks per second.
+    pub fn frequency(&self) -> u64 {
+        1_000_000_000_000_000 / self.period_fs
+    }
+
+    pub fn period_fs(&self) -> u64 {
+        self.period_fs
+    }
+
+    pub fn counter_to_nanos(&self, ticks: u64) -> u64 {
+        (u128::from(ticks) * u128::from(self.period_fs) / 1_000_000) as u64
+    }
+
+    pub fn duration_to_counter(&self, duration: Duration) -> u64 {
+        let femtos = duration.as_nanos() * 1_000_000;
+        ((femtos + u128::from(self.period_fs) - 1) / u128::from(self.period_fs)) as u64
+    }
+
+    /// Whether the main counter is 64 rather than 32 bits wide.
+    pub fn is_64bit(&self) -> bool {
+        self.counter_64bit
+    }
+
+    /// Number of comparators.
+    pub fn timers(&self) -> u8 {
+        self.timers
+    }
+
+    pub fn is_periodic_capable(&self, timer: u8) -> bool {
+        timer < self.timers && self.read(reg_timer_config(timer)) & TIMER_PERIODIC_CAPABLE != 0
+    }
+
+    /// Bitmask of the I/O APIC inputs `timer` can be routed to.
+    pub fn route_capabilities(&self, timer: u8) -> u32 {
+        assert!(timer < self.timers, "no HPET timer {}", timer);
+        (self.read(reg_timer_config(timer)) >> 32) as u32
+    }
+
+    /// Sends `timer`'s interrupts to I/O APIC input `pin`, edge triggered.
+    ///
+    /// Has no effect on timers 0 and 1 while in legacy replacement mode.
+    pub fn set_route(&self, timer: u8, pin: u8) {
+        assert!(
+            pin < 32 && self.route_capabilities(timer) & (1 << pin) != 0,
+            "HPET timer {} can't be routed to input {}",
+            timer,
+            pin
+        );
+        let config = self.read(reg_timer_config(timer)) & !(TIMER_ROUTE_MASK | TIMER_LEVEL_TRIGGERED);
+        self.write(reg_timer_config(timer), config | u64::from(pin) << TIMER_ROUTE_SHIFT);
+    }
+
+    /// Fires `timer` once, `delay` from now.
+    pub fn set_one_shot(&self, timer: u8, delay: Duration) {
+        assert!(timer < self.timers, "no HPET timer {}", timer);
+        let config = self.read(reg_timer_config(timer)) & !TIMER_PERIODIC;
+        self.write(reg_timer_config(timer), config | TIMER_INTERRUPT_ENABLE);
+        let deadline = self.counter().wrapping_add(self.duration_to_counter(delay));
+        self.write(reg_timer_comparator(timer), deadline);
+    }
+
+    /// Fires `timer` every `period`, starting one period from now.
+    pub fn set_periodic(&self, timer: u8, period: Duration) {
+        self.set_periodic_ticks(timer, self.duration_to_counter(period));
+    }
+
+    fn set_periodic_ticks(&self, timer: u8, period: u64) {
+        assert!(self.is_periodic_capable(timer), "HPET timer {} can't do periodic mode", timer);
+        let config = self.read(reg_timer_config(timer));
+        self.write(
+            reg_timer_config(timer),
+            config | TIMER_INTERRUPT_ENABLE | TIMER_PERIODIC | TIMER_SET_VALUE,
+        );
+        // with TIMER_SET_VALUE the first write sets the comparator itself,
+        // the second one the period it is advanced by on every match
+        self.write(reg_timer_comparator(timer), self.counter().wrapping_add(period));
+        self.write(reg_timer_comparator(timer), period);
+    }
+
+    pub fn stop(&self, timer: u8) {
+        assert!(timer < self.timers, "no HPET timer {}", timer);
+        let config = self.read(reg_timer_config(timer));
+        self.write(reg_timer_config(timer), config & !(TIMER_INTERRUPT_ENABLE | TIMER_PERIODIC));
+    }
+
+    /// Clears the status bit of a level-triggered timer interrupt.
+    pub fn acknowledge(&self, timer: u8) {
+        self.write(REG_INTERRUPT_STATUS, 1 << timer);
+    }
+}
+
+/// Finds, maps and starts the HPET.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<&'static Hpet, HpetError> {
+    if let Some(hpet) = HPET.r#try() {
+        return Ok(hpet);
+    }
+
+    let table = acpi::find_table(b"HPET").ok_or(HpetError::NotPresent)?;
+    let table = unsafe { &*(table as *const SdtHeader as *const HpetTable) };
+    let base_address = table.base_address;
+    if base_address.address_space != GenericAddress::SYSTEM_MEMORY || base_address.address == 0 {
+        return Err(HpetError::Unsupported);
+    }
+
+    let registers = memory::map_mmio(
+        PhysAddr::new(base_address.address),
+        REGISTERS_SIZE,
+        mapper,
+        frame_allocator,
+    )?;
+    let mut hpet = Hpet {
+        registers,
+        period_fs: 0,
+        timers: 0,
+        counter_64bit: false,
+        legacy_route: false,
+    };
+
+    let capabilities = hpet.read(REG_CAPABILITIES);
+    hpet.period_fs = capabilities >> 32;
+    hpet.timers = ((capabilities >> 8) & 0x1f) as u8 + 1;
+    hpet.counter_64bit = capabilities & CAP_COUNTER_64BIT != 0;
+    hpet.legacy_route = capabilities & CAP_LEGACY_ROUTE != 0;
+    // the spec caps the period at 100ns
+    if hpet.period_fs == 0 || hpet.period_fs > 100_000_000 {
+        return Err(HpetError::Unsupported);
+    }
+
+    // start from a clean slate: everything off, counter at zero
+    hpet.write(REG_CONFIG, hpet.read(REG_CONFIG) & !(CONFIG_ENABLE | CONFIG_LEGACY_ROUTE));
+    for timer in 0..hpet.timers {
+        hpet.stop(timer);
+    }
+    hpet.write(REG_MAIN_COUNTER, 0);
+    hpet.write(REG_CONFIG, hpet.read(REG_CONFIG) | CONFIG_ENABLE);
+
+    let hpet = HPET.call_once(|| hpet);
+    crate::time::tsc::calibrate_with_hpet(hpet);
+    Ok(hpet)
+}
+
+/// The HPET, if `init` found one.
+pub fn get() -> Option<&'static Hpet> {
+    HPET.r#try()
+}
+
+/// Makes HPET timer 0 drive the timer interrupt at `frequency_hz`.
+///
+/// This puts the HPET in legacy replacement mode, which disconnects the PIT
+/// from IRQ 0 and the RTC from IRQ 8, so `rtc::enable_periodic` stops
+/// working.
+pub fn use_as_system_timer(frequency_hz: u32) -> Result<(), HpetError> {
+    use x86_64::instructions::interrupts;
+
+    let hpet = get().ok_or(HpetError::NotPresent)?;
+    if !hpet.legacy_route || !hpet.is_periodic_capable(0) {
+        return Err(HpetError::Unsupported);
+    }
+
+    assert!(frequency_hz > 0, "timer frequency must be non-zero");
+    let period = (hpet.frequency() / u64::from(frequency_hz)).max(1);
+    interrupts::without_interrupts(|| {
+        hpet.set_periodic_ticks(0, period);
+        hpet.write(REG_CONFIG, hpet.read(REG_CONFIG) | CONFIG_LEGACY_ROUTE);
+        crate::time::set_tick_period_fs(period * hpet.period_fs);
+    });
+    Ok(())
+}
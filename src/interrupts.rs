@@ -1,10 +1,14 @@
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
-use crate::println;
+use crate::{println, serial_println};
 use lazy_static::lazy_static;
 use crate::gdt;
+use trap::{trap_entry, TrapFrame};
 use pic8259::ChainedPics;
 use spin;
-use crate::print;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub mod stats;
+pub(crate) mod trap;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -17,7 +21,9 @@ pub static PICS: spin::Mutex<ChainedPics> =
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
-
+    Lpt1 = PIC_1_OFFSET + 7,
+    Rtc = PIC_2_OFFSET,
+    SecondaryAta = PIC_2_OFFSET + 7,
 }
 
 impl InterruptIndex {
@@ -28,6 +34,78 @@ impl InterruptIndex {
     fn as_usize(self) -> usize {
         usize::from(self.as_u8())
     }
+
+    /// The PIC input line this interrupt arrives on.
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+}
+
+/// Lets `irq` through the PIC it is wired to.
+///
+/// IRQs on the secondary PIC also need the cascade line (IRQ 2) on the
+/// primary unmasked.
+pub fn unmask_irq(irq: u8) {
+    use x86_64::instructions::interrupts;
+    use x86_64::instructions::port::Port;
+
+    let mut primary: Port<u8> = Port::new(0x21);
+    let mut secondary: Port<u8> = Port::new(0xa1);
+    interrupts::without_interrupts(|| {
+        // hold the lock so we don't race with the PICs being reinitialized
+        let _pics = PICS.lock();
+        unsafe {
+            if irq < 8 {
+                let mask = primary.read();
+                primary.write(mask & !(1 << irq));
+            } else {
+                let mask = secondary.read();
+                secondary.write(mask & !(1 << (irq - 8)));
+                let mask = primary.read();
+                primary.write(mask & !(1 << 2));
+            }
+        }
+    });
+}
+
+/// Whether the PIC serving `irq` has it marked as in service.
+///
+/// The PIC raises IRQ 7 (or 15 on the secondary) when an interrupt
+/// request goes away before the CPU acknowledges it. Such spurious IRQs
+/// don't show up in the in-service register. The caller must hold the
+/// `PICS` lock.
+fn in_service(irq: u8) -> bool {
+    use x86_64::instructions::port::Port;
+
+    const READ_ISR: u8 = 0x0b;
+    let mut command: Port<u8> = Port::new(if irq < 8 { 0x20 } else { 0xa0 });
+    unsafe {
+        command.write(READ_ISR);
+        command.read() & (1 << (irq % 8)) != 0
+    }
+}
+
+static HANDLER_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether an interrupt or exception handler is running.
+pub fn in_interrupt() -> bool {
+    HANDLER_DEPTH.load(Ordering::Relaxed) > 0
+}
+
+/// Marks a handler as running for `in_interrupt` until dropped.
+pub(crate) struct HandlerContext(());
+
+impl HandlerContext {
+    pub(crate) fn enter() -> Self {
+        HANDLER_DEPTH.fetch_add(1, Ordering::Relaxed);
+        HandlerContext(())
+    }
+}
+
+impl Drop for HandlerContext {
+    fn drop(&mut self) {
+        HANDLER_DEPTH.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 lazy_static! {
@@ -37,15 +115,35 @@ lazy_static! {
         unsafe {
             idt.double_fault.set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
-
+            idt.non_maskable_interrupt.set_handler_fn(nmi_handler)
+                .set_stack_index(gdt::NMI_IST_INDEX);
+        }
+        // may deliver signals, like the exceptions below
+        unsafe {
+            idt[InterruptIndex::Timer.as_usize()]
+                .set_handler_addr(trap::address(timer_entry));
         }
-        idt[InterruptIndex::Timer.as_usize()]
-            .set_handler_fn(timer_interrupt_handler);
 
         idt[InterruptIndex::Keyboard.as_usize()]
             .set_handler_fn(keyboard_interrupt_handler);
 
-        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt[InterruptIndex::Lpt1.as_usize()]
+            .set_handler_fn(lpt1_interrupt_handler);
+
+        idt[InterruptIndex::Rtc.as_usize()]
+            .set_handler_fn(rtc_interrupt_handler);
+
+        idt[InterruptIndex::SecondaryAta.as_usize()]
+            .set_handler_fn(secondary_ata_interrupt_handler);
+
+        // these may deliver signals, so they go through `trap` stubs
+        unsafe {
+            idt.page_fault.set_handler_addr(trap::address(page_fault_entry));
+            idt.general_protection_fault
+                .set_handler_addr(trap::address(general_protection_fault_entry));
+            idt.invalid_opcode.set_handler_addr(trap::address(invalid_opcode_entry));
+            idt.divide_error.set_handler_addr(trap::address(divide_error_entry));
+        }
 
         idt
     };
@@ -59,52 +157,79 @@ pub fn init_idt() {
 extern "x86-interrupt" fn breakpoint_handler(
     stack_frame: InterruptStackFrame)
 {
+    stats::count(3);
+    let _context = HandlerContext::enter();
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame, _error_code: u64) -> !
 {
+    stats::count(8);
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(
-    _stack_frame: InterruptStackFrame)
+/// The frame pointer of the code an interrupt handler interrupted.
+///
+/// The handler's prologue pushes it like any other caller's, so it's what
+/// the handler's own frame pointer points at.
+#[inline(always)]
+fn interrupted_rbp() -> u64 {
+    unsafe { *(crate::backtrace::current_rbp() as *const u64) }
+}
+
+extern "x86-interrupt" fn nmi_handler(
+    stack_frame: InterruptStackFrame)
 {
-    print!(".");
+    use x86_64::instructions::port::Port;
+
+    stats::count(2);
+    let _context = HandlerContext::enter();
+    // system control port B: bit 7 is a memory parity error, bit 6 an I/O
+    // channel check; anything else came from the watchdog
+    let reason = unsafe { Port::<u8>::new(0x61).read() } & 0xc0;
+    if reason == 0 && crate::watchdog::on_nmi(&stack_frame, interrupted_rbp()) {
+        return;
+    }
+    unsafe { crate::serial::force_unlock() };
+    serial_println!("NMI (reason {:#x})\n{:#?}", reason, stack_frame);
+    crate::backtrace::serial_print_from(interrupted_rbp());
+}
+
+trap_entry!(timer_entry, timer_interrupt_handler);
+
+extern "C" fn timer_interrupt_handler(frame: &mut TrapFrame) {
+    stats::count(InterruptIndex::Timer.as_u8());
+    let context = HandlerContext::enter();
+    crate::time::tick();
+    crate::task::timer::wake_expired();
+    crate::thread::wake_sleepers();
+    crate::watchdog::on_tick(&frame.iret, frame.rbp);
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+    // may not return until this thread is scheduled again, so it goes last,
+    // and whatever runs meanwhile isn't in this handler
+    drop(context);
+    crate::thread::preempt();
+    if frame.from_user() {
+        crate::process::on_return_to_user(frame);
+    }
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(
     _stack_frame: InterruptStackFrame)
 {
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-    use spin::Mutex;
     use x86_64::instructions::port::Port;
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = 
-            Mutex::new(Keyboard::new(layouts::Us104Key, ScancodeSet1,
-                    HandleControl::Ignore)
-                );
-    }
-
-    let mut keyboard = KEYBOARD.lock();
+    stats::count(InterruptIndex::Keyboard.as_u8());
+    let _context = HandlerContext::enter();
     let mut port = Port::new(0x60);
-
     let scancode: u8 = unsafe { port.read() };
 
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(key) => print!("{:?}", key),
-            }
-        }
-    }
+    // decoding happens later, in whichever task reads the scancode stream
+    crate::keyboard::add_scancode(scancode);
 
     unsafe {
         PICS.lock()
@@ -112,24 +237,147 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
     }
 }
 
+extern "x86-interrupt" fn rtc_interrupt_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    stats::count(InterruptIndex::Rtc.as_u8());
+    let _context = HandlerContext::enter();
+    crate::rtc::handle_interrupt();
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Rtc.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn lpt1_interrupt_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    let mut pics = PICS.lock();
+    if !in_service(InterruptIndex::Lpt1.irq()) {
+        // spurious: nothing to acknowledge
+        stats::count_spurious(InterruptIndex::Lpt1.irq());
+        return;
+    }
+
+    stats::count(InterruptIndex::Lpt1.as_u8());
+    let _context = HandlerContext::enter();
+    unsafe {
+        pics.notify_end_of_interrupt(InterruptIndex::Lpt1.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn secondary_ata_interrupt_handler(
+    _stack_frame: InterruptStackFrame)
+{
+    use x86_64::instructions::port::Port;
+
+    let mut pics = PICS.lock();
+    if !in_service(InterruptIndex::SecondaryAta.irq()) {
+        // spurious on the secondary, but the primary did see a real
+        // interrupt on the cascade line and still wants its EOI
+        stats::count_spurious(InterruptIndex::SecondaryAta.irq());
+        const END_OF_INTERRUPT: u8 = 0x20;
+        unsafe { Port::<u8>::new(0x20).write(END_OF_INTERRUPT) };
+        return;
+    }
+
+    stats::count(InterruptIndex::SecondaryAta.as_u8());
+    let _context = HandlerContext::enter();
+    unsafe {
+        pics.notify_end_of_interrupt(InterruptIndex::SecondaryAta.as_u8());
+    }
+}
+
+use core::fmt;
 use x86_64::structures::idt::PageFaultErrorCode;
 use crate::hlt_loop;
+use crate::signal::{self, SIGFPE, SIGILL, SIGSEGV};
+
+/// Raises `signal` in the process that caused `exception`, a description
+/// of it, in user mode. The handler's context must be dropped already.
+fn user_fault(exception: fmt::Arguments, signal: u8, frame: &mut TrapFrame) {
+    println!(
+        "{} in user mode at {:?}, process {}",
+        exception,
+        frame.iret.instruction_pointer,
+        crate::process::current_pid().as_u64()
+    );
+    signal::fault(signal, frame);
+}
+
+trap_entry!(page_fault_entry, page_fault_handler, error_code);
 
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
+extern "C" fn page_fault_handler(frame: &mut TrapFrame) {
     use x86_64::registers::control::Cr2;
-    
+
+    stats::count(14);
+    let context = HandlerContext::enter();
+    let error_code = PageFaultErrorCode::from_bits_truncate(frame.error_code);
+    if frame.from_user() {
+        let address = Cr2::read();
+        // copying a page can sleep, which is fine: the kernel holds no locks
+        // while user code runs
+        drop(context);
+        x86_64::instructions::interrupts::enable();
+        let write = PageFaultErrorCode::CAUSED_BY_WRITE | PageFaultErrorCode::PROTECTION_VIOLATION;
+        if error_code.contains(write)
+            && crate::thread::address_space()
+                .map_or(false, |address_space| address_space.copy_on_write(address) == Ok(true))
+        {
+            return;
+        }
+        user_fault(
+            format_args!("PAGE FAULT at {:?} ({:?})", address, error_code),
+            SIGSEGV,
+            frame,
+        );
+        return;
+    }
     println!("EXCEPTION: PAGE FAULT");
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
+    println!("{:#?}", frame.iret);
     hlt_loop();
 }
 
+trap_entry!(general_protection_fault_entry, general_protection_fault_handler, error_code);
+
+extern "C" fn general_protection_fault_handler(frame: &mut TrapFrame) {
+    stats::count(13);
+    let context = HandlerContext::enter();
+    if frame.from_user() {
+        drop(context);
+        user_fault(format_args!("GENERAL PROTECTION FAULT"), SIGSEGV, frame);
+        return;
+    }
+    panic!("EXCEPTION: GENERAL PROTECTION FAULT ({:#x})\n{:#?}", frame.error_code, frame.iret);
+}
 
+trap_entry!(invalid_opcode_entry, invalid_opcode_handler);
 
+extern "C" fn invalid_opcode_handler(frame: &mut TrapFrame) {
+    stats::count(6);
+    let context = HandlerContext::enter();
+    if frame.from_user() {
+        drop(context);
+        user_fault(format_args!("INVALID OPCODE"), SIGILL, frame);
+        return;
+    }
+    panic!("EXCEPTION: INVALID OPCODE\n{:#?}", frame.iret);
+}
+
+trap_entry!(divide_error_entry, divide_error_handler);
+
+extern "C" fn divide_error_handler(frame: &mut TrapFrame) {
+    stats::count(0);
+    let context = HandlerContext::enter();
+    if frame.from_user() {
+        drop(context);
+        user_fault(format_args!("DIVIDE ERROR"), SIGFPE, frame);
+        return;
+    }
+    panic!("EXCEPTION: DIVIDE ERROR\n{:#?}", frame.iret);
+}
 
 #[test_case]
 fn test_breakpoint_exception() {
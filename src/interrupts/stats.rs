@@ -0,0 +1,135 @@
+//! Per-vector interrupt counters.
+//!
+//! Every handler in `interrupts` bumps the counter for its vector with
+//! `count`. Interrupts the PIC raised on IRQ 7 or 15 without anything
+//! actually being in service are counted separately as spurious. `table`
+//! formats it all like Linux's `/proc/interrupts`, and `print_table` puts
+//! that on the screen.
+
+use super::{InterruptIndex, PIC_1_OFFSET, PIC_2_OFFSET};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const ZERO: AtomicU64 = AtomicU64::new(0);
+
+static COUNTS: [AtomicU64; 256] = [ZERO; 256];
+/// Spurious interrupts on IRQ 7 (primary PIC) and IRQ 15 (secondary PIC).
+static SPURIOUS: [AtomicU64; 2] = [ZERO; 2];
+
+/// Records an interrupt on `vector`.
+pub fn count(vector: u8) {
+    COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records a spurious interrupt from the PIC serving `irq`.
+pub(super) fn count_spurious(irq: u8) {
+    SPURIOUS[usize::from(irq >= 8)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of times `vector` fired since boot.
+pub fn get(vector: u8) -> u64 {
+    COUNTS[usize::from(vector)].load(Ordering::Relaxed)
+}
+
+/// Number of spurious interrupts seen on IRQ 7 (`irq` < 8) or IRQ 15.
+pub fn spurious(irq: u8) -> u64 {
+    SPURIOUS[usize::from(irq >= 8)].load(Ordering::Relaxed)
+}
+
+fn name(vector: u8) -> &'static str {
+    const TIMER: u8 = InterruptIndex::Timer as u8;
+    const KEYBOARD: u8 = InterruptIndex::Keyboard as u8;
+    const LPT1: u8 = InterruptIndex::Lpt1 as u8;
+    const RTC: u8 = InterruptIndex::Rtc as u8;
+    const SECONDARY_ATA: u8 = InterruptIndex::SecondaryAta as u8;
+
+    match vector {
+        0 => "divide error",
+        2 => "nmi",
+        3 => "breakpoint",
+        6 => "invalid opcode",
+        8 => "double fault",
+        13 => "general protection fault",
+        14 => "page fault",
+        TIMER => "timer",
+        KEYBOARD => "keyboard",
+        LPT1 => "lpt1",
+        RTC => "rtc",
+        SECONDARY_ATA => "secondary ata",
+        _ => "",
+    }
+}
+
+/// The counters as a table, read when it's displayed rather than when it's
+/// made.
+pub struct Table;
+
+pub fn table() -> Table {
+    Table
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>6} {:>4} {:>12}  {}", "vector", "irq", "count", "name")?;
+        for vector in 0..=255u8 {
+            let count = get(vector);
+            if count == 0 {
+                continue;
+            }
+            if (PIC_1_OFFSET..PIC_2_OFFSET + 8).contains(&vector) {
+                write!(f, "{:>6} {:>4}", vector, vector - PIC_1_OFFSET)?;
+            } else {
+                write!(f, "{:>6} {:>4}", vector, "-")?;
+            }
+            writeln!(f, " {:>12}  {}", count, name(vector))?;
+        }
+        writeln!(f, "{:>6} {:>4} {:>12}  spurious", "SPU", 7, spurious(7))?;
+        write!(f, "{:>6} {:>4} {:>12}  spurious", "SPU", 15, spurious(15))
+    }
+}
+
+/// Prints the counter table to the VGA buffer.
+pub fn print_table() {
+    crate::println!("{}", table());
+}
+
+#[test_case]
+fn test_timer_is_counted() {
+    let timer = InterruptIndex::Timer as u8;
+    let start = get(timer);
+    crate::time::sleep_ticks(2);
+    assert!(get(timer) >= start + 2);
+}
+
+#[test_case]
+fn test_table_lists_timer() {
+    use core::fmt::Write;
+
+    /// Collects the text in place, as there's no heap here.
+    struct Buffer {
+        bytes: [u8; 4096],
+        len: usize,
+    }
+
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.bytes.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    let mut buffer = Buffer { bytes: [0; 4096], len: 0 };
+    write!(buffer, "{}", table()).unwrap();
+    let text = core::str::from_utf8(&buffer.bytes[..buffer.len]).unwrap();
+    let timer = InterruptIndex::Timer as u8;
+    let mut lines = text.lines();
+    assert!(lines.next().unwrap().starts_with("vector"));
+    // the timer has fired by now, so it has a row
+    assert!(lines.any(|line| {
+        let vector = line.split_whitespace().next().and_then(|vector| vector.parse().ok());
+        vector == Some(timer) && line.ends_with("timer")
+    }));
+    assert_eq!(text.lines().filter(|line| line.ends_with("spurious")).count(), 2);
+}
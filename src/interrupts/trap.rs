@@ -0,0 +1,235 @@
+//! Entry stubs for handlers that need every register of the code they
+//! interrupted, not just what the CPU pushes.
+//!
+//! Delivering a signal means rewriting the user's registers on the way
+//! back to ring 3, which `extern "x86-interrupt"` handlers can't do. The
+//! vectors that may deliver one, the timer and the exceptions user code can
+//! raise, enter through a stub made with `trap_entry!` instead. It pushes a
+//! zero for vectors without an error code, saves the general purpose
+//! registers to complete a `TrapFrame`, calls the handler with it and
+//! returns with `iretq` using whatever the handler left in it.
+
+use crate::gdt;
+use crate::signal::{SignalContext, UserRegisters};
+use core::arch::{asm, global_asm};
+use x86_64::instructions::interrupts;
+use x86_64::registers::rflags::{self, RFlags};
+use x86_64::structures::idt::InterruptStackFrameValue;
+use x86_64::VirtAddr;
+
+/// The interrupted registers, lowest address first.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    /// Zero for vectors that don't push one.
+    pub error_code: u64,
+    /// What the CPU pushed.
+    pub iret: InterruptStackFrameValue,
+}
+
+impl TrapFrame {
+    /// A frame that enters ring 3 with the registers in `context`.
+    pub fn user(context: &SignalContext) -> Self {
+        let mut frame = TrapFrame {
+            r15: 0,
+            r14: 0,
+            r13: 0,
+            r12: 0,
+            r11: 0,
+            r10: 0,
+            r9: 0,
+            r8: 0,
+            rbp: 0,
+            rdi: 0,
+            rsi: 0,
+            rdx: 0,
+            rcx: 0,
+            rbx: 0,
+            rax: 0,
+            error_code: 0,
+            iret: InterruptStackFrameValue {
+                instruction_pointer: VirtAddr::zero(),
+                code_segment: 0,
+                cpu_flags: 0,
+                stack_pointer: VirtAddr::zero(),
+                stack_segment: 0,
+            },
+        };
+        frame.set_context(context);
+        frame
+    }
+
+    /// Whether the interrupted code ran in ring 3.
+    pub fn from_user(&self) -> bool {
+        self.iret.code_segment & 3 == 3
+    }
+}
+
+impl UserRegisters for TrapFrame {
+    fn context(&self) -> SignalContext {
+        SignalContext {
+            r8: self.r8,
+            r9: self.r9,
+            r10: self.r10,
+            r11: self.r11,
+            r12: self.r12,
+            r13: self.r13,
+            r14: self.r14,
+            r15: self.r15,
+            rdi: self.rdi,
+            rsi: self.rsi,
+            rbp: self.rbp,
+            rbx: self.rbx,
+            rdx: self.rdx,
+            rax: self.rax,
+            rcx: self.rcx,
+            rsp: self.iret.stack_pointer.as_u64(),
+            rip: self.iret.instruction_pointer.as_u64(),
+            rflags: self.iret.cpu_flags,
+            mask: 0,
+        }
+    }
+
+    fn set_context(&mut self, context: &SignalContext) {
+        let selectors = gdt::selectors();
+        self.r8 = context.r8;
+        self.r9 = context.r9;
+        self.r10 = context.r10;
+        self.r11 = context.r11;
+        self.r12 = context.r12;
+        self.r13 = context.r13;
+        self.r14 = context.r14;
+        self.r15 = context.r15;
+        self.rdi = context.rdi;
+        self.rsi = context.rsi;
+        self.rbp = context.rbp;
+        self.rbx = context.rbx;
+        self.rdx = context.rdx;
+        self.rax = context.rax;
+        self.rcx = context.rcx;
+        self.iret.stack_pointer = VirtAddr::new_truncate(context.rsp);
+        self.iret.instruction_pointer = VirtAddr::new_truncate(context.rip);
+        self.iret.cpu_flags = context.rflags;
+        self.iret.code_segment = u64::from(selectors.user_code.0);
+        self.iret.stack_segment = u64::from(selectors.user_data.0);
+    }
+}
+
+/// Defines `$name`, an entry stub calling `$handler`, an
+/// `extern "C" fn(&mut TrapFrame)`. Add `error_code` for vectors where the
+/// CPU pushes one.
+macro_rules! trap_entry {
+    ($name:ident, $handler:path) => {
+        core::arch::global_asm!(
+            concat!(".global ", stringify!($name)),
+            concat!(stringify!($name), ":"),
+            "push 0",
+            "push rax",
+            "lea rax, [rip + {handler}]",
+            "jmp mooos_trap_common",
+            handler = sym $handler,
+        );
+        extern "C" {
+            fn $name();
+        }
+    };
+    ($name:ident, $handler:path, error_code) => {
+        core::arch::global_asm!(
+            concat!(".global ", stringify!($name)),
+            concat!(stringify!($name), ":"),
+            "push rax",
+            "lea rax, [rip + {handler}]",
+            "jmp mooos_trap_common",
+            handler = sym $handler,
+        );
+        extern "C" {
+            fn $name();
+        }
+    };
+}
+pub(crate) use trap_entry;
+
+// the CPU leaves rsp 16-byte aligned before pushing five words; with the
+// error code and fifteen registers on top, the call needs eight more bytes
+global_asm!(
+    "mooos_trap_common:",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    // user code may have left the direction flag set
+    "cld",
+    "mov rdi, rsp",
+    "sub rsp, 8",
+    "call rax",
+    "add rsp, 8",
+    // `return_to_user` comes in here, with rsp at a frame of its own
+    ".global mooos_trap_return",
+    "mooos_trap_return:",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "add rsp, 8",
+    "iretq",
+);
+
+/// The address to put in the IDT for a stub made with `trap_entry!`.
+pub(crate) fn address(stub: unsafe extern "C" fn()) -> VirtAddr {
+    VirtAddr::new(stub as *const () as u64)
+}
+
+/// Leaves the kernel with the registers in `frame`, the way a trap returns.
+///
+/// # Safety
+///
+/// `frame` must return to ring 3, to code and a stack user code may use.
+pub(crate) unsafe fn return_to_user(frame: &TrapFrame) -> ! {
+    // as on the way in, the registers come back with interrupts masked
+    interrupts::disable();
+    // and without NT, which makes iretq fault in long mode; syscall entry
+    // masks it, but this mustn't depend on how the kernel was entered
+    rflags::write(rflags::read() - RFlags::NESTED_TASK);
+    asm!(
+        "mov rsp, {frame}",
+        "jmp mooos_trap_return",
+        frame = in(reg) frame,
+        options(noreturn),
+    );
+}
@@ -0,0 +1,155 @@
+//! I/O APIC, just enough to route individual inputs.
+//!
+//! Device interrupts still go through the 8259 PICs, and every I/O APIC input
+//! is left masked except the ones set up here. What the I/O APIC adds is
+//! delivery modes the PICs don't have: `route_nmi` turns an input into a
+//! non-maskable interrupt, which the watchdog relies on.
+
+use crate::acpi::{self, SdtHeader};
+use crate::memory;
+use core::arch::x86_64::__cpuid;
+use core::mem;
+use core::ptr;
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts;
+use x86_64::structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Where the I/O APIC sits if the MADT doesn't say otherwise.
+const DEFAULT_ADDRESS: u64 = 0xfec0_0000;
+
+const REG_SELECT: usize = 0x00;
+const REG_WINDOW: usize = 0x10;
+
+const REG_VERSION: u32 = 0x01;
+
+const fn reg_redirection(pin: u8) -> u32 {
+    0x10 + 2 * pin as u32
+}
+
+/// redirection entry: deliver as an NMI, ignoring the vector
+const DELIVERY_NMI: u64 = 0b100 << 8;
+/// redirection entry: don't deliver at all
+const MASKED: u64 = 1 << 16;
+
+/// MADT entry type describing an I/O APIC.
+const MADT_IO_APIC: u8 = 1;
+
+pub struct IoApic {
+    registers: VirtAddr,
+    inputs: u8,
+    /// Serializes the select/window register pair.
+    lock: Mutex<()>,
+}
+
+static IOAPIC: Once<IoApic> = Once::new();
+
+impl IoApic {
+    fn read(&self, reg: u32) -> u32 {
+        let select: *mut u32 = (self.registers + REG_SELECT).as_mut_ptr();
+        let window: *mut u32 = (self.registers + REG_WINDOW).as_mut_ptr();
+        interrupts::without_interrupts(|| {
+            let _lock = self.lock.lock();
+            unsafe {
+                ptr::write_volatile(select, reg);
+                ptr::read_volatile(window)
+            }
+        })
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        let select: *mut u32 = (self.registers + REG_SELECT).as_mut_ptr();
+        let window: *mut u32 = (self.registers + REG_WINDOW).as_mut_ptr();
+        interrupts::without_interrupts(|| {
+            let _lock = self.lock.lock();
+            unsafe {
+                ptr::write_volatile(select, reg);
+                ptr::write_volatile(window, value);
+            }
+        })
+    }
+
+    fn set_redirection(&self, pin: u8, entry: u64) {
+        assert!(pin < self.inputs, "no I/O APIC input {}", pin);
+        // write the half with the mask bit last, so a half-written entry is
+        // never live
+        self.write(reg_redirection(pin), MASKED as u32);
+        self.write(reg_redirection(pin) + 1, (entry >> 32) as u32);
+        self.write(reg_redirection(pin), entry as u32);
+    }
+
+    /// Number of interrupt inputs.
+    pub fn inputs(&self) -> u8 {
+        self.inputs
+    }
+
+    /// Delivers edges on `pin` to this CPU as non-maskable interrupts.
+    pub fn route_nmi(&self, pin: u8) {
+        // physical destination mode, active high, edge triggered
+        self.set_redirection(pin, DELIVERY_NMI | u64::from(local_apic_id()) << 56);
+    }
+
+    pub fn mask(&self, pin: u8) {
+        self.set_redirection(pin, MASKED);
+    }
+}
+
+/// The APIC ID of the CPU we're running on.
+fn local_apic_id() -> u8 {
+    (unsafe { __cpuid(1) }.ebx >> 24) as u8
+}
+
+/// The I/O APIC handling global interrupt 0, from the MADT.
+fn address_from_madt() -> Option<u64> {
+    let madt = acpi::find_table(b"APIC")?;
+    let start = madt as *const SdtHeader as usize;
+    let end = start + madt.length as usize;
+    // the header is followed by the local APIC address and flags
+    let mut entry = start + mem::size_of::<SdtHeader>() + 8;
+
+    while entry + 2 <= end {
+        let (kind, length) = unsafe { (*(entry as *const u8), *((entry + 1) as *const u8)) };
+        if length < 2 {
+            return None;
+        }
+        if kind == MADT_IO_APIC && length >= 12 {
+            let address = unsafe { ptr::read_unaligned((entry + 4) as *const u32) };
+            let gsi_base = unsafe { ptr::read_unaligned((entry + 8) as *const u32) };
+            if gsi_base == 0 {
+                return Some(u64::from(address));
+            }
+        }
+        entry += usize::from(length);
+    }
+    None
+}
+
+/// Maps the I/O APIC and masks all of its inputs.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<&'static IoApic, MapToError<Size4KiB>> {
+    if let Some(ioapic) = IOAPIC.r#try() {
+        return Ok(ioapic);
+    }
+
+    let address = address_from_madt().unwrap_or(DEFAULT_ADDRESS);
+    let registers = memory::map_mmio(PhysAddr::new(address), 0x20, mapper, frame_allocator)?;
+    let mut ioapic = IoApic {
+        registers,
+        inputs: 0,
+        lock: Mutex::new(()),
+    };
+    // the version register holds the index of the last redirection entry
+    ioapic.inputs = ((ioapic.read(REG_VERSION) >> 16) & 0xff) as u8 + 1;
+    for pin in 0..ioapic.inputs {
+        ioapic.mask(pin);
+    }
+
+    Ok(IOAPIC.call_once(|| ioapic))
+}
+
+/// The I/O APIC, if `init` has run.
+pub fn get() -> Option<&'static IoApic> {
+    IOAPIC.r#try()
+}
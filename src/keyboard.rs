@@ -0,0 +1,179 @@
+//! PS/2 keyboard input.
+//!
+//! The interrupt handler only reads the scancode off the controller and hands
+//! it to `add_scancode`, which queues it without locking or allocating.
+//! Decoding happens in a task reading the queue through a `ScancodeStream`,
+//! such as `print_keypresses`, which also makes the typed text available to
+//! threads through `read`, and sends SIGINT to the foreground process when
+//! Ctrl+C is pressed.
+
+use crate::print;
+use crate::process::{self, Pid};
+use crate::signal::SIGINT;
+use crate::sync::{ArrayQueue, WaitQueue};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll};
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
+
+const QUEUE_SIZE: usize = 100;
+
+static SCANCODE_QUEUE: ArrayQueue<u8, QUEUE_SIZE> = ArrayQueue::new();
+static WAKER: AtomicWaker = AtomicWaker::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+/// Whether a `ScancodeStream` has been handed out.
+static STREAM_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Typed text, UTF-8 encoded, waiting for `read`.
+static INPUT: ArrayQueue<u8, 256> = ArrayQueue::new();
+static INPUT_WAITERS: WaitQueue = WaitQueue::new();
+/// The process Ctrl+C interrupts, `Pid::KERNEL` for none.
+static FOREGROUND: AtomicU64 = AtomicU64::new(0);
+
+/// Queues a scancode for the `ScancodeStream`. Called by the keyboard
+/// interrupt handler, and by tests that type.
+///
+/// If the queue is full the scancode is dropped and counted in `dropped`,
+/// which beats stalling in the interrupt handler.
+pub fn add_scancode(scancode: u8) {
+    if SCANCODE_QUEUE.push(scancode).is_err() {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    } else {
+        WAKER.wake();
+    }
+}
+
+/// Number of scancodes lost to a full queue since boot.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// The raw scancodes coming from the keyboard.
+///
+/// There is a single queue behind it, so only one stream can exist.
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    pub fn new() -> Self {
+        if STREAM_TAKEN.swap(true, Ordering::AcqRel) {
+            panic!("ScancodeStream::new should only be called once");
+        }
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        // fast path
+        if let Some(scancode) = SCANCODE_QUEUE.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        // register before checking again, or a scancode pushed in between
+        // would not wake us
+        WAKER.register(cx.waker());
+        match SCANCODE_QUEUE.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Blocks until text has been typed, then moves as much of it into `buf` as
+/// fits and returns how many bytes that was. A multi-byte character may be
+/// split across calls. Returns `None` if the calling process is interrupted
+/// before anything was typed (see `WaitQueue::wait_until_interruptible`).
+///
+/// Only sees keys decoded by `print_keypresses`.
+pub fn read(buf: &mut [u8]) -> Option<usize> {
+    if buf.is_empty() {
+        return Some(0);
+    }
+    INPUT_WAITERS.wait_until_interruptible(|| !INPUT.is_empty()).ok()?;
+    let mut read = 0;
+    while read < buf.len() {
+        match INPUT.pop() {
+            Some(byte) => buf[read] = byte,
+            None => break,
+        }
+        read += 1;
+    }
+    Some(read)
+}
+
+/// Makes `pid` the process Ctrl+C sends SIGINT to; `Pid::KERNEL` for none.
+pub fn set_foreground(pid: Pid) {
+    FOREGROUND.store(pid.as_u64(), Ordering::Relaxed);
+}
+
+/// The process Ctrl+C sends SIGINT to, `Pid::KERNEL` if none.
+pub fn foreground() -> Pid {
+    Pid::from_u64(FOREGROUND.load(Ordering::Relaxed))
+}
+
+/// Sends SIGINT to the foreground process, which also wakes its reads.
+fn interrupt() {
+    let pid = foreground();
+    if pid != Pid::KERNEL {
+        let _ = process::kill(pid, SIGINT);
+    }
+}
+
+/// Queues a typed character for `read`. If nobody reads, typing more than
+/// the queue holds loses the excess.
+fn add_input(character: char) {
+    let mut encoded = [0; 4];
+    for &byte in character.encode_utf8(&mut encoded).as_bytes() {
+        let _ = INPUT.push(byte);
+    }
+    INPUT_WAITERS.notify_all();
+}
+
+/// Decodes keypresses and echoes them to the screen, forever.
+pub async fn print_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::MapLettersToUnicode);
+
+    while let Some(scancode) = scancodes.next().await {
+        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            if let Some(key) = keyboard.process_keyevent(key_event) {
+                match key {
+                    // Ctrl+C
+                    DecodedKey::Unicode('\u{3}') => {
+                        print!("^C");
+                        interrupt();
+                    }
+                    DecodedKey::Unicode(character) => {
+                        print!("{}", character);
+                        add_input(character);
+                    }
+                    DecodedKey::RawKey(key) => print!("{:?}", key),
+                }
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_stream_yields_queued_scancodes() {
+    use futures_util::task::noop_waker_ref;
+
+    let mut stream = ScancodeStream::new();
+    let mut cx = Context::from_waker(noop_waker_ref());
+    while let Poll::Ready(_) = Pin::new(&mut stream).poll_next(&mut cx) {}
+
+    add_scancode(0x1e);
+    add_scancode(0x9e);
+    assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Ready(Some(0x1e)));
+    assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Ready(Some(0x9e)));
+    assert_eq!(Pin::new(&mut stream).poll_next(&mut cx), Poll::Pending);
+}
@@ -13,6 +13,31 @@ pub mod interrupts;
 pub mod gdt;
 pub mod memory;
 pub mod allocator;
+pub mod backtrace;
+pub mod symbols;
+pub mod time;
+pub mod rtc;
+pub mod acpi;
+pub mod hpet;
+pub mod ioapic;
+pub mod watchdog;
+pub mod softirq;
+pub mod sync;
+pub mod deferred;
+pub mod keyboard;
+pub mod task;
+pub mod thread;
+pub mod syscall;
+pub mod usermode;
+pub mod elf;
+pub mod loader;
+pub mod file;
+pub mod pipe;
+pub mod channel;
+pub mod process;
+pub mod signal;
+pub mod fs;
+pub mod programs;
 
 extern crate alloc;
 
@@ -20,8 +45,12 @@ use core::panic::PanicInfo;
 
 pub fn init() {
     gdt::init();
+    syscall::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    time::init(time::DEFAULT_FREQUENCY_HZ);
+    time::tsc::init();
+    rtc::init();
     x86_64::instructions::interrupts::enable();
 }
 
@@ -31,6 +60,31 @@ pub fn hlt_loop() -> ! {
     }
 }
 
+/// Halts until an interrupt comes in, then runs whatever softirqs it raised.
+///
+/// Unlike `hlt_loop` this keeps the kernel's deferred work going and gives
+/// the CPU to any kernel thread that wants it, which also keeps the
+/// watchdog fed, so it's what a kernel that is done setting up should end
+/// in.
+pub fn idle_loop() -> ! {
+    use x86_64::instructions::interrupts;
+
+    // whatever else is runnable comes first
+    thread::set_priority(thread::Priority::MIN);
+    loop {
+        softirq::run_pending();
+        thread::yield_now();
+        // check and halt with interrupts off, or a softirq raised in between
+        // would wait for the next interrupt
+        interrupts::disable();
+        if softirq::is_pending() || thread::has_ready() {
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -70,6 +124,7 @@ pub fn test_runner(tests: &[&dyn Testable]) {
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
+    backtrace::serial_print();
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
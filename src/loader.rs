@@ -0,0 +1,199 @@
+//! Loading ELF executables into fresh address spaces.
+//!
+//! `load` maps every `PT_LOAD` segment with the permissions it asks for,
+//! copies in its file contents and leaves the rest of it (the BSS) zeroed,
+//! then builds the initial stack the System V ABI describes:
+//!
+//! ```text
+//! STACK_TOP  argument and environment strings
+//!            padding
+//!            auxiliary vector, ending with AT_NULL
+//!            envp pointers, ending with null
+//!            argv pointers, ending with null
+//! rsp ->     argc
+//! ```
+
+use crate::elf::{Elf, ElfError, ProgramHeader, PF_W, PF_X, PT_LOAD, PT_PHDR};
+use crate::memory::{address_space, AddressSpace};
+use crate::thread::{self, ThreadId};
+use crate::usermode::{self, USER_END};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/// The initial stack grows down from here.
+pub const STACK_TOP: u64 = USER_END;
+pub const STACK_SIZE: u64 = 64 * 1024;
+/// How much of the stack the arguments, environment and auxiliary vector
+/// may take up.
+const MAX_ARGS_SIZE: u64 = STACK_SIZE / 4;
+
+// auxiliary vector entry types
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    Elf(ElfError),
+    /// A segment or the entry point lies outside the user range.
+    NotInUserRange,
+    /// A segment runs into the stack.
+    Overlap,
+    OutOfMemory,
+    ArgumentsTooLong,
+    TooManyThreads,
+}
+
+impl From<ElfError> for LoadError {
+    fn from(err: ElfError) -> Self {
+        LoadError::Elf(err)
+    }
+}
+
+impl From<address_space::Error> for LoadError {
+    fn from(err: address_space::Error) -> Self {
+        match err {
+            address_space::Error::OutOfMemory => LoadError::OutOfMemory,
+            address_space::Error::AlreadyMapped | address_space::Error::NotMapped => {
+                LoadError::Overlap
+            }
+        }
+    }
+}
+
+/// An executable loaded into its own address space, ready to run.
+pub struct Program {
+    pub address_space: Arc<AddressSpace>,
+    pub entry: VirtAddr,
+    pub stack_pointer: VirtAddr,
+}
+
+/// Loads the executable in `image` into a new address space, with `argv`
+/// and `envp` on its stack.
+pub fn load(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<Program, LoadError> {
+    let elf = Elf::parse(image)?;
+    let address_space = AddressSpace::new()?;
+
+    let no_execute = Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE);
+    let mut loaded_headers = None;
+    for header in elf.program_headers().filter(|header| header.kind == PT_LOAD) {
+        let start = VirtAddr::try_new(header.vaddr).map_err(|_| LoadError::NotInUserRange)?;
+        if !usermode::is_user_range(start, header.memsz) {
+            return Err(LoadError::NotInUserRange);
+        }
+        address_space.map_user_merged(start, header.memsz, segment_flags(&header, no_execute))?;
+        address_space.write(start, elf.segment_data(&header))?;
+
+        // the program headers are in memory if a segment loads them
+        let phoff = elf.program_header_offset();
+        if phoff >= header.offset && phoff < header.offset + header.filesz {
+            loaded_headers = Some(header.vaddr + (phoff - header.offset));
+        }
+    }
+    if let Some(phdr) = elf.program_headers().find(|header| header.kind == PT_PHDR) {
+        loaded_headers = Some(phdr.vaddr);
+    }
+
+    let entry = VirtAddr::try_new(elf.entry()).map_err(|_| LoadError::NotInUserRange)?;
+    if !usermode::is_user_range(entry, 1) {
+        return Err(LoadError::NotInUserRange);
+    }
+
+    let stack_bottom = VirtAddr::new(STACK_TOP - STACK_SIZE);
+    address_space.map_user(stack_bottom, STACK_SIZE, PageTableFlags::WRITABLE | nx(no_execute))?;
+
+    let mut auxv = Vec::new();
+    if let Some(phdr) = loaded_headers {
+        auxv.push((AT_PHDR, phdr));
+    }
+    auxv.push((AT_PHENT, elf.program_header_size() as u64));
+    auxv.push((AT_PHNUM, elf.program_header_count() as u64));
+    auxv.push((AT_PAGESZ, 4096));
+    auxv.push((AT_ENTRY, entry.as_u64()));
+    let stack_pointer = build_stack(&address_space, argv, envp, &auxv)?;
+
+    Ok(Program {
+        address_space: Arc::new(address_space),
+        entry,
+        stack_pointer,
+    })
+}
+
+/// Loads the executable in `image` and starts running it on a new thread.
+pub fn spawn(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<ThreadId, LoadError> {
+    let program = load(image, argv, envp)?;
+    let (entry, stack_pointer) = (program.entry, program.stack_pointer);
+    thread::spawn_in(program.address_space, move || unsafe { usermode::enter(entry, stack_pointer) })
+        .map_err(|_| LoadError::TooManyThreads)
+}
+
+fn nx(no_execute: bool) -> PageTableFlags {
+    if no_execute {
+        PageTableFlags::NO_EXECUTE
+    } else {
+        PageTableFlags::empty()
+    }
+}
+
+fn segment_flags(header: &ProgramHeader, no_execute: bool) -> PageTableFlags {
+    // every segment is readable; there is no way to map it otherwise
+    let mut flags = PageTableFlags::empty();
+    if header.flags & PF_W != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if header.flags & PF_X == 0 {
+        flags |= nx(no_execute);
+    }
+    flags
+}
+
+/// Writes argc, argv, envp and the auxiliary vector below `STACK_TOP` and
+/// returns the resulting stack pointer.
+fn build_stack(
+    address_space: &AddressSpace,
+    argv: &[&str],
+    envp: &[&str],
+    auxv: &[(u64, u64)],
+) -> Result<VirtAddr, LoadError> {
+    // strings go at the very top, each null terminated
+    let strings_len: u64 = argv.iter().chain(envp).map(|s| s.len() as u64 + 1).sum();
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2 * (auxv.len() + 1);
+    let size = strings_len + 8 * words as u64 + 15;
+    if size > MAX_ARGS_SIZE {
+        return Err(LoadError::ArgumentsTooLong);
+    }
+    let strings_start = STACK_TOP - strings_len;
+    // argc must end up 16 byte aligned
+    let stack_pointer = (strings_start - 8 * words as u64) & !0xf;
+
+    let mut image = Vec::with_capacity((STACK_TOP - stack_pointer) as usize);
+    let push_word = |image: &mut Vec<u8>, word: u64| image.extend_from_slice(&word.to_le_bytes());
+    let mut string_at = strings_start;
+    let mut strings = Vec::with_capacity(strings_len as usize);
+
+    push_word(&mut image, argv.len() as u64);
+    for list in [argv, envp].iter() {
+        for s in list.iter() {
+            push_word(&mut image, string_at);
+            strings.extend_from_slice(s.as_bytes());
+            strings.push(0);
+            string_at += s.len() as u64 + 1;
+        }
+        push_word(&mut image, 0);
+    }
+    for &(kind, value) in auxv.iter().chain(&[(AT_NULL, 0)]) {
+        push_word(&mut image, kind);
+        push_word(&mut image, value);
+    }
+    image.resize((strings_start - stack_pointer) as usize, 0);
+    image.extend_from_slice(&strings);
+
+    address_space.write(VirtAddr::new(stack_pointer), &image)?;
+    Ok(VirtAddr::new(stack_pointer))
+}
@@ -7,7 +7,8 @@
 extern crate alloc;
 
 use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
-use mooos::println;
+use mooos::task::{executor::Executor, Task};
+use mooos::{println, serial_println};
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 
@@ -26,6 +27,16 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    mooos::thread::init();
+
+    match mooos::hpet::init(&mut mapper, &mut frame_allocator) {
+        Ok(hpet) => println!("HPET at {} Hz, {} timers", hpet.frequency(), hpet.timers()),
+        Err(err) => println!("no HPET: {:?}", err),
+    }
+    let watchdog = mooos::watchdog::init(&mut mapper, &mut frame_allocator);
+    println!("watchdog checks from {:?}", watchdog);
+    memory::init_global(mapper, frame_allocator);
+    mooos::programs::install();
 
     // allocate a number on the heap
     let heap_value = Box::new(41);
@@ -55,7 +66,21 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     test_main();
 
     println!("It did not crash!");
-    mooos::hlt_loop();
+    mooos::watchdog::start(core::time::Duration::from_secs(5));
+
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(example_task()));
+    executor.spawn(Task::new(mooos::keyboard::print_keypresses()));
+    executor.run();
+}
+
+async fn async_number() -> u32 {
+    42
+}
+
+async fn example_task() {
+    let number = async_number().await;
+    println!("async number: {}", number);
 }
 
 /// This function is called on panic.
@@ -63,6 +88,8 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{}", info);
+    serial_println!("{}", info);
+    mooos::backtrace::print();
     mooos::hlt_loop();
 }
 
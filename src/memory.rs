@@ -1,12 +1,20 @@
 use x86_64::{
-    structures::paging::{Page, PageTable, PhysFrame, Mapper, Size4KiB, FrameAllocator},
+    structures::paging::{Page, PageTable, PhysFrame, Mapper, Size4KiB, FrameAllocator, FrameDeallocator},
     VirtAddr,
 };
 use x86_64::PhysAddr;
 use x86_64::structures::paging::OffsetPageTable;
+use x86_64::structures::paging::mapper::MapToError;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::Mutex;
+use alloc::collections::BTreeMap;
 
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 
+pub mod address_space;
+
+pub use address_space::AddressSpace;
+
 
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
 pub struct BootInfoFrameAllocator {
@@ -55,10 +63,55 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+    let (level_4_table, _) = x86_64::registers::control::Cr3::read();
+    KERNEL_LEVEL_4_TABLE.store(level_4_table.start_address().as_u64(), Ordering::Relaxed);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+/// Where the bootloader mapped all of physical memory, set by `init`.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Translates a physical address through the bootloader's complete mapping
+/// of physical memory. Only valid after `init`.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    let offset = PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed);
+    debug_assert!(offset != 0, "memory::init hasn't run");
+    VirtAddr::new(offset + addr.as_u64())
+}
+
+/// Start of the virtual window device registers get mapped into.
+pub const MMIO_START: u64 = 0x_5555_5555_0000;
+
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
+
+/// Maps `size` bytes of device registers at `phys` as uncached memory.
+///
+/// Returns the virtual address corresponding to `phys`. The mapping is never
+/// torn down.
+pub fn map_mmio(
+    phys: PhysAddr,
+    size: u64,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    use x86_64::structures::paging::PageTableFlags as Flags;
+
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+    let last_frame = PhysFrame::<Size4KiB>::containing_address(phys + (size.max(1) - 1));
+    let frames = PhysFrame::range_inclusive(first_frame, last_frame);
+    let pages = last_frame - first_frame + 1;
+
+    let start = VirtAddr::new(NEXT_MMIO.fetch_add(pages * 4096, Ordering::Relaxed));
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_CACHE | Flags::WRITE_THROUGH;
+    for (i, frame) in frames.enumerate() {
+        let page = Page::containing_address(start + i as u64 * 4096);
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    Ok(start + (phys.as_u64() - first_frame.start_address().as_u64()))
+}
 
 pub fn create_example_mapping(
     page: Page,
@@ -78,6 +131,173 @@ pub fn create_example_mapping(
     map_to_result.expect("map_to failed").flush();
 }
 
+/// Hands out frames given back through `deallocate_frame` before fresh
+/// ones from the boot allocator.
+///
+/// The free frames form a list threaded through their first eight bytes,
+/// so freeing never allocates. Frames can also be shared, by address spaces
+/// after a fork; see `share`.
+pub struct RecyclingFrameAllocator {
+    fresh: BootInfoFrameAllocator,
+    free: Option<PhysFrame>,
+    free_frames: usize,
+    /// Frames with more than one owner, and how many more.
+    shared: BTreeMap<PhysFrame, usize>,
+}
+
+impl RecyclingFrameAllocator {
+    pub fn new(fresh: BootInfoFrameAllocator) -> Self {
+        RecyclingFrameAllocator {
+            fresh,
+            free: None,
+            free_frames: 0,
+            shared: BTreeMap::new(),
+        }
+    }
+
+    /// Number of frames waiting to be reused.
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// Gives `frame` another owner. It is only freed once each of them has
+    /// deallocated it.
+    pub fn share(&mut self, frame: PhysFrame) {
+        *self.shared.entry(frame).or_insert(0) += 1;
+    }
+
+    /// Whether `frame` has more than one owner.
+    pub fn is_shared(&self, frame: PhysFrame) -> bool {
+        self.shared.contains_key(&frame)
+    }
+}
+
+/// Marks the end of the free list; no usable frame lives at this address.
+const NO_FRAME: u64 = u64::MAX;
+
+unsafe impl FrameAllocator<Size4KiB> for RecyclingFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        match self.free.take() {
+            Some(frame) => {
+                let next = unsafe { *phys_to_virt(frame.start_address()).as_ptr::<u64>() };
+                if next != NO_FRAME {
+                    self.free = Some(PhysFrame::containing_address(PhysAddr::new(next)));
+                }
+                self.free_frames -= 1;
+                Some(frame)
+            }
+            None => self.fresh.allocate_frame(),
+        }
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for RecyclingFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        if let Some(owners) = self.shared.get_mut(&frame) {
+            *owners -= 1;
+            if *owners == 0 {
+                self.shared.remove(&frame);
+            }
+            return;
+        }
+        let next = self.free.map_or(NO_FRAME, |next| next.start_address().as_u64());
+        *phys_to_virt(frame.start_address()).as_mut_ptr::<u64>() = next;
+        self.free = Some(frame);
+        self.free_frames += 1;
+    }
+}
+
+/// The kernel's page table once booting is done, for code that maps memory
+/// later on.
+static KERNEL_MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+/// Where the frame allocator goes once booting is done. Always locked after
+/// any page table.
+static FRAMES: Mutex<Option<RecyclingFrameAllocator>> = Mutex::new(None);
+
+/// Hands the page table and frame allocator over to `with_global` and
+/// `with_frames`.
+pub fn init_global(mapper: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator) {
+    *KERNEL_MAPPER.lock() = Some(mapper);
+    *FRAMES.lock() = Some(RecyclingFrameAllocator::new(frame_allocator));
+}
+
+/// Runs `f` with the kernel's page table and the frame allocator, or returns
+/// `None` if `init_global` hasn't run yet. May block.
+pub fn with_global<R>(
+    f: impl FnOnce(&mut OffsetPageTable<'static>, &mut RecyclingFrameAllocator) -> R,
+) -> Option<R> {
+    let mut mapper = KERNEL_MAPPER.lock();
+    let mut frames = FRAMES.lock();
+    Some(f(mapper.as_mut()?, frames.as_mut()?))
+}
+
+/// Runs `f` with the frame allocator, or returns `None` if `init_global`
+/// hasn't run yet. May block.
+pub fn with_frames<R>(f: impl FnOnce(&mut RecyclingFrameAllocator) -> R) -> Option<R> {
+    Some(f(FRAMES.lock().as_mut()?))
+}
+
+/// The kernel's level 4 table, as found by `init`. Every address space
+/// shares its kernel part.
+pub fn kernel_level_4_table() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_LEVEL_4_TABLE.load(Ordering::Relaxed)))
+}
+
+static KERNEL_LEVEL_4_TABLE: AtomicU64 = AtomicU64::new(0);
+
+/// Whether all of `[start, start + len)` is mapped in the active page table
+/// so that ring 3 may read it, and write it too if `write` is set.
+///
+/// Walks the tables by hand rather than through a `Mapper`, so it can run
+/// anywhere, e.g. in a system call checking its arguments.
+pub fn user_accessible(start: VirtAddr, len: u64, write: bool) -> bool {
+    use x86_64::registers::control::Cr3;
+    use x86_64::structures::paging::PageTableFlags as Flags;
+
+    if len == 0 {
+        return true;
+    }
+    let end = match start.as_u64().checked_add(len - 1) {
+        Some(end) => end,
+        None => return false,
+    };
+    let mut required = Flags::PRESENT | Flags::USER_ACCESSIBLE;
+    if write {
+        required |= Flags::WRITABLE;
+    }
+
+    let (level_4_table, _) = Cr3::read();
+    let mut addr = start.align_down(4096u64).as_u64();
+    while addr <= end {
+        let virt = VirtAddr::new(addr);
+        let indexes = [virt.p4_index(), virt.p3_index(), virt.p2_index(), virt.p1_index()];
+        let mut frame = level_4_table.start_address();
+        // the access is allowed only if every level allows it
+        let mut page_size = 4096;
+        for (level, &index) in indexes.iter().enumerate() {
+            let table: &PageTable = unsafe { &*phys_to_virt(frame).as_ptr() };
+            let entry = &table[index];
+            if !entry.flags().contains(required) {
+                return false;
+            }
+            frame = entry.addr();
+            if entry.flags().contains(Flags::HUGE_PAGE) {
+                page_size = match level {
+                    1 => 1 << 30,
+                    2 => 1 << 21,
+                    _ => return false,
+                };
+                break;
+            }
+        }
+        addr = match (addr & !(page_size - 1)).checked_add(page_size) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    true
+}
+
 pub struct EmptyFrameAllocator;
 
 unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
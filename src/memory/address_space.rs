@@ -0,0 +1,343 @@
+//! Address spaces for user programs.
+//!
+//! Each address space has a level 4 table of its own. The entries covering
+//! the user range belong to it alone; all the others are copied from the
+//! kernel's table when it is created, so the kernel looks the same from
+//! every address space. That holds only because the kernel doesn't add level
+//! 4 entries after booting: the heap, the MMIO window and the physical memory
+//! mapping all have theirs by then.
+//!
+//! Page tables and frames come from the allocator behind `with_frames`, and
+//! everything in the user range goes back to it when the address space is
+//! dropped.
+//!
+//! `fork` copies an address space lazily: both copies map the same frames,
+//! read-only, with writable pages marked `COPY_ON_WRITE`. Whichever writes
+//! such a page first faults, and `copy_on_write` gives it a copy of its
+//! own. The frame allocator counts the owners of shared frames, so a frame
+//! is only freed once every address space using it is gone.
+
+use super::{kernel_level_4_table, phys_to_virt, with_frames, RecyclingFrameAllocator};
+use crate::sync::Mutex;
+use crate::usermode::{self, USER_END, USER_START};
+use core::ops::Range;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::instructions::tlb;
+use x86_64::structures::paging::mapper::{MapToError, MappedFrame, TranslateResult};
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+/// Level 4 entries that cover the user range.
+const USER_ENTRIES: Range<usize> = (USER_START >> 39) as usize..(USER_END >> 39) as usize;
+
+/// Marks a page that `fork` made read-only, and that becomes writable again
+/// once copied. The CPU ignores this bit.
+pub const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No frame left for a page or page table.
+    OutOfMemory,
+    /// Part of the range was mapped already.
+    AlreadyMapped,
+    /// Part of the range isn't mapped.
+    NotMapped,
+}
+
+impl From<MapToError<Size4KiB>> for Error {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        match err {
+            MapToError::FrameAllocationFailed => Error::OutOfMemory,
+            MapToError::ParentEntryHugePage | MapToError::PageAlreadyMapped(_) => {
+                Error::AlreadyMapped
+            }
+        }
+    }
+}
+
+pub struct AddressSpace {
+    level_4_table: PhysFrame,
+    /// Serializes changes to the tables.
+    lock: Mutex<()>,
+}
+
+fn table_at(frame: PhysFrame) -> &'static mut PageTable {
+    unsafe { &mut *phys_to_virt(frame.start_address()).as_mut_ptr() }
+}
+
+impl AddressSpace {
+    /// An address space with the kernel mapped and nothing in the user range.
+    pub fn new() -> Result<AddressSpace, Error> {
+        let frame = with_frames(|frames| frames.allocate_frame())
+            .flatten()
+            .ok_or(Error::OutOfMemory)?;
+        let table = table_at(frame);
+        table.zero();
+        let kernel = table_at(kernel_level_4_table());
+        for (i, entry) in kernel.iter().enumerate() {
+            if !USER_ENTRIES.contains(&i) {
+                table[i] = entry.clone();
+            }
+        }
+        Ok(AddressSpace {
+            level_4_table: frame,
+            lock: Mutex::new(()),
+        })
+    }
+
+    pub fn level_4_table(&self) -> PhysFrame {
+        self.level_4_table
+    }
+
+    /// Makes this the address space the CPU translates through.
+    pub fn activate(&self) {
+        if Cr3::read().0 != self.level_4_table {
+            unsafe { Cr3::write(self.level_4_table, Cr3Flags::empty()) };
+        }
+    }
+
+    fn with_mapper<R>(&self, f: impl FnOnce(&mut OffsetPageTable) -> R) -> R {
+        let _lock = self.lock.lock();
+        let offset = phys_to_virt(x86_64::PhysAddr::new(0));
+        let mut mapper = unsafe { OffsetPageTable::new(table_at(self.level_4_table), offset) };
+        f(&mut mapper)
+    }
+
+    /// Like `usermode::map_user`, but in this address space.
+    pub fn map_user(&self, start: VirtAddr, len: u64, flags: PageTableFlags) -> Result<(), Error> {
+        self.with_mapper(|mapper| {
+            with_frames(|frames| usermode::map_user(start, len, flags, mapper, frames))
+                .ok_or(Error::OutOfMemory)?
+                .map_err(Error::from)
+        })
+    }
+
+    /// Like `map_user`, except that pages already mapped are kept, with
+    /// their permissions widened to include `flags`. For loading segments
+    /// that share a page.
+    pub fn map_user_merged(
+        &self,
+        start: VirtAddr,
+        len: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), Error> {
+        if len == 0 {
+            return Ok(());
+        }
+        let first = Page::<Size4KiB>::containing_address(start);
+        let last = Page::<Size4KiB>::containing_address(start + (len - 1));
+        for page in Page::range_inclusive(first, last) {
+            let existing = self.with_mapper(|mapper| match mapper.translate(page.start_address()) {
+                TranslateResult::Mapped { flags, .. } => Some(flags),
+                _ => None,
+            });
+            match existing {
+                Some(existing) => {
+                    let mut merged = existing | (flags & PageTableFlags::WRITABLE);
+                    if !flags.contains(PageTableFlags::NO_EXECUTE) {
+                        merged.remove(PageTableFlags::NO_EXECUTE);
+                    }
+                    self.with_mapper(|mapper| unsafe {
+                        mapper
+                            .update_flags(page, merged)
+                            .map(|flush| flush.flush())
+                            .map_err(|_| Error::NotMapped)
+                    })?;
+                }
+                None => self.map_user(page.start_address(), 4096, flags)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// A copy of this address space, sharing its frames copy-on-write.
+    pub fn fork(&self) -> Result<AddressSpace, Error> {
+        let child = AddressSpace::new()?;
+        let _lock = self.lock.lock();
+        let mut result = Ok(());
+        for_each_user_page(self.level_4_table, |page, entry| {
+            if result.is_err() {
+                return;
+            }
+            let frame = match entry.frame() {
+                Ok(frame) => frame,
+                Err(_) => return,
+            };
+            let mut flags = entry.flags();
+            if flags.contains(PageTableFlags::WRITABLE) {
+                flags = (flags - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+                entry.set_flags(flags);
+            }
+            result = child.with_mapper(|mapper| {
+                with_frames(|frames| {
+                    frames.share(frame);
+                    match unsafe { mapper.map_to(page, frame, flags, frames) } {
+                        Ok(flush) => {
+                            flush.ignore();
+                            Ok(())
+                        }
+                        Err(err) => {
+                            unsafe { frames.deallocate_frame(frame) };
+                            Err(Error::from(err))
+                        }
+                    }
+                })
+                .unwrap_or(Err(Error::OutOfMemory))
+            });
+        });
+        // the pages just made read-only may still be writable in the TLB
+        if Cr3::read().0 == self.level_4_table {
+            tlb::flush_all();
+        }
+        result.map(|()| child)
+    }
+
+    /// Gives this address space a writable copy of its own of the page at
+    /// `addr`, if it is shared copy-on-write. Returns whether it was.
+    pub fn copy_on_write(&self, addr: VirtAddr) -> Result<bool, Error> {
+        let page = Page::<Size4KiB>::containing_address(addr);
+        self.with_mapper(|mapper| {
+            let (frame, flags) = match mapper.translate(page.start_address()) {
+                TranslateResult::Mapped {
+                    frame: MappedFrame::Size4KiB(frame),
+                    flags,
+                    ..
+                } => (frame, flags),
+                _ => return Ok(false),
+            };
+            if !flags.contains(COPY_ON_WRITE) {
+                return Ok(false);
+            }
+            let flags = (flags - COPY_ON_WRITE) | PageTableFlags::WRITABLE;
+            with_frames(|frames| {
+                // the last owner can just take the frame back
+                let copy = if frames.is_shared(frame) {
+                    let copy = frames.allocate_frame().ok_or(Error::OutOfMemory)?;
+                    unsafe {
+                        phys_to_virt(copy.start_address()).as_mut_ptr::<u8>().copy_from_nonoverlapping(
+                            phys_to_virt(frame.start_address()).as_ptr::<u8>(),
+                            4096,
+                        );
+                        frames.deallocate_frame(frame);
+                    }
+                    copy
+                } else {
+                    frame
+                };
+                let (_, flush) = mapper.unmap(page).map_err(|_| Error::NotMapped)?;
+                flush.flush();
+                unsafe { mapper.map_to(page, copy, flags, frames) }?.flush();
+                Ok(true)
+            })
+            .unwrap_or(Err(Error::OutOfMemory))
+        })
+    }
+
+    /// Copies `data` to `addr`, whatever the page permissions are, without
+    /// the address space being active. Pages shared copy-on-write are
+    /// copied first.
+    pub fn write(&self, addr: VirtAddr, data: &[u8]) -> Result<(), Error> {
+        if !data.is_empty() {
+            let first = Page::<Size4KiB>::containing_address(addr);
+            let last = Page::<Size4KiB>::containing_address(addr + (data.len() - 1));
+            for page in Page::range_inclusive(first, last) {
+                self.copy_on_write(page.start_address())?;
+            }
+        }
+        self.copy(addr, data.len(), |ptr, offset, len| unsafe {
+            ptr.copy_from_nonoverlapping(data[offset..].as_ptr(), len)
+        })
+    }
+
+    /// Copies from `addr` to `buf`, without the address space being active.
+    pub fn read(&self, addr: VirtAddr, buf: &mut [u8]) -> Result<(), Error> {
+        self.copy(addr, buf.len(), |ptr, offset, len| unsafe {
+            ptr.copy_to_nonoverlapping(buf[offset..].as_mut_ptr(), len)
+        })
+    }
+
+    /// Calls `f` for each page-sized piece of `[addr, addr + len)` with the
+    /// piece's kernel address, its offset into the range and its length.
+    fn copy(
+        &self,
+        addr: VirtAddr,
+        len: usize,
+        mut f: impl FnMut(*mut u8, usize, usize),
+    ) -> Result<(), Error> {
+        let mut done = 0;
+        while done < len {
+            let at = addr + done;
+            let phys = self
+                .with_mapper(|mapper| mapper.translate_addr(at))
+                .ok_or(Error::NotMapped)?;
+            let chunk = (4096 - (at.as_u64() % 4096) as usize).min(len - done);
+            f(phys_to_virt(phys).as_mut_ptr(), done, chunk);
+            done += chunk;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert_ne!(Cr3::read().0, self.level_4_table, "dropping the active address space");
+        with_frames(|frames| {
+            let table = table_at(self.level_4_table);
+            for i in USER_ENTRIES {
+                free_entry(&table[i], 3, frames);
+            }
+            unsafe { frames.deallocate_frame(self.level_4_table) };
+        });
+    }
+}
+
+/// Calls `f` for every page mapped in the user half of the tables under
+/// `level_4_table`, with the entry mapping it.
+fn for_each_user_page(level_4_table: PhysFrame, mut f: impl FnMut(Page, &mut PageTableEntry)) {
+    let level_4 = table_at(level_4_table);
+    for i4 in USER_ENTRIES {
+        let level_3 = match level_4[i4].frame() {
+            Ok(frame) => table_at(frame),
+            Err(_) => continue,
+        };
+        for i3 in 0..512 {
+            let level_2 = match level_3[i3].frame() {
+                Ok(frame) => table_at(frame),
+                Err(_) => continue,
+            };
+            for i2 in 0..512 {
+                let level_1 = match level_2[i2].frame() {
+                    Ok(frame) => table_at(frame),
+                    Err(_) => continue,
+                };
+                for i1 in 0..512 {
+                    if level_1[i1].is_unused() {
+                        continue;
+                    }
+                    let addr = (i4 << 39 | i3 << 30 | i2 << 21 | i1 << 12) as u64;
+                    f(Page::containing_address(VirtAddr::new(addr)), &mut level_1[i1]);
+                }
+            }
+        }
+    }
+}
+
+/// Frees what `entry` points to: a page table at `level` 1 to 3, along with
+/// everything below it, or a page at level 0.
+fn free_entry(entry: &PageTableEntry, level: u8, frames: &mut RecyclingFrameAllocator) {
+    let frame = match entry.frame() {
+        Ok(frame) => frame,
+        // unused, or a huge page, which user mappings never are
+        Err(_) => return,
+    };
+    if level > 0 {
+        for entry in table_at(frame).iter() {
+            free_entry(entry, level - 1, frames);
+        }
+    }
+    unsafe { frames.deallocate_frame(frame) };
+}
@@ -0,0 +1,126 @@
+//! Pipes: a bounded byte buffer with a read end and a write end.
+//!
+//! Both ends are `File`s, usually installed in file tables by the `pipe`
+//! system call and shared by forking. Reads block while the pipe is empty
+//! and writes while it's full, until the process is interrupted (see
+//! `WaitQueue::wait_until_interruptible`). An end is closed once the last
+//! reference to it goes: after that, reads drain what's left and then see
+//! the end of the file, and writes fail with `Error::BrokenPipe`, sending
+//! the writer's process SIGPIPE as well.
+
+use crate::file::File;
+use crate::process;
+use crate::signal::SIGPIPE;
+use crate::sync::{Condvar, Interrupted, Mutex};
+use crate::syscall::Error;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+/// Bytes a pipe holds before writers block.
+pub const PIPE_SIZE: usize = 4096;
+
+struct State {
+    buffer: VecDeque<u8>,
+    reader_open: bool,
+    writer_open: bool,
+}
+
+struct Pipe {
+    state: Mutex<State>,
+    /// Notified when there is something to read, or the writer closed.
+    readable: Condvar,
+    /// Notified when there is room to write, or the reader closed.
+    writable: Condvar,
+}
+
+/// Makes a pipe, and returns its read end and its write end.
+pub fn pipe() -> (ReadEnd, WriteEnd) {
+    let pipe = Arc::new(Pipe {
+        state: Mutex::new(State {
+            buffer: VecDeque::with_capacity(PIPE_SIZE),
+            reader_open: true,
+            writer_open: true,
+        }),
+        readable: Condvar::new(),
+        writable: Condvar::new(),
+    });
+    (ReadEnd(pipe.clone()), WriteEnd(pipe))
+}
+
+pub struct ReadEnd(Arc<Pipe>);
+
+impl File for ReadEnd {
+    /// Returns 0 only once the write end is closed and the pipe drained.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pipe = &self.0;
+        let state = pipe.state.lock();
+        let mut state = pipe
+            .readable
+            .wait_while_interruptible(state, |state| state.buffer.is_empty() && state.writer_open)
+            .map_err(|Interrupted| Error::Interrupted)?;
+        let len = buf.len().min(state.buffer.len());
+        for (byte, read) in buf.iter_mut().zip(state.buffer.drain(..len)) {
+            *byte = read;
+        }
+        drop(state);
+        if len > 0 {
+            pipe.writable.notify_all();
+        }
+        Ok(len)
+    }
+}
+
+impl Drop for ReadEnd {
+    fn drop(&mut self) {
+        self.0.state.lock().reader_open = false;
+        self.0.writable.notify_all();
+    }
+}
+
+pub struct WriteEnd(Arc<Pipe>);
+
+impl File for WriteEnd {
+    /// Blocks until all of `buf` is in the pipe. If the read end closes
+    /// first, returns what got in, or fails if nothing did; the same if the
+    /// process is interrupted.
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        let pipe = &self.0;
+        let mut written = 0;
+        let mut state = pipe.state.lock();
+        while written < buf.len() {
+            let waited = pipe.writable.wait_while_interruptible(state, |state| {
+                state.buffer.len() == PIPE_SIZE && state.reader_open
+            });
+            state = match waited {
+                Ok(state) => state,
+                Err(Interrupted) if written > 0 => return Ok(written),
+                Err(Interrupted) => return Err(Error::Interrupted),
+            };
+            if !state.reader_open {
+                break;
+            }
+            let len = (buf.len() - written).min(PIPE_SIZE - state.buffer.len());
+            state.buffer.extend(&buf[written..written + len]);
+            written += len;
+            pipe.readable.notify_all();
+        }
+        drop(state);
+        if written == 0 && !buf.is_empty() {
+            if let Some(process) = process::current() {
+                process.signals().raise(SIGPIPE);
+            }
+            return Err(Error::BrokenPipe);
+        }
+        Ok(written)
+    }
+}
+
+impl Drop for WriteEnd {
+    fn drop(&mut self) {
+        self.0.state.lock().writer_open = false;
+        self.0.readable.notify_all();
+    }
+}
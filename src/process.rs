@@ -0,0 +1,489 @@
+//! Processes: an address space, a file table and the threads running in them.
+//!
+//! Every process has a parent, the process that spawned it, or `Pid::KERNEL`
+//! if the kernel started it itself. When its last thread exits, a process
+//! closes its files and lets go of its address space, and stays behind as a
+//! zombie holding only its exit status until the parent collects that with
+//! `waitpid`. Children whose parent exits first are orphaned: nobody can
+//! wait for them any more, so they are dropped as soon as they exit.
+//!
+//! `exit` ends the whole process. The calling thread goes right away, the
+//! others the next time they would return to user mode, from a system call
+//! or a timer interrupt. A thread blocked in the kernel holds its process up
+//! until it wakes; `kill` and `exit` wake it if it's in an interruptible
+//! wait, see `WaitQueue::wait_until_interruptible`.
+//!
+//! `fork` starts a child with a copy-on-write copy of the caller's address
+//! space and a copy of its file table; `exec` replaces the caller's program
+//! with one from `fs`.
+//!
+//! `kill` sends a process a signal, see `signal`. A child's exit sends its
+//! parent SIGCHLD.
+//!
+//! Lock order: `PROCESSES` before the locks of any one process.
+
+use crate::file::FileTable;
+use crate::loader::{self, LoadError};
+use crate::memory::AddressSpace;
+use crate::signal::{self, Signals, UserRegisters, SIGCHLD, SIGKILL};
+use crate::sync::{Condvar, Interrupted, IrqSpinLock, Mutex, MutexGuard, WaitQueue};
+use crate::thread::{self, TooManyThreads};
+use crate::{fs, keyboard, usermode};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
+use x86_64::VirtAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Pid(u64);
+
+impl Pid {
+    /// The parent of processes the kernel started. No process has this pid.
+    pub const KERNEL: Pid = Pid(0);
+
+    fn next() -> Self {
+        static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+        Pid(NEXT_PID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn from_u64(pid: u64) -> Self {
+        Pid(pid)
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// How a process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// It called `exit` with this code.
+    Exited(u8),
+    /// A signal ended it, one of the `signal::SIG*` numbers.
+    Killed(u8),
+}
+
+impl ExitStatus {
+    /// The status as Linux's `wait` reports it: the exit code in bits 8 to
+    /// 15, or the signal in bits 0 to 6.
+    pub fn code(self) -> u32 {
+        match self {
+            ExitStatus::Exited(code) => u32::from(code) << 8,
+            ExitStatus::Killed(signal) => u32::from(signal & 0x7f),
+        }
+    }
+}
+
+pub struct Process {
+    pid: Pid,
+    /// `None` once the last thread has exited.
+    address_space: Mutex<Option<Arc<AddressSpace>>>,
+    files: Mutex<FileTable>,
+    signals: Signals,
+    /// Threads that haven't exited yet.
+    threads: AtomicUsize,
+    /// Set by `exit`, and checked without locking on the way to user mode.
+    exiting: AtomicBool,
+    /// What `exit` was asked for first.
+    status: Mutex<Option<ExitStatus>>,
+    /// The queue the process's thread is in an interruptible wait on.
+    waiting: IrqSpinLock<Option<WaitingOn>>,
+}
+
+/// Points to a `WaitQueue` that a thread is blocked on. The `Waiting` guard
+/// that stored it takes it out again before the wait returns, so it never
+/// outlives the queue.
+struct WaitingOn(*const WaitQueue);
+
+// only dereferenced under `Process::waiting`, see above
+unsafe impl Send for WaitingOn {}
+
+/// Returned by `Process::wait_on`; unregisters the wait when dropped.
+pub(crate) struct Waiting<'a> {
+    process: &'a Process,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        *self.process.waiting.lock() = None;
+    }
+}
+
+impl Process {
+    fn new(address_space: Arc<AddressSpace>, files: FileTable, signals: Signals) -> Self {
+        Process {
+            pid: Pid::next(),
+            address_space: Mutex::new(Some(address_space)),
+            files: Mutex::new(files),
+            signals,
+            threads: AtomicUsize::new(1),
+            exiting: AtomicBool::new(false),
+            status: Mutex::new(None),
+            waiting: IrqSpinLock::new(None),
+        }
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn address_space(&self) -> Option<Arc<AddressSpace>> {
+        self.address_space.lock().clone()
+    }
+
+    pub fn files(&self) -> MutexGuard<FileTable> {
+        self.files.lock()
+    }
+
+    pub fn signals(&self) -> &Signals {
+        &self.signals
+    }
+
+    /// Whether the process is on its way out.
+    pub fn is_exiting(&self) -> bool {
+        self.exiting.load(Ordering::Acquire)
+    }
+
+    /// Number of threads that haven't exited yet.
+    pub fn thread_count(&self) -> usize {
+        self.threads.load(Ordering::Acquire)
+    }
+
+    /// Whether an interruptible wait should give up: the process is exiting
+    /// or has a signal pending that will do something.
+    pub fn is_interrupted(&self) -> bool {
+        self.is_exiting() || self.signals.has_interrupting()
+    }
+
+    /// Registers the calling thread, which belongs to this process, as
+    /// waiting on `queue`, so that `interrupt` can wake it, until the
+    /// returned guard is dropped.
+    pub(crate) fn wait_on<'a>(&'a self, queue: &'a WaitQueue) -> Waiting<'a> {
+        let mut waiting = self.waiting.lock();
+        debug_assert!(waiting.is_none(), "process has two threads in interruptible waits");
+        *waiting = Some(WaitingOn(queue));
+        Waiting { process: self }
+    }
+
+    /// Wakes the process's thread if it's in an interruptible wait, for it
+    /// to check `is_interrupted`.
+    fn interrupt(&self) {
+        if let Some(WaitingOn(queue)) = *self.waiting.lock() {
+            // still registered, so the waiter and its queue are still there
+            unsafe { &*queue }.notify_all();
+        }
+    }
+
+    /// Tells every thread to exit; the first status asked for sticks.
+    fn request_exit(&self, status: ExitStatus) {
+        let mut requested = self.status.lock();
+        if requested.is_none() {
+            *requested = Some(status);
+        }
+        self.exiting.store(true, Ordering::Release);
+        drop(requested);
+        self.interrupt();
+    }
+}
+
+/// A process table entry; the relationships live here so that one lock
+/// covers them all.
+struct Entry {
+    process: Arc<Process>,
+    /// `None` once orphaned.
+    parent: Option<Pid>,
+    /// Set when the process becomes a zombie.
+    exit_status: Option<ExitStatus>,
+}
+
+lazy_static! {
+    /// Every process that hasn't been reaped yet.
+    static ref PROCESSES: Mutex<BTreeMap<Pid, Entry>> = Mutex::new(BTreeMap::new());
+}
+/// Notified whenever a process becomes a zombie.
+static CHILD_EXITED: Condvar = Condvar::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The caller has no child it could wait for.
+    NoChildren,
+    /// The calling process was killed or signalled before a child exited.
+    Interrupted,
+}
+
+/// Returned by `kill` for a process that doesn't exist or already exited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoSuchProcess;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForkError {
+    /// Kernel threads have nothing to fork.
+    NotAProcess,
+    OutOfMemory,
+    TooManyThreads,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    /// Kernel threads have no program to replace.
+    NotAProcess,
+    /// Nothing in `fs` at that path.
+    NotFound,
+    Load(LoadError),
+}
+
+impl From<LoadError> for ExecError {
+    fn from(err: LoadError) -> Self {
+        ExecError::Load(err)
+    }
+}
+
+/// The process of the calling thread, if it belongs to one.
+pub fn current() -> Option<Arc<Process>> {
+    // nothing runs in a process before there are threads
+    if !thread::is_initialized() {
+        return None;
+    }
+    thread::process()
+}
+
+/// The pid of the calling thread's process, `Pid::KERNEL` for kernel threads.
+pub fn current_pid() -> Pid {
+    current().map_or(Pid::KERNEL, |process| process.pid)
+}
+
+/// Loads the executable in `image` into a new process, a child of the
+/// calling one, and starts its main thread. The child gets a copy of the
+/// parent's file table, or the console if the parent is the kernel, in
+/// which case it also becomes the process Ctrl+C interrupts.
+pub fn spawn(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<Pid, LoadError> {
+    let parent = current();
+    let files = parent.as_ref().map_or_else(FileTable::with_console, |parent| parent.files().clone());
+    let pid = spawn_with_files(image, argv, envp, files)?;
+    if parent.is_none() {
+        keyboard::set_foreground(pid);
+    }
+    Ok(pid)
+}
+
+/// Like `spawn`, but the child starts out with `files`.
+pub fn spawn_with_files(
+    image: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+    files: FileTable,
+) -> Result<Pid, LoadError> {
+    let program = loader::load(image, argv, envp)?;
+    let process = Process::new(program.address_space, files, Signals::new());
+    let (entry, stack_pointer) = (program.entry, program.stack_pointer);
+    start(process, move || unsafe { usermode::enter(entry, stack_pointer) })
+        .map_err(|TooManyThreads| LoadError::TooManyThreads)
+}
+
+/// Starts a child of the calling process that shares its memory
+/// copy-on-write and has a copy of its file table and signal actions. The child's one thread
+/// runs `f`, which typically returns to user mode where the parent's
+/// system call left it.
+pub fn fork<F>(f: F) -> Result<Pid, ForkError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let parent = current().ok_or(ForkError::NotAProcess)?;
+    let address_space = parent.address_space().ok_or(ForkError::NotAProcess)?;
+    // running out of frames is the only way a fork can fail
+    let copy = address_space.fork().map_err(|_| ForkError::OutOfMemory)?;
+    let files = parent.files().clone();
+    let signals = parent.signals.fork();
+    start(Process::new(Arc::new(copy), files, signals), f).map_err(|TooManyThreads| ForkError::TooManyThreads)
+}
+
+/// Replaces the calling process's program with the executable at `path` in
+/// `fs`, and returns its entry point and initial stack pointer, for the
+/// caller to enter. Files stay open, signal handlers go back to the
+/// default action. On failure the old program carries on.
+pub fn exec(path: &str, argv: &[&str], envp: &[&str]) -> Result<(VirtAddr, VirtAddr), ExecError> {
+    let process = current().ok_or(ExecError::NotAProcess)?;
+    let image = fs::lookup(path).ok_or(ExecError::NotFound)?;
+    let program = loader::load(image, argv, envp)?;
+    *process.address_space.lock() = Some(program.address_space.clone());
+    process.signals.reset_handlers();
+    let old = thread::replace_address_space(Some(program.address_space));
+    drop(old);
+    Ok((program.entry, program.stack_pointer))
+}
+
+/// Puts `process` in the table as a child of the calling one, and starts its
+/// first thread running `f`.
+fn start<F>(process: Process, f: F) -> Result<Pid, TooManyThreads>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let process = Arc::new(process);
+    let pid = process.pid;
+    // in the table first, so that it can exit right away
+    PROCESSES.lock().insert(
+        pid,
+        Entry {
+            process: process.clone(),
+            parent: Some(current_pid()),
+            exit_status: None,
+        },
+    );
+    if let Err(err) = thread::spawn_for(process, f) {
+        let entry = PROCESSES.lock().remove(&pid);
+        drop(entry);
+        return Err(err);
+    }
+    Ok(pid)
+}
+
+/// Ends the calling process with `status`, or just the calling thread if it
+/// doesn't belong to one.
+pub fn exit(status: ExitStatus) -> ! {
+    if let Some(process) = current() {
+        process.request_exit(status);
+    }
+    exit_thread()
+}
+
+/// Sends `signal` to process `pid`, for it to deliver the next time one of
+/// its threads returns to user mode. SIGKILL makes all of them exit then.
+/// Either way a thread in an interruptible wait is woken to get there.
+/// Signal 0 only checks that the process exists.
+///
+/// Panics if `signal` is neither 0 nor a valid signal.
+pub fn kill(pid: Pid, signal: u8) -> Result<(), NoSuchProcess> {
+    assert!(signal == 0 || signal::is_valid(signal), "no signal {}", signal);
+    let process = match PROCESSES.lock().get(&pid) {
+        Some(entry) if entry.exit_status.is_none() => entry.process.clone(),
+        _ => return Err(NoSuchProcess),
+    };
+    match signal {
+        0 => {}
+        SIGKILL => process.request_exit(ExitStatus::Killed(SIGKILL)),
+        signal => {
+            process.signals.raise(signal);
+            process.interrupt();
+        }
+    }
+    Ok(())
+}
+
+/// Waits for a child of the calling process to exit, child `pid` or any if
+/// `None`, and reaps it. The wait is interruptible.
+pub fn waitpid(pid: Option<Pid>) -> Result<(Pid, ExitStatus), WaitError> {
+    let parent = current_pid();
+    let mut processes = PROCESSES.lock();
+    loop {
+        let mut children = processes
+            .iter()
+            .filter(|(child, entry)| entry.parent == Some(parent) && pid.map_or(true, |pid| **child == pid))
+            .peekable();
+        if children.peek().is_none() {
+            return Err(WaitError::NoChildren);
+        }
+        let zombie = children.find_map(|(child, entry)| entry.exit_status.map(|status| (*child, status)));
+        if let Some((child, status)) = zombie {
+            let entry = processes.remove(&child);
+            drop(processes);
+            drop(entry);
+            return Ok((child, status));
+        }
+        processes = CHILD_EXITED
+            .wait_interruptible(processes)
+            .map_err(|Interrupted| WaitError::Interrupted)?;
+    }
+}
+
+/// `waitpid` for any child.
+pub fn wait() -> Result<(Pid, ExitStatus), WaitError> {
+    waitpid(None)
+}
+
+/// Called on the way back to user mode with the user's `registers`, maybe
+/// with interrupts disabled but holding no locks. Ends the calling thread if
+/// its process is exiting, and otherwise delivers its pending signals.
+pub(crate) fn on_return_to_user(registers: &mut impl UserRegisters) {
+    let process = match current() {
+        Some(process) => process,
+        None => return,
+    };
+    if process.is_exiting() {
+        interrupts::enable();
+        drop(process);
+        exit_thread();
+    }
+    if process.signals.has_deliverable() {
+        interrupts::enable();
+        let fatal = signal::deliver(&process.signals, registers);
+        // exiting never returns, so nothing may be left to drop
+        drop(process);
+        if let Some(signal) = fatal {
+            exit(ExitStatus::Killed(signal));
+        }
+    }
+}
+
+/// Ends the calling thread, and its process with it if it was the last one.
+fn exit_thread() -> ! {
+    if let Some(process) = current() {
+        if process.threads.fetch_sub(1, Ordering::AcqRel) == 1 {
+            finish(&process);
+        }
+    }
+    thread::exit()
+}
+
+/// Turns `process`, whose threads have all exited, into a zombie.
+fn finish(process: &Process) {
+    let status = process.status.lock().unwrap_or(ExitStatus::Exited(0));
+    process.files().clear();
+    // the exiting thread still has it loaded, and frees it once reaped
+    let address_space = process.address_space.lock().take();
+    drop(address_space);
+
+    let mut processes = PROCESSES.lock();
+    let mut reaped = Vec::new();
+    for (child, entry) in processes.iter_mut() {
+        if entry.parent == Some(process.pid) {
+            entry.parent = None;
+            if entry.exit_status.is_some() {
+                reaped.push(*child);
+            }
+        }
+    }
+    let parent = {
+        let entry = processes.get_mut(&process.pid).expect("exiting process isn't in the table");
+        entry.exit_status = Some(status);
+        entry.parent
+    };
+    if let Some(parent) = parent.and_then(|parent| processes.get(&parent)) {
+        parent.process.signals.raise(SIGCHLD);
+    }
+    let orphan = parent.is_none();
+    if orphan {
+        reaped.push(process.pid);
+    }
+    let reaped: Vec<_> = reaped.iter().filter_map(|pid| processes.remove(pid)).collect();
+    drop(processes);
+    drop(reaped);
+    CHILD_EXITED.notify_all();
+}
+
+#[test_case]
+fn test_exit_status_codes() {
+    use crate::signal::SIGSEGV;
+
+    assert_eq!(ExitStatus::Exited(3).code(), 0x300);
+    assert_eq!(ExitStatus::Killed(SIGSEGV).code(), 11);
+}
+
+#[test_case]
+fn test_wait_without_children() {
+    assert_eq!(wait(), Err(WaitError::NoChildren));
+    assert_eq!(kill(Pid::KERNEL, SIGKILL), Err(NoSuchProcess));
+}
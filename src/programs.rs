@@ -0,0 +1,26 @@
+//! User programs built into the kernel, from the `user` crate (see build.rs).
+
+use crate::fs;
+
+macro_rules! program {
+    ($name:literal) => {
+        include_bytes!(concat!(env!("MOOOS_USER_BIN"), "/", $name))
+    };
+}
+
+/// Prints a greeting with its pid.
+pub const HELLO: &[u8] = program!("hello");
+/// Prints its arguments.
+pub const ECHO: &[u8] = program!("echo");
+/// Copies standard input to standard output.
+pub const CAT: &[u8] = program!("cat");
+
+/// Every program, by name.
+pub const ALL: &[(&str, &[u8])] = &[("hello", HELLO), ("echo", ECHO), ("cat", CAT)];
+
+/// Puts every program in `fs`, under /bin.
+pub fn install() {
+    for (name, image) in ALL {
+        fs::install(&alloc::format!("/bin/{}", name), image);
+    }
+}
@@ -0,0 +1,310 @@
+//! CMOS real-time clock and wall-clock time.
+//!
+//! The RTC is only read once, at `init`, since every read means polling
+//! slow I/O ports around an update cycle. The wall clock after that is the
+//! boot reading plus the monotonic `time::tsc::now` clock.
+//!
+//! The RTC can also raise a periodic interrupt on IRQ 8, which
+//! `enable_periodic` turns on.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+/// Not standardized, but where every PC we care about keeps it.
+const REG_CENTURY: u8 = 0x32;
+
+/// status A: an update is in progress, the time registers are in flux
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// status B: hours are 0-23 rather than 1-12 with a PM bit
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+/// status B: values are binary rather than BCD
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// status B: periodic interrupt enable
+const STATUS_B_PERIODIC: u8 = 1 << 6;
+
+/// status C: the interrupt was a periodic one
+const STATUS_C_PERIODIC: u8 = 1 << 6;
+
+/// set on the hour register in 12-hour mode for PM
+const HOUR_PM: u8 = 1 << 7;
+
+/// Selecting a register with bit 7 set would also mask NMIs.
+const NMI_DISABLE: u8 = 1 << 7;
+
+struct Cmos {
+    index: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    const fn new() -> Cmos {
+        Cmos {
+            index: Port::new(0x70),
+            data: Port::new(0x71),
+        }
+    }
+
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.index.write(register & !NMI_DISABLE);
+            self.data.read()
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        unsafe {
+            self.index.write(register & !NMI_DISABLE);
+            self.data.write(value);
+        }
+    }
+
+    fn update_in_progress(&mut self) -> bool {
+        self.read(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+    }
+
+    fn read_raw(&mut self) -> [u8; 7] {
+        while self.update_in_progress() {}
+        [
+            self.read(REG_SECONDS),
+            self.read(REG_MINUTES),
+            self.read(REG_HOURS),
+            self.read(REG_DAY),
+            self.read(REG_MONTH),
+            self.read(REG_YEAR),
+            self.read(REG_CENTURY),
+        ]
+    }
+}
+
+/// The interrupt handler reads status C too, so normal code must only touch
+/// this with interrupts disabled.
+static CMOS: Mutex<Cmos> = Mutex::new(Cmos::new());
+
+static BOOT_UNIX_SECONDS: AtomicU64 = AtomicU64::new(0);
+/// `tsc::now` at the moment of the boot reading.
+static BOOT_MONOTONIC_NANOS: AtomicU64 = AtomicU64::new(0);
+
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01T00:00:00Z.
+    pub fn to_unix_seconds(&self) -> u64 {
+        let days = days_from_civil(i64::from(self.year), self.month.into(), self.day.into());
+        let seconds_of_day =
+            u64::from(self.hour) * 3600 + u64::from(self.minute) * 60 + u64::from(self.second);
+        days as u64 * 86400 + seconds_of_day
+    }
+
+    pub fn from_unix_seconds(seconds: u64) -> DateTime {
+        let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+        let seconds_of_day = seconds % 86400;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+// Howard Hinnant's days <-> civil date algorithms, proleptic Gregorian.
+// (http://howardhinnant.github.io/date_algorithms.html)
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Reads the current date and time from the CMOS clock.
+///
+/// The registers are read until two passes agree, so a value can't be torn
+/// by an update that starts halfway through.
+pub fn read() -> DateTime {
+    let (raw, status_b) = interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        let mut raw = cmos.read_raw();
+        loop {
+            let again = cmos.read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        (raw, cmos.read(REG_STATUS_B))
+    });
+
+    let [mut second, mut minute, hour_raw, mut day, mut month, mut year, mut century] = raw;
+    let pm = hour_raw & HOUR_PM != 0;
+    let mut hour = hour_raw & !HOUR_PM;
+
+    if status_b & STATUS_B_BINARY == 0 {
+        second = from_bcd(second);
+        minute = from_bcd(minute);
+        hour = from_bcd(hour);
+        day = from_bcd(day);
+        month = from_bcd(month);
+        year = from_bcd(year);
+        century = from_bcd(century);
+    }
+
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is midnight, 12 PM is noon
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    // some firmware leaves the century register empty
+    let century = if (19..=21).contains(&century) { century } else { 20 };
+
+    DateTime {
+        year: u16::from(century) * 100 + u16::from(year),
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    }
+}
+
+/// Takes the boot reading the wall clock is based on.
+pub fn init() {
+    let boot = read();
+    BOOT_MONOTONIC_NANOS.store(crate::time::tsc::now(), Ordering::Relaxed);
+    BOOT_UNIX_SECONDS.store(boot.to_unix_seconds(), Ordering::Relaxed);
+}
+
+/// Time since the Unix epoch.
+pub fn wall_clock() -> Duration {
+    let boot = Duration::from_secs(BOOT_UNIX_SECONDS.load(Ordering::Relaxed));
+    let elapsed = crate::time::tsc::now() - BOOT_MONOTONIC_NANOS.load(Ordering::Relaxed);
+    boot + Duration::from_nanos(elapsed)
+}
+
+/// The current date and time, in UTC.
+pub fn now() -> DateTime {
+    DateTime::from_unix_seconds(wall_clock().as_secs())
+}
+
+/// Turns on the RTC periodic interrupt at 32768 >> (`rate` - 1) Hz.
+///
+/// `rate` must be between 3 (8192 Hz) and 15 (2 Hz).
+pub fn enable_periodic(rate: u8) {
+    assert!((3..=15).contains(&rate), "invalid RTC rate {}", rate);
+
+    interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        let status_a = cmos.read(REG_STATUS_A);
+        cmos.write(REG_STATUS_A, (status_a & 0xf0) | rate);
+        let status_b = cmos.read(REG_STATUS_B);
+        cmos.write(REG_STATUS_B, status_b | STATUS_B_PERIODIC);
+        // a pending interrupt that isn't acknowledged blocks all further ones
+        cmos.read(REG_STATUS_C);
+    });
+    crate::interrupts::unmask_irq(crate::interrupts::InterruptIndex::Rtc.irq());
+}
+
+pub fn disable_periodic() {
+    interrupts::without_interrupts(|| {
+        let mut cmos = CMOS.lock();
+        let status_b = cmos.read(REG_STATUS_B);
+        cmos.write(REG_STATUS_B, status_b & !STATUS_B_PERIODIC);
+    });
+}
+
+/// Number of periodic interrupts seen so far.
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+/// Acknowledges an RTC interrupt. Called from the IRQ 8 handler.
+pub(crate) fn handle_interrupt() {
+    // the RTC won't raise another interrupt until status C has been read
+    let status_c = CMOS.lock().read(REG_STATUS_C);
+    if status_c & STATUS_C_PERIODIC != 0 {
+        PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test_case]
+fn test_unix_round_trip() {
+    let date = DateTime { year: 2000, month: 3, day: 1, hour: 13, minute: 37, second: 42 };
+    assert_eq!(date.to_unix_seconds(), 951_868_800 + 13 * 3600 + 37 * 60 + 42);
+    assert_eq!(DateTime::from_unix_seconds(date.to_unix_seconds()), date);
+}
+
+#[test_case]
+fn test_read_is_sane() {
+    let date = read();
+    assert!(date.year >= 2020);
+    assert!((1..=12).contains(&date.month));
+    assert!((1..=31).contains(&date.day));
+    assert!(date.hour < 24 && date.minute < 60 && date.second < 60);
+}
+
+#[test_case]
+fn test_periodic_interrupt() {
+    enable_periodic(6); // 1024 Hz
+    let start = periodic_ticks();
+    crate::time::sleep_ticks(20);
+    disable_periodic();
+    assert!(periodic_ticks() > start);
+}
@@ -1,26 +1,32 @@
+use crate::sync::IrqSpinLock;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use uart_16550::SerialPort;
 
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
+    pub static ref SERIAL1: IrqSpinLock<SerialPort> = {
         let mut serial_port = unsafe { SerialPort::new(0x3F8) };
         serial_port.init();
-        Mutex::new(serial_port)
+        IrqSpinLock::named("SERIAL1", serial_port)
     };
 }
 
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
-    interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
-    })
+    SERIAL1
+        .lock()
+        .write_fmt(args)
+        .expect("Printing to serial failed");
+}
+
+/// Breaks the serial port lock if someone holds it.
+///
+/// Only for reporting from a handler that may have interrupted the lock
+/// holder, which would otherwise deadlock. Output can end up interleaved
+/// with whatever the holder was printing.
+pub unsafe fn force_unlock() {
+    SERIAL1.force_unlock();
 }
 
 /// Prints to the host through the serial interface.
@@ -0,0 +1,488 @@
+//! Signals: asynchronous notifications for processes.
+//!
+//! Every process has a set of pending signals, a mask of blocked ones and
+//! an action for each signal. `process::kill` marks a signal pending; a
+//! thread delivers pending signals that aren't blocked on its way back to
+//! user mode, from a system call or a trap. Depending on the action, the
+//! signal is ignored, ends the process, or runs a handler in user code.
+//!
+//! To run a handler, the kernel saves the interrupted registers and the
+//! mask as a `SignalContext` on the user stack, below the red zone, with
+//! the action's restorer as the return address on top. The handler starts
+//! with the signal number in rdi and more signals blocked; when it returns
+//! the restorer makes the `sigreturn` system call, which puts back the
+//! registers and the mask from the context. User code provides the
+//! restorer, a trampoline that is nothing but that call.
+//!
+//! Exceptions raised in ring 3 are signals too, forced through even when
+//! blocked or ignored since carrying on would only fault again. SIGKILL
+//! can't be caught, blocked or ignored, and ends the process right away.
+//!
+//! Only reads from the keyboard are cut short by a signal; other blocking
+//! calls finish first.
+
+use crate::interrupts::trap::{self, TrapFrame};
+use crate::process::{self, ExitStatus};
+use crate::syscall::{self, Error};
+use crate::sync::Mutex;
+use crate::usermode;
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
+
+/// Signal numbers, as on Linux.
+pub const SIGINT: u8 = 2;
+pub const SIGILL: u8 = 4;
+pub const SIGFPE: u8 = 8;
+pub const SIGKILL: u8 = 9;
+pub const SIGUSR1: u8 = 10;
+pub const SIGSEGV: u8 = 11;
+pub const SIGUSR2: u8 = 12;
+pub const SIGPIPE: u8 = 13;
+pub const SIGTERM: u8 = 15;
+pub const SIGCHLD: u8 = 17;
+
+/// Signals are numbered from 1 up to, not including, this.
+pub const NSIG: u8 = 32;
+
+/// `SigAction::handler` for the default action.
+pub const SIG_DFL: u64 = 0;
+/// `SigAction::handler` to ignore the signal.
+pub const SIG_IGN: u64 = 1;
+
+/// Bytes below the user's stack pointer that leaf functions may use
+/// without moving it, as the System V ABI allows.
+const RED_ZONE: u64 = 128;
+
+/// RFLAGS bits a signal context may change: the arithmetic flags and the
+/// direction flag.
+const USER_FLAGS: u64 = 0xcd5;
+
+/// A set of signals; signal `n` is bit `n - 1`, as on Linux.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigSet(u64);
+
+impl SigSet {
+    pub const EMPTY: SigSet = SigSet(0);
+
+    pub fn from_bits(bits: u64) -> Self {
+        SigSet(bits)
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn contains(self, signal: u8) -> bool {
+        self.0 & bit(signal) != 0
+    }
+
+    pub fn with(self, signal: u8) -> Self {
+        SigSet(self.0 | bit(signal))
+    }
+
+    /// The set without the signals nothing can block.
+    fn blockable(self) -> Self {
+        SigSet(self.0 & !bit(SIGKILL))
+    }
+}
+
+fn bit(signal: u8) -> u64 {
+    assert!(is_valid(signal), "no signal {}", signal);
+    1 << (signal - 1)
+}
+
+/// Whether there is a signal numbered `signal`.
+pub fn is_valid(signal: u8) -> bool {
+    (1..NSIG).contains(&signal)
+}
+
+/// Whether a signal's default action is to do nothing.
+fn ignored_by_default(signal: u8) -> bool {
+    signal == SIGCHLD
+}
+
+/// What a process does with a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Ignore SIGCHLD, end the process for anything else.
+    Default,
+    Ignore,
+    /// Run a handler in user code.
+    Handler {
+        /// Called with the signal number.
+        entry: u64,
+        /// Blocked while the handler runs, on top of the signal itself.
+        mask: SigSet,
+        /// Where the handler returns to; it must make the `sigreturn`
+        /// system call.
+        restorer: u64,
+    },
+}
+
+/// `Action` as user code passes it to `sigaction`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN` or the handler's address.
+    pub handler: u64,
+    pub mask: u64,
+    pub restorer: u64,
+}
+
+impl From<Action> for SigAction {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Default => SigAction { handler: SIG_DFL, ..SigAction::default() },
+            Action::Ignore => SigAction { handler: SIG_IGN, ..SigAction::default() },
+            Action::Handler { entry, mask, restorer } => SigAction {
+                handler: entry,
+                mask: mask.bits(),
+                restorer,
+            },
+        }
+    }
+}
+
+/// Why `Signals::set_action` refused an action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionError {
+    /// No such signal, or one whose action can't change.
+    InvalidSignal,
+    /// A handler or restorer outside the user range.
+    BadAddress,
+}
+
+impl SigAction {
+    /// The action this describes, if its addresses are ones user code could
+    /// run.
+    pub fn to_action(self) -> Result<Action, ActionError> {
+        match self.handler {
+            SIG_DFL => Ok(Action::Default),
+            SIG_IGN => Ok(Action::Ignore),
+            entry if is_user_address(entry) && is_user_address(self.restorer) => Ok(Action::Handler {
+                entry,
+                mask: SigSet(self.mask),
+                restorer: self.restorer,
+            }),
+            _ => Err(ActionError::BadAddress),
+        }
+    }
+}
+
+/// Whether user code could run code at `addr`, if anything is mapped there.
+fn is_user_address(addr: u64) -> bool {
+    VirtAddr::try_new(addr).map_or(false, |addr| usermode::is_user_range(addr, 1))
+}
+
+/// The registers saved while a handler runs, and the mask to go back to.
+/// User code finds it where the handler's stack pointer pointed after the
+/// return address: the layout is ABI.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct SignalContext {
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub rdx: u64,
+    pub rax: u64,
+    pub rcx: u64,
+    pub rsp: u64,
+    pub rip: u64,
+    pub rflags: u64,
+    /// Blocked signals, as a `SigSet`.
+    pub mask: u64,
+}
+
+/// The saved user registers on a way back to ring 3: a `SyscallFrame` or a
+/// `TrapFrame`.
+pub(crate) trait UserRegisters {
+    /// The registers, with an empty mask.
+    fn context(&self) -> SignalContext;
+    /// Makes the return go to the registers in `context`, ignoring its mask.
+    fn set_context(&mut self, context: &SignalContext);
+}
+
+/// A process's signal state.
+pub struct Signals {
+    pending: AtomicU64,
+    blocked: AtomicU64,
+    actions: Mutex<[Action; NSIG as usize]>,
+    /// The signals `actions` has nothing happen for, kept in step with it
+    /// so that it can be checked without the lock.
+    ignored: AtomicU64,
+}
+
+/// The signals with nothing to do among `actions`.
+fn ignored(actions: &[Action; NSIG as usize]) -> u64 {
+    (1..NSIG)
+        .filter(|&signal| match actions[usize::from(signal)] {
+            Action::Ignore => true,
+            Action::Default => ignored_by_default(signal),
+            Action::Handler { .. } => false,
+        })
+        .fold(0, |set, signal| set | bit(signal))
+}
+
+impl Signals {
+    pub(crate) fn new() -> Self {
+        let actions = [Action::Default; NSIG as usize];
+        Signals {
+            pending: AtomicU64::new(0),
+            blocked: AtomicU64::new(0),
+            ignored: AtomicU64::new(ignored(&actions)),
+            actions: Mutex::new(actions),
+        }
+    }
+
+    /// The state a forked child starts with: the same actions and mask,
+    /// nothing pending.
+    pub(crate) fn fork(&self) -> Self {
+        let actions = *self.actions.lock();
+        Signals {
+            pending: AtomicU64::new(0),
+            blocked: AtomicU64::new(self.blocked.load(Ordering::Acquire)),
+            ignored: AtomicU64::new(ignored(&actions)),
+            actions: Mutex::new(actions),
+        }
+    }
+
+    /// Forgets the handlers, which belong to the program `exec` replaced.
+    /// Ignored signals stay ignored.
+    pub(crate) fn reset_handlers(&self) {
+        let mut actions = self.actions.lock();
+        for action in actions.iter_mut() {
+            if let Action::Handler { .. } = action {
+                *action = Action::Default;
+            }
+        }
+        self.ignored.store(ignored(&actions), Ordering::Release);
+    }
+
+    pub fn pending(&self) -> SigSet {
+        SigSet(self.pending.load(Ordering::Acquire))
+    }
+
+    pub fn blocked(&self) -> SigSet {
+        SigSet(self.blocked.load(Ordering::Acquire))
+    }
+
+    /// Blocks the signals in `set`, or as many as can be, and returns what
+    /// was blocked before.
+    pub fn set_blocked(&self, set: SigSet) -> SigSet {
+        SigSet(self.blocked.swap(set.blockable().bits(), Ordering::AcqRel))
+    }
+
+    pub fn action(&self, signal: u8) -> Action {
+        self.actions.lock()[usize::from(signal)]
+    }
+
+    /// Sets the action for `signal` and returns the old one.
+    pub fn set_action(&self, signal: u8, action: Action) -> Result<Action, ActionError> {
+        if !is_valid(signal) || signal == SIGKILL {
+            return Err(ActionError::InvalidSignal);
+        }
+        let mut actions = self.actions.lock();
+        let old = mem::replace(&mut actions[usize::from(signal)], action);
+        self.ignored.store(ignored(&actions), Ordering::Release);
+        Ok(old)
+    }
+
+    /// Whether a pending signal isn't blocked, so the next return to user
+    /// mode will deliver it.
+    pub fn has_deliverable(&self) -> bool {
+        self.pending.load(Ordering::Acquire) & !self.blocked.load(Ordering::Acquire) != 0
+    }
+
+    /// Like `has_deliverable`, but only counting signals that will do
+    /// something when delivered: run a handler or end the process. Doesn't
+    /// take any locks, so blocking calls can check it while they wait.
+    pub fn has_interrupting(&self) -> bool {
+        let quiet = self.blocked.load(Ordering::Acquire) | self.ignored.load(Ordering::Acquire);
+        self.pending.load(Ordering::Acquire) & !quiet != 0
+    }
+
+    /// Marks `signal` pending. Never blocks.
+    pub(crate) fn raise(&self, signal: u8) {
+        self.pending.fetch_or(bit(signal), Ordering::AcqRel);
+    }
+
+    /// Raises `signal` so that it's delivered, resetting its action to the
+    /// default if it was blocked or ignored, as Linux does for exceptions.
+    fn force(&self, signal: u8) {
+        let mut actions = self.actions.lock();
+        let blocked = self.blocked.fetch_and(!bit(signal), Ordering::AcqRel) & bit(signal) != 0;
+        let action = &mut actions[usize::from(signal)];
+        if blocked || *action == Action::Ignore {
+            *action = Action::Default;
+        }
+        self.ignored.store(ignored(&actions), Ordering::Release);
+        drop(actions);
+        self.raise(signal);
+    }
+
+    /// Takes the lowest numbered pending signal that isn't blocked.
+    fn take(&self) -> Option<u8> {
+        loop {
+            let pending = self.pending.load(Ordering::Acquire);
+            let deliverable = pending & !self.blocked.load(Ordering::Acquire);
+            if deliverable == 0 {
+                return None;
+            }
+            let lowest = deliverable & deliverable.wrapping_neg();
+            if self.pending.fetch_and(!lowest, Ordering::AcqRel) & lowest != 0 {
+                return Some(lowest.trailing_zeros() as u8 + 1);
+            }
+        }
+    }
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Signals::new()
+    }
+}
+
+/// Delivers the pending signals in `signals`, the calling thread's
+/// process's, that aren't blocked: skips the ignored ones and makes
+/// `registers` enter the handler of the first one that has one. Returns the
+/// signal the process has to die of instead, if any. Needs interrupts
+/// enabled.
+pub(crate) fn deliver(signals: &Signals, registers: &mut impl UserRegisters) -> Option<u8> {
+    while let Some(signal) = signals.take() {
+        match signals.action(signal) {
+            Action::Ignore => {}
+            Action::Default if ignored_by_default(signal) => {}
+            Action::Default => return Some(signal),
+            Action::Handler { entry, mask, restorer } => {
+                let old_mask = signals.blocked();
+                let mut context = registers.context();
+                context.mask = old_mask.bits();
+                let stack_pointer = match push_frame(&context, restorer) {
+                    Ok(stack_pointer) => stack_pointer,
+                    // the stack is gone, nothing left to run the handler on
+                    Err(_) => return Some(SIGSEGV),
+                };
+                signals.set_blocked(SigSet(old_mask.bits() | mask.bits()).with(signal));
+                registers.set_context(&SignalContext {
+                    rip: entry,
+                    rsp: stack_pointer,
+                    rdi: u64::from(signal),
+                    // the ABI has functions start with it clear
+                    rflags: usermode::USER_RFLAGS
+                        | (context.rflags & USER_FLAGS & !RFlags::DIRECTION_FLAG.bits()),
+                    ..context
+                });
+                return None;
+            }
+        }
+    }
+    None
+}
+
+/// Puts `context` and then `restorer` on the user stack below the red
+/// zone, and returns the stack pointer the handler starts with.
+fn push_frame(context: &SignalContext, restorer: u64) -> Result<u64, Error> {
+    let size = mem::size_of::<SignalContext>() as u64;
+    let context_at = context.rsp.checked_sub(RED_ZONE + size).ok_or(Error::BadAddress)? & !0xf;
+    // as if called: the return address is at rsp, and rsp + 8 is aligned
+    let stack_pointer = context_at - 8;
+    syscall::write_user(context_at, *context)?;
+    syscall::write_user(stack_pointer, restorer)?;
+    Ok(stack_pointer)
+}
+
+/// The `sigreturn` system call: goes back to the user code a handler
+/// interrupted, with the registers and mask in the context at
+/// `stack_pointer`, where the handler's return left the user stack.
+pub(crate) fn sigreturn(stack_pointer: u64) -> ! {
+    let context = match unsafe { syscall::read_user::<SignalContext>(stack_pointer) } {
+        Ok(context) => context,
+        Err(_) => process::exit(ExitStatus::Killed(SIGSEGV)),
+    };
+    // user code could have written anything there
+    if !is_user_address(context.rip) {
+        process::exit(ExitStatus::Killed(SIGSEGV));
+    }
+    if let Some(process) = process::current() {
+        process.signals().set_blocked(SigSet(context.mask));
+    }
+    let mut frame = TrapFrame::user(&SignalContext {
+        rflags: usermode::USER_RFLAGS | (context.rflags & USER_FLAGS),
+        ..context
+    });
+    process::on_return_to_user(&mut frame);
+    unsafe { trap::return_to_user(&frame) }
+}
+
+/// Delivers `signal` for an exception the calling thread raised in ring
+/// 3, even if it is blocked or ignored. Threads outside of any process
+/// exit instead. For trap handlers, after leaving their `HandlerContext`.
+pub(crate) fn fault(signal: u8, frame: &mut TrapFrame) {
+    // the kernel holds no locks while running user code
+    x86_64::instructions::interrupts::enable();
+    match process::current() {
+        Some(process) => {
+            process.signals().force(signal);
+            drop(process);
+            process::on_return_to_user(frame);
+        }
+        None => process::exit(ExitStatus::Killed(signal)),
+    }
+}
+
+#[test_case]
+fn test_take_goes_lowest_first() {
+    let signals = Signals::new();
+    signals.raise(SIGTERM);
+    signals.raise(SIGINT);
+    signals.set_blocked(SigSet::EMPTY.with(SIGINT).with(SIGKILL));
+    assert_eq!(signals.blocked(), SigSet::EMPTY.with(SIGINT));
+    assert_eq!(signals.take(), Some(SIGTERM));
+    assert_eq!(signals.take(), None);
+    assert!(!signals.has_deliverable());
+    signals.set_blocked(SigSet::EMPTY);
+    assert_eq!(signals.take(), Some(SIGINT));
+    assert_eq!(signals.pending(), SigSet::EMPTY);
+}
+
+#[test_case]
+fn test_ignored_signals_dont_interrupt() {
+    let signals = Signals::new();
+    signals.raise(SIGCHLD);
+    assert!(signals.has_deliverable());
+    assert!(!signals.has_interrupting());
+    let handler = SigAction { handler: usermode::USER_START, mask: 0, restorer: usermode::USER_START + 16 };
+    signals.set_action(SIGCHLD, handler.to_action().unwrap()).unwrap();
+    assert!(signals.has_interrupting());
+    signals.set_action(SIGCHLD, Action::Ignore).unwrap();
+    assert!(!signals.has_interrupting());
+    signals.raise(SIGTERM);
+    assert!(signals.has_interrupting());
+}
+
+#[test_case]
+fn test_actions() {
+    let signals = Signals::new();
+    assert_eq!(signals.set_action(SIGKILL, Action::Ignore), Err(ActionError::InvalidSignal));
+    assert_eq!(signals.set_action(NSIG, Action::Ignore), Err(ActionError::InvalidSignal));
+    let handler = SigAction { handler: usermode::USER_START, mask: 0, restorer: usermode::USER_START + 16 };
+    let action = handler.to_action().unwrap();
+    assert_eq!(signals.set_action(SIGINT, action), Ok(Action::Default));
+    assert_eq!(SigAction::from(signals.action(SIGINT)), handler);
+    let forked = signals.fork();
+    signals.reset_handlers();
+    assert_eq!(signals.action(SIGINT), Action::Default);
+    assert_eq!(forked.action(SIGINT), action);
+    assert!(SigAction { handler: 0x1000, ..handler }.to_action().is_err());
+}
@@ -0,0 +1,64 @@
+//! Softirqs: work an interrupt handler asks for but that runs later.
+//!
+//! Interrupt handlers only get to set a pending bit with `raise`. The bits
+//! are serviced by `run_pending`, from normal context with interrupts
+//! enabled, which is where anything that takes locks or allocates belongs.
+//! The kernel's idle loop (`crate::idle_loop`) calls it whenever an interrupt
+//! wakes it up.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Softirq {
+    /// Run expired `time::wheel` timers.
+    Timer,
+    /// Run work queued with `deferred::schedule`.
+    Work,
+}
+
+const ALL: [Softirq; 2] = [Softirq::Timer, Softirq::Work];
+
+/// Give up after this many rounds so a softirq that keeps re-raising itself
+/// can't starve everything else; the rest waits for the next call.
+const MAX_ROUNDS: usize = 8;
+
+static PENDING: AtomicU32 = AtomicU32::new(0);
+
+impl Softirq {
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    fn run(self) {
+        match self {
+            Softirq::Timer => crate::time::wheel::run_expired(),
+            Softirq::Work => crate::deferred::run_pending(),
+        }
+    }
+}
+
+/// Marks `softirq` as pending. Safe to call from interrupt handlers.
+pub fn raise(softirq: Softirq) {
+    PENDING.fetch_or(softirq.bit(), Ordering::Release);
+}
+
+pub fn is_pending() -> bool {
+    PENDING.load(Ordering::Acquire) != 0
+}
+
+/// Runs every pending softirq.
+///
+/// Must not be called from an interrupt handler or with locks held that a
+/// softirq might take.
+pub fn run_pending() {
+    for _ in 0..MAX_ROUNDS {
+        let pending = PENDING.swap(0, Ordering::AcqRel);
+        if pending == 0 {
+            return;
+        }
+        for softirq in ALL.iter().filter(|s| pending & s.bit() != 0) {
+            softirq.run();
+        }
+    }
+}
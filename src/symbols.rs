@@ -0,0 +1,148 @@
+//! The kernel's own symbol table, used to put names on backtrace addresses.
+//!
+//! The table lives in the `.ksyms` section. It starts out zeroed and is filled
+//! in after linking by `scripts/ksyms.py` (the cargo runner calls it before
+//! handing the binary to bootimage), so a kernel that wasn't post-processed
+//! simply has no symbols.
+//!
+//! Blob layout, all integers little endian:
+//!
+//! ```text
+//! 0x00  b"KSYM"
+//! 0x04  u32  number of entries
+//! 0x08  u32  offset of the string table from the start of the blob
+//! 0x0c  u32  reserved
+//! 0x10  entries, sorted by address: { addr: u64, size: u32, name: u32 }
+//! ....  string table: NUL terminated, already demangled names
+//! ```
+
+use core::convert::TryInto;
+use core::fmt;
+
+/// Size of the `.ksyms` section reserved in the kernel image.
+pub const CAPACITY: usize = 512 * 1024;
+
+const MAGIC: &[u8; 4] = b"KSYM";
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 16;
+
+// `static mut` + `no_mangle` keeps the compiler from const-folding reads of
+// the zeroed initializer: the real contents are only there after linking.
+#[no_mangle]
+#[used]
+#[link_section = ".ksyms"]
+static mut KSYMS: [u8; CAPACITY] = [0; CAPACITY];
+
+/// A resolved address: the enclosing function and how far into it we are.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub name: &'static str,
+    pub address: u64,
+    pub offset: u64,
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name, self.offset)
+    }
+}
+
+struct Table {
+    entries: &'static [u8],
+    strings: &'static [u8],
+}
+
+impl Table {
+    fn load() -> Option<Table> {
+        let blob = unsafe {
+            core::slice::from_raw_parts(core::ptr::addr_of!(KSYMS) as *const u8, CAPACITY)
+        };
+        if &blob[..4] != MAGIC {
+            return None;
+        }
+
+        let count = read_u32(blob, 4) as usize;
+        let strings_offset = read_u32(blob, 8) as usize;
+        let entries_end = count.checked_mul(ENTRY_SIZE)?.checked_add(HEADER_SIZE)?;
+        if entries_end > strings_offset || strings_offset > blob.len() {
+            return None;
+        }
+
+        Some(Table {
+            entries: &blob[HEADER_SIZE..entries_end],
+            strings: &blob[strings_offset..],
+        })
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len() / ENTRY_SIZE
+    }
+
+    /// Returns (address, size, name offset) of the `index`th entry.
+    fn entry(&self, index: usize) -> (u64, u32, u32) {
+        let entry = &self.entries[index * ENTRY_SIZE..(index + 1) * ENTRY_SIZE];
+        let address = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+        (address, read_u32(entry, 8), read_u32(entry, 12))
+    }
+
+    fn name(&self, offset: u32) -> &'static str {
+        let rest = match self.strings.get(offset as usize..) {
+            Some(rest) => rest,
+            None => return "<bad symbol>",
+        };
+        let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        core::str::from_utf8(&rest[..end]).unwrap_or("<bad symbol>")
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+/// Whether the post-link step filled in the symbol table.
+pub fn is_loaded() -> bool {
+    Table::load().is_some()
+}
+
+/// Finds the function containing `address`.
+pub fn lookup(address: u64) -> Option<Symbol> {
+    let table = Table::load()?;
+
+    // binary search for the last entry starting at or before `address`
+    let (mut low, mut high) = (0, table.len());
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if table.entry(mid).0 <= address {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    if low == 0 {
+        return None;
+    }
+
+    let (start, size, name) = table.entry(low - 1);
+    // symbols without a size (hand written asm) get the benefit of the doubt
+    if size != 0 && address >= start + u64::from(size) {
+        return None;
+    }
+
+    Some(Symbol {
+        name: table.name(name),
+        address: start,
+        offset: address - start,
+    })
+}
+
+#[test_case]
+fn test_lookup_own_function() {
+    if !is_loaded() {
+        return;
+    }
+    let address = lookup as fn(u64) -> Option<Symbol> as u64;
+    let symbol = lookup(address + 1).expect("lookup failed");
+    assert_eq!(symbol.address, address);
+    assert_eq!(symbol.offset, 1);
+    assert!(symbol.name.ends_with("symbols::lookup"));
+}
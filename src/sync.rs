@@ -0,0 +1,29 @@
+//! Synchronization primitives beyond `spin::Mutex`.
+//!
+//! The blocking ones (`Mutex`, `Semaphore`, `Condvar`, `RwLock`) put a
+//! contending thread to sleep on a `WaitQueue` instead of spinning. They need
+//! `thread::init` to have run; before that they fall back to spinning. Never
+//! block in an interrupt handler. The `*_interruptible` waits also give up
+//! when the calling process is killed or signalled.
+//!
+//! `IrqSpinLock` is for the other side: data shared with interrupt handlers.
+//! It spins with interrupts off and keeps them off while held. In debug
+//! builds, `lockdep` checks the order spinlocks are taken in, and that
+//! nothing blocks in a handler or under a spinlock.
+
+pub mod array_queue;
+pub mod condvar;
+pub mod irq_spin_lock;
+pub mod lockdep;
+pub mod mutex;
+pub mod rwlock;
+pub mod semaphore;
+pub mod wait_queue;
+
+pub use array_queue::ArrayQueue;
+pub use condvar::Condvar;
+pub use irq_spin_lock::{IrqSpinLock, IrqSpinLockGuard};
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::Semaphore;
+pub use wait_queue::{Interrupted, WaitQueue};
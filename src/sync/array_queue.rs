@@ -0,0 +1,178 @@
+//! Bounded lock-free multi-producer multi-consumer queue.
+//!
+//! This is Dmitry Vyukov's bounded queue: every slot carries a sequence
+//! number that says whose turn it is, so producers and consumers only ever
+//! race on a single compare-and-swap of the tail or head index. Nothing
+//! blocks, which makes it safe to push from an interrupt handler that
+//! interrupted a push or pop.
+//!
+//! The storage is inline and `new` is a `const fn`, so a queue can be a
+//! plain `static` that works before the heap is set up. The capacity `N`
+//! must be at least 2, or a full slot can't be told apart from a free one.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+struct Slot<T> {
+    /// The slot's sequence number minus its index, so that the initial
+    /// state (sequence == index) is all zeroes and can be built in a const.
+    stamp: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    const EMPTY: Slot<T> = Slot {
+        stamp: AtomicUsize::new(0),
+        value: UnsafeCell::new(MaybeUninit::uninit()),
+    };
+}
+
+pub struct ArrayQueue<T, const N: usize> {
+    slots: [Slot<T>; N],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+unsafe impl<T: Send, const N: usize> Sync for ArrayQueue<T, N> {}
+unsafe impl<T: Send, const N: usize> Send for ArrayQueue<T, N> {}
+
+impl<T, const N: usize> ArrayQueue<T, N> {
+    pub const fn new() -> Self {
+        assert!(N >= 2, "ArrayQueue needs a capacity of at least 2");
+        ArrayQueue {
+            slots: [Slot::<T>::EMPTY; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    fn sequence(&self, index: usize) -> usize {
+        self.slots[index].stamp.load(Ordering::Acquire).wrapping_add(index)
+    }
+
+    fn set_sequence(&self, index: usize, sequence: usize) {
+        self.slots[index].stamp.store(sequence.wrapping_sub(index), Ordering::Release);
+    }
+
+    /// Appends `value`, or hands it back if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let index = tail % N;
+            let sequence = self.sequence(index);
+            let lag = sequence.wrapping_sub(tail) as isize;
+
+            if lag == 0 {
+                // the slot is free for this lap; claim it
+                match self.tail.compare_exchange_weak(
+                    tail,
+                    tail.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*self.slots[index].value.get()).as_mut_ptr().write(value) };
+                        self.set_sequence(index, tail.wrapping_add(1));
+                        return Ok(());
+                    }
+                    Err(current) => tail = current,
+                }
+            } else if lag < 0 {
+                // still holds last lap's value: full
+                return Err(value);
+            } else {
+                tail = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Removes the oldest value, if any.
+    pub fn pop(&self) -> Option<T> {
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let index = head % N;
+            let sequence = self.sequence(index);
+            let lag = sequence.wrapping_sub(head.wrapping_add(1)) as isize;
+
+            if lag == 0 {
+                match self.head.compare_exchange_weak(
+                    head,
+                    head.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*self.slots[index].value.get()).as_ptr().read() };
+                        // free the slot for the producer one lap ahead
+                        self.set_sequence(index, head.wrapping_add(N));
+                        return Some(value);
+                    }
+                    Err(current) => head = current,
+                }
+            } else if lag < 0 {
+                // nothing written here yet: empty
+                return None;
+            } else {
+                head = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of values in the queue. Only a snapshot if other cores or
+    /// interrupt handlers are pushing or popping.
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Relaxed);
+        tail.wrapping_sub(head).min(N)
+    }
+}
+
+impl<T, const N: usize> Drop for ArrayQueue<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[test_case]
+fn test_fifo_order() {
+    let queue: ArrayQueue<u32, 4> = ArrayQueue::new();
+    for i in 0..3 {
+        queue.push(i).unwrap();
+    }
+    assert_eq!(queue.len(), 3);
+    assert_eq!(queue.pop(), Some(0));
+    queue.push(3).unwrap();
+    assert_eq!(queue.pop(), Some(1));
+    assert_eq!(queue.pop(), Some(2));
+    assert_eq!(queue.pop(), Some(3));
+    assert_eq!(queue.pop(), None);
+    assert!(queue.is_empty());
+}
+
+#[test_case]
+fn test_full() {
+    let queue: ArrayQueue<u8, 2> = ArrayQueue::new();
+    assert_eq!(queue.push(1), Ok(()));
+    assert_eq!(queue.push(2), Ok(()));
+    assert_eq!(queue.push(3), Err(3));
+    assert_eq!(queue.pop(), Some(1));
+    assert_eq!(queue.push(3), Ok(()));
+}
+
+#[test_case]
+fn test_wraps_around() {
+    let queue: ArrayQueue<usize, 3> = ArrayQueue::new();
+    for i in 0..100 {
+        queue.push(i).unwrap();
+        assert_eq!(queue.pop(), Some(i));
+    }
+}
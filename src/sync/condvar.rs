@@ -0,0 +1,84 @@
+//! Condition variables for use with `sync::Mutex`.
+
+use super::{lockdep, Interrupted, MutexGuard, WaitQueue};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub struct Condvar {
+    /// Bumped by every notification, so a waiter can tell it was notified
+    /// after it released the mutex.
+    generation: AtomicU64,
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            generation: AtomicU64::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Releases the mutex behind `guard`, sleeps until notified and locks the
+    /// mutex again. Like any condition variable it can wake up spuriously,
+    /// so check the condition in a loop, or use `wait_while`.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        lockdep::might_sleep("Condvar::wait");
+        let mutex = guard.mutex();
+        let generation = self.generation.load(Ordering::Acquire);
+        drop(guard);
+        self.waiters
+            .wait_until(|| self.generation.load(Ordering::Acquire) != generation);
+        mutex.lock()
+    }
+
+    /// Waits for as long as `condition` holds.
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Like `wait`, but gives up if the calling process is interrupted
+    /// first, see `WaitQueue::wait_until_interruptible`. The mutex stays
+    /// unlocked then.
+    pub fn wait_interruptible<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+    ) -> Result<MutexGuard<'a, T>, Interrupted> {
+        lockdep::might_sleep("Condvar::wait_interruptible");
+        let mutex = guard.mutex();
+        let generation = self.generation.load(Ordering::Acquire);
+        drop(guard);
+        self.waiters
+            .wait_until_interruptible(|| self.generation.load(Ordering::Acquire) != generation)?;
+        Ok(mutex.lock())
+    }
+
+    /// Like `wait_while`, but gives up if the calling process is interrupted
+    /// first.
+    pub fn wait_while_interruptible<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> Result<MutexGuard<'a, T>, Interrupted> {
+        while condition(&mut *guard) {
+            guard = self.wait_interruptible(guard)?;
+        }
+        Ok(guard)
+    }
+
+    pub fn notify_one(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        self.waiters.notify_all();
+    }
+}
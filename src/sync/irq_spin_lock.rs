@@ -0,0 +1,142 @@
+//! A spinlock that keeps interrupts off while it is held.
+//!
+//! With a plain spinlock, an interrupt handler that wants a lock the code it
+//! interrupted holds spins forever. Disabling interrupts for as long as the
+//! lock is held rules that out, so this is the lock for anything shared with
+//! an interrupt handler. The guard restores the interrupt flag to what it
+//! was, so guards nest.
+
+use super::lockdep;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::registers::rflags::{self, RFlags};
+
+pub struct IrqSpinLock<T: ?Sized> {
+    locked: AtomicBool,
+    class: lockdep::Class,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for IrqSpinLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for IrqSpinLock<T> {}
+
+pub struct IrqSpinLockGuard<'a, T: ?Sized> {
+    lock: &'a IrqSpinLock<T>,
+    /// RFLAGS from before the lock disabled interrupts.
+    saved_flags: RFlags,
+}
+
+impl<T> IrqSpinLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self::named("", data)
+    }
+
+    /// Like `new`, with a name for lockdep reports.
+    pub const fn named(name: &'static str, data: T) -> Self {
+        IrqSpinLock {
+            locked: AtomicBool::new(false),
+            class: lockdep::Class::new(name),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> IrqSpinLock<T> {
+    pub fn lock(&self) -> IrqSpinLockGuard<T> {
+        let saved_flags = rflags::read();
+        interrupts::disable();
+        lockdep::acquire(&self.class);
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        IrqSpinLockGuard { lock: self, saved_flags }
+    }
+
+    pub fn try_lock(&self) -> Option<IrqSpinLockGuard<T>> {
+        let saved_flags = rflags::read();
+        interrupts::disable();
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            lockdep::acquire(&self.class);
+            Some(IrqSpinLockGuard { lock: self, saved_flags })
+        } else {
+            if saved_flags.contains(RFlags::INTERRUPT_FLAG) {
+                interrupts::enable();
+            }
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    /// Unlocks the lock no matter who holds it.
+    ///
+    /// Only for error reporting paths that would otherwise deadlock on a lock
+    /// held by the code they interrupted.
+    pub unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+impl<T: ?Sized> Deref for IrqSpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for IrqSpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for IrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        lockdep::release(&self.lock.class);
+        if self.saved_flags.contains(RFlags::INTERRUPT_FLAG) {
+            interrupts::enable();
+        }
+    }
+}
+
+#[test_case]
+fn test_disables_interrupts_while_held() {
+    let lock = IrqSpinLock::new(0);
+    assert!(interrupts::are_enabled());
+    {
+        let mut guard = lock.lock();
+        assert!(!interrupts::are_enabled());
+        assert!(lock.try_lock().is_none());
+        assert!(!interrupts::are_enabled());
+        *guard += 1;
+    }
+    assert!(interrupts::are_enabled());
+    assert_eq!(*lock.lock(), 1);
+}
+
+#[test_case]
+fn test_nested_guards_restore_in_order() {
+    let outer = IrqSpinLock::new(());
+    let inner = IrqSpinLock::new(());
+    let outer_guard = outer.lock();
+    let inner_guard = inner.lock();
+    drop(inner_guard);
+    // still inside the outer lock
+    assert!(!interrupts::are_enabled());
+    drop(outer_guard);
+    assert!(interrupts::are_enabled());
+}
@@ -0,0 +1,243 @@
+//! Lock dependency checker, in debug builds.
+//!
+//! Every `IrqSpinLock` gets a class the first time it is taken. Taking a
+//! lock while holding others records that it was taken after them; if the
+//! recorded order ever says the opposite, some path takes the same locks in
+//! the reverse order and can deadlock, which is reported along with a
+//! backtrace. So are taking a lock that is already held, and anything that
+//! may sleep (`might_sleep`) in an interrupt handler or under a spinlock.
+//!
+//! Only the first problem is reported, then the checker turns itself off,
+//! like Linux's lockdep. Spinlocks are held with interrupts off and never
+//! across a thread switch, so one stack of held locks is enough. There are
+//! only `MAX_CLASSES` classes; locks beyond that go unchecked.
+
+use crate::{backtrace, serial, serial_println};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+
+const MAX_CLASSES: usize = 64;
+const MAX_HELD: usize = 16;
+
+/// `Class::id` before the first acquire.
+const UNASSIGNED: usize = 0;
+/// `Class::id` once we ran out of classes.
+const UNTRACKED: usize = usize::MAX;
+
+pub struct Class {
+    /// Index into the tables plus one, or one of the markers above.
+    id: AtomicUsize,
+    name: &'static str,
+}
+
+impl Class {
+    pub const fn new(name: &'static str) -> Self {
+        Class {
+            id: AtomicUsize::new(UNASSIGNED),
+            name,
+        }
+    }
+
+    fn index(&self) -> Option<usize> {
+        match self.id.load(Ordering::Relaxed) {
+            UNASSIGNED => {
+                let index = NEXT_CLASS.fetch_add(1, Ordering::Relaxed);
+                if index >= MAX_CLASSES {
+                    self.id.store(UNTRACKED, Ordering::Relaxed);
+                    return None;
+                }
+                NAMES.lock()[index] = self.name;
+                self.id.store(index + 1, Ordering::Relaxed);
+                Some(index)
+            }
+            UNTRACKED => None,
+            id => Some(id - 1),
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(cfg!(debug_assertions));
+static REPORTS: AtomicU64 = AtomicU64::new(0);
+
+static NEXT_CLASS: AtomicUsize = AtomicUsize::new(0);
+static NAMES: spin::Mutex<[&str; MAX_CLASSES]> = spin::Mutex::new([""; MAX_CLASSES]);
+/// Bit `j` of `AFTER[i]`: class `j` was taken while class `i` was held.
+static AFTER: [AtomicU64; MAX_CLASSES] = {
+    const NONE: AtomicU64 = AtomicU64::new(0);
+    [NONE; MAX_CLASSES]
+};
+
+static HELD: [AtomicUsize; MAX_HELD] = {
+    const NONE: AtomicUsize = AtomicUsize::new(0);
+    [NONE; MAX_HELD]
+};
+static HELD_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of problems reported since boot.
+pub fn reports() -> u64 {
+    REPORTS.load(Ordering::Relaxed)
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn held() -> impl Iterator<Item = usize> {
+    let len = HELD_LEN.load(Ordering::Relaxed).min(MAX_HELD);
+    (0..len).map(|i| HELD[i].load(Ordering::Relaxed))
+}
+
+/// Whether `to` was ever taken after `from`, directly or through others.
+fn reachable(from: usize, to: usize) -> bool {
+    let mut visited = 0u64;
+    let mut frontier = 1u64 << from;
+    while frontier != 0 {
+        if frontier & (1 << to) != 0 {
+            return true;
+        }
+        visited |= frontier;
+        let mut next = 0;
+        for class in (0..MAX_CLASSES).filter(|class| frontier & (1 << class) != 0) {
+            next |= AFTER[class].load(Ordering::Relaxed);
+        }
+        frontier = next & !visited;
+    }
+    false
+}
+
+fn name(index: usize) -> &'static str {
+    match NAMES.lock()[index] {
+        "" => "<unnamed>",
+        name => name,
+    }
+}
+
+/// Prints a report, unless one was printed already.
+fn report(args: core::fmt::Arguments) {
+    if !ENABLED.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    REPORTS.fetch_add(1, Ordering::Relaxed);
+    interrupts::without_interrupts(|| {
+        // we may well be holding the serial port lock ourselves
+        unsafe { serial::force_unlock() };
+        serial_println!("lockdep: {}", args);
+        backtrace::serial_print();
+    });
+}
+
+/// Called by a spinlock right before it spins for `class`. Interrupts must
+/// be off.
+pub fn acquire(class: &Class) {
+    if !is_enabled() {
+        return;
+    }
+    let index = match class.index() {
+        Some(index) => index,
+        None => return,
+    };
+
+    for held in held() {
+        if held == index {
+            report(format_args!("{} taken while already held, this deadlocks", name(index)));
+            return;
+        }
+        if reachable(index, held) {
+            report(format_args!(
+                "{} taken while holding {}, but elsewhere it was taken the other way round",
+                name(index),
+                name(held)
+            ));
+            return;
+        }
+    }
+    for held in held() {
+        AFTER[held].fetch_or(1 << index, Ordering::Relaxed);
+    }
+
+    let len = HELD_LEN.load(Ordering::Relaxed);
+    if len < MAX_HELD {
+        HELD[len].store(index, Ordering::Relaxed);
+    }
+    HELD_LEN.store(len + 1, Ordering::Relaxed);
+}
+
+/// Called by a spinlock when it is unlocked.
+pub fn release(class: &Class) {
+    let index = match class.id.load(Ordering::Relaxed) {
+        UNASSIGNED | UNTRACKED => return,
+        id => id - 1,
+    };
+    let len = HELD_LEN.load(Ordering::Relaxed);
+    if len == 0 {
+        return;
+    }
+    if len > MAX_HELD {
+        // the overflowing ones weren't recorded; assume it's one of those
+        HELD_LEN.store(len - 1, Ordering::Relaxed);
+        return;
+    }
+    // usually the last one, but locks may be released in any order
+    if let Some(position) = (0..len).rev().find(|&i| HELD[i].load(Ordering::Relaxed) == index) {
+        for i in position..len - 1 {
+            HELD[i].store(HELD[i + 1].load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        HELD_LEN.store(len - 1, Ordering::Relaxed);
+    }
+}
+
+/// Called by anything that may put the calling thread to sleep.
+pub fn might_sleep(what: &str) {
+    if !is_enabled() {
+        return;
+    }
+    if crate::interrupts::in_interrupt() {
+        report(format_args!("{} may sleep, but was called in an interrupt handler", what));
+    } else if let Some(held) = interrupts::without_interrupts(|| held().last()) {
+        report(format_args!("{} may sleep, but was called holding spinlock {}", what, name(held)));
+    }
+}
+
+#[cfg(test)]
+fn reset() {
+    interrupts::without_interrupts(|| {
+        for after in AFTER.iter() {
+            after.store(0, Ordering::Relaxed);
+        }
+        HELD_LEN.store(0, Ordering::Relaxed);
+        ENABLED.store(true, Ordering::Relaxed);
+    });
+}
+
+#[test_case]
+fn test_detects_inversion() {
+    use super::IrqSpinLock;
+
+    reset();
+    let a = IrqSpinLock::named("test a", ());
+    let b = IrqSpinLock::named("test b", ());
+    let before = reports();
+    {
+        let _a = a.lock();
+        let _b = b.lock();
+    }
+    assert_eq!(reports(), before);
+    {
+        let _b = b.lock();
+        let _a = a.lock();
+    }
+    assert_eq!(reports(), before + 1);
+    reset();
+}
+
+#[test_case]
+fn test_detects_sleeping_in_handler() {
+    reset();
+    let before = reports();
+    {
+        let _context = crate::interrupts::HandlerContext::enter();
+        might_sleep("test");
+    }
+    assert_eq!(reports(), before + 1);
+    reset();
+}
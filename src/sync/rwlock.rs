@@ -0,0 +1,150 @@
+//! A reader-writer lock that puts contending threads to sleep.
+//!
+//! Any number of readers or one writer. There is no writer preference, so a
+//! steady stream of readers can starve writers.
+
+use super::{lockdep, WaitQueue};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// `state` value while write locked; otherwise it is the number of readers.
+const WRITER: usize = usize::MAX;
+
+pub struct RwLock<T: ?Sized> {
+    state: AtomicUsize,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        RwLock {
+            state: AtomicUsize::new(0),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        lockdep::might_sleep("RwLock::read");
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            self.waiters.wait_until(|| self.state.load(Ordering::Relaxed) != WRITER);
+        }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        self.state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |state| {
+                if state == WRITER || state == WRITER - 1 {
+                    None
+                } else {
+                    Some(state + 1)
+                }
+            })
+            .ok()
+            .map(|_| RwLockReadGuard { lock: self })
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        lockdep::might_sleep("RwLock::write");
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            self.waiters.wait_until(|| self.state.load(Ordering::Relaxed) == 0);
+        }
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+
+    /// Number of readers holding the lock, or `None` if a writer does.
+    pub fn readers(&self) -> Option<usize> {
+        match self.state.load(Ordering::Relaxed) {
+            WRITER => None,
+            readers => Some(readers),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.lock.state.fetch_sub(1, Ordering::Release) == 1 {
+            // last reader out: a writer may be waiting
+            self.lock.waiters.notify_all();
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+        self.lock.waiters.notify_all();
+    }
+}
+
+#[test_case]
+fn test_readers_and_writers() {
+    let lock = RwLock::new(1);
+    {
+        let a = lock.read();
+        let b = lock.read();
+        assert_eq!(lock.readers(), Some(2));
+        assert!(lock.try_write().is_none());
+        assert_eq!(*a + *b, 2);
+    }
+    {
+        let mut writer = lock.write();
+        *writer = 5;
+        assert_eq!(lock.readers(), None);
+        assert!(lock.try_read().is_none());
+    }
+    assert_eq!(*lock.read(), 5);
+}
@@ -0,0 +1,57 @@
+//! A counting semaphore.
+
+use super::{lockdep, WaitQueue};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Takes a permit, sleeping until one is available.
+    pub fn acquire(&self) {
+        lockdep::might_sleep("Semaphore::acquire");
+        while !self.try_acquire() {
+            self.waiters.wait_until(|| self.permits.load(Ordering::Relaxed) > 0);
+        }
+    }
+
+    /// Takes a permit if one is available.
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| permits.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Returns a permit. Safe to call from interrupt handlers, which makes a
+    /// semaphore the way for one to wake a thread.
+    pub fn release(&self) {
+        self.permits.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    /// Permits available right now.
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
+
+#[test_case]
+fn test_permits() {
+    let semaphore = Semaphore::new(2);
+    assert!(semaphore.try_acquire());
+    assert!(semaphore.try_acquire());
+    assert!(!semaphore.try_acquire());
+    semaphore.release();
+    assert_eq!(semaphore.available(), 1);
+    semaphore.acquire();
+    assert_eq!(semaphore.available(), 0);
+}
@@ -0,0 +1,116 @@
+//! Threads waiting for a condition to become true.
+
+use super::lockdep;
+use crate::process;
+use crate::thread::{self, ThreadList};
+use x86_64::instructions::interrupts;
+
+/// Returned by interruptible waits that gave up because the calling process
+/// was killed, or got a signal it handles or dies of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted;
+
+pub struct WaitQueue {
+    waiters: spin::Mutex<ThreadList>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: spin::Mutex::new(ThreadList::new()),
+        }
+    }
+
+    /// Blocks the calling thread until `condition` returns true.
+    ///
+    /// `condition` is checked with interrupts off and the queue locked, so a
+    /// `notify_*` that follows making it true can't slip in between the
+    /// check and going to sleep. It is checked again after every wakeup.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        lockdep::might_sleep("WaitQueue::wait_until");
+        loop {
+            let done = interrupts::without_interrupts(|| {
+                let waiters = self.waiters.lock();
+                if condition() {
+                    return true;
+                }
+                if thread::is_initialized() {
+                    thread::block(waiters, |mut waiters, thread| waiters.push(thread));
+                }
+                false
+            });
+            if done {
+                return;
+            }
+            if !thread::is_initialized() {
+                core::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Like `wait_until`, but also returns, with `Err(Interrupted)`, once
+    /// the calling process is exiting or has a signal pending that will
+    /// interrupt it. `process::kill` wakes the thread for that. Threads
+    /// outside processes can't be interrupted.
+    pub fn wait_until_interruptible(
+        &self,
+        mut condition: impl FnMut() -> bool,
+    ) -> Result<(), Interrupted> {
+        let process = match process::current() {
+            Some(process) => process,
+            None => {
+                self.wait_until(condition);
+                return Ok(());
+            }
+        };
+        let _waiting = process.wait_on(self);
+        let mut interrupted = false;
+        self.wait_until(|| {
+            if condition() {
+                return true;
+            }
+            interrupted = process.is_interrupted();
+            interrupted
+        });
+        if interrupted {
+            Err(Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wakes the thread that has been waiting longest. Returns whether there
+    /// was one. Safe to call from interrupt handlers.
+    pub fn notify_one(&self) -> bool {
+        interrupts::without_interrupts(|| {
+            let waiter = self.waiters.lock().pop();
+            match waiter {
+                Some(waiter) => {
+                    thread::wake(waiter);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    /// Wakes every waiting thread and returns how many there were.
+    pub fn notify_all(&self) -> usize {
+        interrupts::without_interrupts(|| {
+            let mut woken = 0;
+            while self.notify_one() {
+                woken += 1;
+            }
+            woken
+        })
+    }
+
+    /// Number of threads waiting.
+    pub fn len(&self) -> usize {
+        interrupts::without_interrupts(|| self.waiters.lock().len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,787 @@
+//! System calls, entered from ring 3 with the `syscall` instruction.
+//!
+//! The call number goes in rax and up to six arguments in rdi, rsi, rdx,
+//! r10, r8 and r9, as on Linux. The result comes back in rax, with failures
+//! as small negative numbers (see `Error`). `syscall` itself clobbers rcx
+//! and r11; every other register is preserved.
+//!
+//! The CPU doesn't switch stacks on `syscall`, so the entry stub does: it
+//! moves to the running thread's kernel stack, the one `gdt::set_kernel_stack`
+//! last set, and saves the user's registers there as a `SyscallFrame`. The
+//! handler then runs with interrupts enabled, so system calls can block and
+//! be preempted like any other kernel code. There is only one CPU, so the
+//! stub may park the user stack pointer in a static while interrupts are
+//! still masked.
+//!
+//! Calls that wait, for input, a message, a child or time to pass, fail with
+//! `Error::Interrupted` if the process is killed or gets a signal it handles
+//! or dies of meanwhile.
+//!
+//! Pointers and lengths from user code are never trusted: a buffer must lie
+//! in the user range and be mapped for user access, or the call fails with
+//! `Error::BadAddress`. A buffer the kernel writes to is copied out of any
+//! copy-on-write sharing first, so the kernel itself never faults on one.
+
+use crate::channel::{self, Message, RawMessage};
+use crate::file::{File, FileTable};
+use crate::memory::{self, address_space};
+use crate::loader::LoadError;
+use crate::process::{self, ExecError, ExitStatus, ForkError, Pid, WaitError};
+use crate::signal::{self, ActionError, SigAction, SigSet, SignalContext, UserRegisters};
+use crate::sync::Interrupted;
+use crate::{gdt, pipe, thread, usermode};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::arch::{asm, global_asm};
+use core::convert::TryFrom;
+use core::mem;
+use core::time::Duration;
+use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+/// System call numbers. These are ABI: never renumber, only append.
+pub mod number {
+    /// `write(fd, buf, len) -> written`
+    pub const WRITE: u64 = 0;
+    /// `read(fd, buf, len) -> read`
+    pub const READ: u64 = 1;
+    /// `exit(status) -> !`
+    pub const EXIT: u64 = 2;
+    /// `yield() -> 0`
+    pub const YIELD: u64 = 3;
+    /// `mmap(addr, len, flags) -> addr`
+    pub const MMAP: u64 = 4;
+    /// `getpid() -> pid`
+    pub const GETPID: u64 = 5;
+    /// `sleep(milliseconds) -> 0`
+    pub const SLEEP: u64 = 6;
+    /// `wait(pid or -1, status) -> pid`
+    pub const WAIT: u64 = 7;
+    /// `fork() -> child pid, or 0 in the child`
+    pub const FORK: u64 = 8;
+    /// `exec(path, argv, envp) -> !`, with null-terminated strings and
+    /// null-terminated arrays of them
+    pub const EXEC: u64 = 9;
+    /// `sigaction(signal, action, old action) -> 0`, with either pointer to
+    /// a `signal::SigAction` or null
+    pub const SIGACTION: u64 = 10;
+    /// `sigprocmask(how, set, old set) -> 0`, with either pointer to a
+    /// 64-bit mask or null
+    pub const SIGPROCMASK: u64 = 11;
+    /// `kill(pid, signal) -> 0`
+    pub const KILL: u64 = 12;
+    /// `sigreturn() -> !`, only from a signal handler's restorer
+    pub const SIGRETURN: u64 = 13;
+    /// `pipe(fds) -> 0`, storing the read end's fd and then the write
+    /// end's as two 32-bit numbers at `fds`
+    pub const PIPE: u64 = 14;
+    /// `close(fd) -> 0`
+    pub const CLOSE: u64 = 15;
+    /// `dup2(fd, new fd) -> new fd`, closing whatever `new fd` was first
+    pub const DUP2: u64 = 16;
+    /// `channel(fds) -> 0`, storing the two endpoints' fds like `pipe`
+    pub const CHANNEL: u64 = 17;
+    /// `send(fd, message) -> 0`, with a pointer to a `channel::RawMessage`
+    /// whose handle is duplicated for the receiver
+    pub const SEND: u64 = 18;
+    /// `recv(fd, message, flags) -> 0`, filling in a `channel::RawMessage`
+    /// with any handle opened as a new fd
+    pub const RECV: u64 = 19;
+}
+
+/// `mmap` flag: make the pages writable.
+pub const MMAP_WRITE: u64 = 1 << 0;
+
+/// `recv` flag: fail with `Error::TryAgain` rather than wait for a message.
+pub const RECV_NONBLOCK: u64 = 1 << 0;
+
+/// `sigprocmask` how: block the signals in the set as well.
+pub const SIG_BLOCK: u64 = 0;
+/// `sigprocmask` how: unblock the signals in the set.
+pub const SIG_UNBLOCK: u64 = 1;
+/// `sigprocmask` how: block exactly the signals in the set.
+pub const SIG_SETMASK: u64 = 2;
+
+/// Why a system call failed. User code sees the negated Linux errno.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Error {
+    NoSuchFile = -2,
+    NoSuchProcess = -3,
+    /// A signal arrived before a blocking call could finish.
+    Interrupted = -4,
+    ArgumentListTooLong = -7,
+    NotExecutable = -8,
+    BadFileDescriptor = -9,
+    NoChildProcess = -10,
+    TryAgain = -11,
+    OutOfMemory = -12,
+    BadAddress = -14,
+    InvalidArgument = -22,
+    TooManyFiles = -24,
+    /// Writing to a pipe nobody reads any more.
+    BrokenPipe = -32,
+    NoSuchSystemCall = -38,
+}
+
+impl Error {
+    /// The value returned in rax.
+    pub fn code(self) -> i64 {
+        self as i64
+    }
+}
+
+/// The user's registers, as saved by the entry stub, lowest address first.
+///
+/// The callee-saved ones would survive the handler anyway, but a forked
+/// child needs all of them to return to user mode from a system call it
+/// didn't make.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C)]
+pub struct SyscallFrame {
+    /// Call number on entry, result on exit.
+    pub rax: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub r10: u64,
+    pub r8: u64,
+    pub r9: u64,
+    /// Where to return to in user code.
+    pub rcx: u64,
+    /// The user's RFLAGS.
+    pub r11: u64,
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    /// The user's stack pointer.
+    pub rsp: u64,
+}
+
+impl SyscallFrame {
+    fn args(&self) -> [u64; 6] {
+        [self.rdi, self.rsi, self.rdx, self.r10, self.r8, self.r9]
+    }
+}
+
+// sysret takes rip and rflags from rcx and r11, so the user's own rcx and
+// r11 don't survive a system call
+impl UserRegisters for SyscallFrame {
+    fn context(&self) -> SignalContext {
+        SignalContext {
+            r8: self.r8,
+            r9: self.r9,
+            r10: self.r10,
+            r11: self.r11,
+            r12: self.r12,
+            r13: self.r13,
+            r14: self.r14,
+            r15: self.r15,
+            rdi: self.rdi,
+            rsi: self.rsi,
+            rbp: self.rbp,
+            rbx: self.rbx,
+            rdx: self.rdx,
+            rax: self.rax,
+            rcx: self.rcx,
+            rsp: self.rsp,
+            rip: self.rcx,
+            rflags: self.r11,
+            mask: 0,
+        }
+    }
+
+    fn set_context(&mut self, context: &SignalContext) {
+        *self = SyscallFrame {
+            rax: context.rax,
+            rdi: context.rdi,
+            rsi: context.rsi,
+            rdx: context.rdx,
+            r10: context.r10,
+            r8: context.r8,
+            r9: context.r9,
+            rcx: context.rip,
+            r11: context.rflags,
+            r15: context.r15,
+            r14: context.r14,
+            r13: context.r13,
+            r12: context.r12,
+            rbp: context.rbp,
+            rbx: context.rbx,
+            rsp: context.rsp,
+        };
+    }
+}
+
+type Handler = fn(&mut SyscallFrame) -> Result<u64, Error>;
+
+/// Indexed by call number.
+static TABLE: [Handler; 20] = [
+    sys_write,
+    sys_read,
+    sys_exit,
+    sys_yield,
+    sys_mmap,
+    sys_getpid,
+    sys_sleep,
+    sys_wait,
+    sys_fork,
+    sys_exec,
+    sys_sigaction,
+    sys_sigprocmask,
+    sys_kill,
+    sys_sigreturn,
+    sys_pipe,
+    sys_close,
+    sys_dup2,
+    sys_channel,
+    sys_send,
+    sys_recv,
+];
+
+lazy_static! {
+    /// The files of threads that don't belong to a process.
+    static ref CONSOLE: FileTable = FileTable::with_console();
+}
+
+// clears interrupts until the kernel stack is set up (through SFMASK), and
+// restores the user's RFLAGS from r11 on the way out
+global_asm!(
+    ".global mooos_syscall_entry",
+    "mooos_syscall_entry:",
+    "mov [rip + MOOOS_SYSCALL_USER_RSP], rsp",
+    "mov rsp, [rip + MOOOS_KERNEL_STACK_TOP]",
+    "push qword ptr [rip + MOOOS_SYSCALL_USER_RSP]",
+    "push rbx",
+    "push rbp",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "push r11",
+    "push rcx",
+    "push r9",
+    "push r8",
+    "push r10",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rax",
+    "mov rdi, rsp",
+    "call mooos_syscall_dispatch",
+    // `return_to_user` comes in here, with rsp at a frame of its own
+    ".global mooos_syscall_return",
+    "mooos_syscall_return:",
+    "pop rax",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop r10",
+    "pop r8",
+    "pop r9",
+    "pop rcx",
+    "pop r11",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbp",
+    "pop rbx",
+    "pop rsp",
+    "sysretq",
+);
+
+extern "C" {
+    fn mooos_syscall_entry();
+}
+
+/// Scratch space for the entry stub, only used with interrupts masked.
+#[no_mangle]
+static mut MOOOS_SYSCALL_USER_RSP: u64 = 0;
+
+/// Points `syscall` at the entry stub. Call after `gdt::init`.
+pub fn init() {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code,
+        selectors.user_data,
+        selectors.kernel_code,
+        selectors.kernel_data,
+    )
+    .expect("GDT layout doesn't suit syscall/sysret");
+    LStar::write(VirtAddr::new(mooos_syscall_entry as usize as u64));
+    // NT too, as an iretq in the kernel faults while it's set
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::NESTED_TASK,
+    );
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+#[no_mangle]
+extern "C" fn mooos_syscall_dispatch(frame: &mut SyscallFrame) {
+    interrupts::enable();
+    let result = match TABLE.get(frame.rax as usize) {
+        Some(handler) => handler(frame),
+        None => Err(Error::NoSuchSystemCall),
+    };
+    frame.rax = match result {
+        Ok(value) => value,
+        Err(err) => err.code() as u64,
+    };
+    process::on_return_to_user(frame);
+    // the stub is back on a stack it found in a static
+    interrupts::disable();
+}
+
+/// Leaves the kernel for user code with the registers in `frame`, the way a
+/// system call returns.
+unsafe fn return_to_user(frame: &SyscallFrame) -> ! {
+    // the stub switches to the user stack before sysret
+    interrupts::disable();
+    asm!(
+        "mov rsp, {frame}",
+        "jmp mooos_syscall_return",
+        frame = in(reg) frame,
+        options(noreturn),
+    );
+}
+
+/// The user memory at `[ptr, ptr + len)`, if user code may read all of it.
+fn user_slice<'a>(ptr: u64, len: u64) -> Result<&'a [u8], Error> {
+    let start = check_user_range(ptr, len, false)?;
+    Ok(unsafe { core::slice::from_raw_parts(start.as_ptr(), len as usize) })
+}
+
+/// The user memory at `[ptr, ptr + len)`, if user code may write all of it.
+fn user_slice_mut<'a>(ptr: u64, len: u64) -> Result<&'a mut [u8], Error> {
+    let start = check_user_range(ptr, len, true)?;
+    Ok(unsafe { core::slice::from_raw_parts_mut(start.as_mut_ptr(), len as usize) })
+}
+
+fn check_user_range(ptr: u64, len: u64, write: bool) -> Result<VirtAddr, Error> {
+    let start = VirtAddr::try_new(ptr).map_err(|_| Error::BadAddress)?;
+    if len == 0 {
+        return Ok(start);
+    }
+    if !usermode::is_user_range(start, len) {
+        return Err(Error::BadAddress);
+    }
+    if write {
+        if let Some(address_space) = thread::address_space() {
+            let first = Page::<Size4KiB>::containing_address(start);
+            let last = Page::<Size4KiB>::containing_address(start + (len - 1));
+            for page in Page::range_inclusive(first, last) {
+                address_space
+                    .copy_on_write(page.start_address())
+                    .map_err(|_| Error::OutOfMemory)?;
+            }
+        }
+    }
+    if !memory::user_accessible(start, len, write) {
+        return Err(Error::BadAddress);
+    }
+    Ok(start)
+}
+
+/// Longest string `user_string` accepts, terminator included.
+const MAX_STRING: u64 = 4096;
+/// Most strings `user_strings` accepts.
+const MAX_STRINGS: u64 = 256;
+
+/// Copies in the `T` at `ptr` in user memory.
+///
+/// # Safety
+///
+/// Any bit pattern must be a valid `T`.
+pub(crate) unsafe fn read_user<T: Copy>(ptr: u64) -> Result<T, Error> {
+    let bytes = user_slice(ptr, mem::size_of::<T>() as u64)?;
+    Ok((bytes.as_ptr() as *const T).read_unaligned())
+}
+
+/// Copies `value` out to `ptr` in user memory.
+pub(crate) fn write_user<T: Copy>(ptr: u64, value: T) -> Result<(), Error> {
+    let bytes = user_slice_mut(ptr, mem::size_of::<T>() as u64)?;
+    unsafe { (bytes.as_mut_ptr() as *mut T).write_unaligned(value) };
+    Ok(())
+}
+
+/// Copies in the null-terminated string at `ptr`.
+fn user_string(ptr: u64) -> Result<String, Error> {
+    let mut bytes = Vec::new();
+    let mut at = ptr;
+    loop {
+        // a page at a time, since the string may end anywhere
+        let chunk = user_slice(at, 4096 - at % 4096)?;
+        match chunk.iter().position(|&byte| byte == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&chunk[..end]);
+                break;
+            }
+            None => bytes.extend_from_slice(chunk),
+        }
+        if bytes.len() as u64 >= MAX_STRING {
+            return Err(Error::ArgumentListTooLong);
+        }
+        at += chunk.len() as u64;
+    }
+    String::from_utf8(bytes).map_err(|_| Error::InvalidArgument)
+}
+
+/// Copies in the strings the null-terminated array at `ptr` points to. A
+/// null `ptr` is an empty array.
+fn user_strings(ptr: u64) -> Result<Vec<String>, Error> {
+    let mut strings = Vec::new();
+    if ptr == 0 {
+        return Ok(strings);
+    }
+    for i in 0..MAX_STRINGS {
+        let entry = user_slice(ptr + 8 * i, 8)?;
+        let mut pointer = [0; 8];
+        pointer.copy_from_slice(entry);
+        match u64::from_le_bytes(pointer) {
+            0 => return Ok(strings),
+            string => strings.push(user_string(string)?),
+        }
+    }
+    Err(Error::ArgumentListTooLong)
+}
+
+/// Open file `fd` of the calling process.
+fn file(fd: u64) -> Result<Arc<dyn File>, Error> {
+    match process::current() {
+        Some(process) => process.files().get(fd),
+        None => CONSOLE.get(fd),
+    }
+}
+
+fn sys_write(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [fd, buf, len, ..] = frame.args();
+    let file = file(fd)?;
+    let written = file.write(user_slice(buf, len)?)?;
+    Ok(written as u64)
+}
+
+fn sys_read(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [fd, buf, len, ..] = frame.args();
+    let file = file(fd)?;
+    let read = file.read(user_slice_mut(buf, len)?)?;
+    Ok(read as u64)
+}
+
+fn sys_exit(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    process::exit(ExitStatus::Exited(frame.rdi as u8))
+}
+
+fn sys_yield(_frame: &mut SyscallFrame) -> Result<u64, Error> {
+    thread::yield_now();
+    Ok(0)
+}
+
+fn sys_mmap(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [addr, len, flags, ..] = frame.args();
+    if addr % 4096 != 0 || len == 0 || flags & !MMAP_WRITE != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    let start = VirtAddr::try_new(addr).map_err(|_| Error::InvalidArgument)?;
+    if !usermode::is_user_range(start, len) {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut page_flags = PageTableFlags::empty();
+    if flags & MMAP_WRITE != 0 {
+        page_flags |= PageTableFlags::WRITABLE;
+    }
+    // threads without an address space of their own run in the kernel's
+    let mapped = match thread::address_space() {
+        Some(address_space) => address_space.map_user(start, len, page_flags),
+        None => memory::with_global(|mapper, frame_allocator| {
+            usermode::map_user(start, len, page_flags, mapper, frame_allocator)
+                .map_err(address_space::Error::from)
+        })
+        .unwrap_or(Err(address_space::Error::OutOfMemory)),
+    };
+    match mapped {
+        Ok(()) => Ok(addr),
+        Err(address_space::Error::OutOfMemory) => Err(Error::OutOfMemory),
+        Err(_) => Err(Error::InvalidArgument),
+    }
+}
+
+fn sys_getpid(_frame: &mut SyscallFrame) -> Result<u64, Error> {
+    // threads outside of any process get their thread id
+    match process::current() {
+        Some(process) => Ok(process.pid().as_u64()),
+        None => Ok(thread::current().as_u64()),
+    }
+}
+
+/// Longest `sleep`, about 49 days; asking for more sleeps this long.
+const MAX_SLEEP_MS: u64 = u32::MAX as u64;
+
+fn sys_sleep(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    thread::sleep_interruptible(Duration::from_millis(frame.rdi.min(MAX_SLEEP_MS)))
+        .map_err(|Interrupted| Error::Interrupted)?;
+    Ok(0)
+}
+
+fn sys_wait(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [pid, status, ..] = frame.args();
+    let pid = match pid as i64 {
+        -1 => None,
+        pid if pid > 0 => Some(Pid::from_u64(pid as u64)),
+        _ => return Err(Error::InvalidArgument),
+    };
+    // check before reaping, or the status would be lost
+    if status != 0 {
+        user_slice_mut(status, 4)?;
+    }
+    let (child, exit_status) = process::waitpid(pid).map_err(|err| match err {
+        WaitError::NoChildren => Error::NoChildProcess,
+        WaitError::Interrupted => Error::Interrupted,
+    })?;
+    if status != 0 {
+        user_slice_mut(status, 4)?.copy_from_slice(&exit_status.code().to_le_bytes());
+    }
+    Ok(child.as_u64())
+}
+
+fn sys_fork(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let mut child = *frame;
+    child.rax = 0;
+    let pid = process::fork(move || {
+        let frame = child;
+        unsafe { return_to_user(&frame) }
+    })
+    .map_err(|err| match err {
+        ForkError::NotAProcess => Error::InvalidArgument,
+        ForkError::OutOfMemory => Error::OutOfMemory,
+        ForkError::TooManyThreads => Error::TryAgain,
+    })?;
+    Ok(pid.as_u64())
+}
+
+fn sys_exec(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [path, argv, envp, ..] = frame.args();
+    let path = user_string(path)?;
+    let argv = user_strings(argv)?;
+    let envp = user_strings(envp)?;
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+    let (entry, stack_pointer) = process::exec(&path, &argv, &envp).map_err(|err| match err {
+        ExecError::NotAProcess => Error::InvalidArgument,
+        ExecError::NotFound => Error::NoSuchFile,
+        ExecError::Load(LoadError::Elf(_)) => Error::NotExecutable,
+        ExecError::Load(LoadError::ArgumentsTooLong) => Error::ArgumentListTooLong,
+        ExecError::Load(LoadError::OutOfMemory) => Error::OutOfMemory,
+        ExecError::Load(_) => Error::NotExecutable,
+    })?;
+    // start the new program with a clean slate
+    *frame = SyscallFrame {
+        rcx: entry.as_u64(),
+        r11: usermode::USER_RFLAGS,
+        rsp: stack_pointer.as_u64(),
+        ..SyscallFrame::default()
+    };
+    Ok(0)
+}
+
+/// `signal` if it's a valid signal number.
+fn signal_number(signal: u64) -> Result<u8, Error> {
+    u8::try_from(signal)
+        .ok()
+        .filter(|&signal| signal::is_valid(signal))
+        .ok_or(Error::InvalidArgument)
+}
+
+fn sys_sigaction(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [signal, action, old_action, ..] = frame.args();
+    let signal = signal_number(signal)?;
+    let process = process::current().ok_or(Error::InvalidArgument)?;
+    // check before changing anything, or the old action would be lost
+    if old_action != 0 {
+        user_slice_mut(old_action, mem::size_of::<SigAction>() as u64)?;
+    }
+    let old = if action != 0 {
+        let action = unsafe { read_user::<SigAction>(action)? };
+        action
+            .to_action()
+            .and_then(|action| process.signals().set_action(signal, action))
+            .map_err(|err| match err {
+                ActionError::InvalidSignal => Error::InvalidArgument,
+                ActionError::BadAddress => Error::BadAddress,
+            })?
+    } else {
+        process.signals().action(signal)
+    };
+    if old_action != 0 {
+        write_user(old_action, SigAction::from(old))?;
+    }
+    Ok(0)
+}
+
+fn sys_sigprocmask(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [how, set, old_set, ..] = frame.args();
+    let process = process::current().ok_or(Error::InvalidArgument)?;
+    if old_set != 0 {
+        user_slice_mut(old_set, 8)?;
+    }
+    let signals = process.signals();
+    let old = signals.blocked();
+    if set != 0 {
+        let set = unsafe { read_user::<u64>(set)? };
+        let blocked = match how {
+            SIG_BLOCK => old.bits() | set,
+            SIG_UNBLOCK => old.bits() & !set,
+            SIG_SETMASK => set,
+            _ => return Err(Error::InvalidArgument),
+        };
+        signals.set_blocked(SigSet::from_bits(blocked));
+    }
+    if old_set != 0 {
+        write_user(old_set, old.bits())?;
+    }
+    Ok(0)
+}
+
+fn sys_kill(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [pid, signal, ..] = frame.args();
+    // 0 only checks that the process is there
+    let signal = match signal {
+        0 => 0,
+        signal => signal_number(signal)?,
+    };
+    if pid as i64 <= 0 {
+        return Err(Error::InvalidArgument);
+    }
+    process::kill(Pid::from_u64(pid), signal).map_err(|_| Error::NoSuchProcess)?;
+    Ok(0)
+}
+
+fn sys_sigreturn(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    // the handler's return popped the restorer, the context is next
+    signal::sigreturn(frame.rsp)
+}
+
+fn sys_pipe(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let (read_end, write_end) = pipe::pipe();
+    open_pair(frame.rdi, Arc::new(read_end), Arc::new(write_end))
+}
+
+/// Opens `first` and `second` in the calling process and stores their fds
+/// as two 32-bit numbers at `fds`.
+fn open_pair(fds: u64, first: Arc<dyn File>, second: Arc<dyn File>) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::InvalidArgument)?;
+    // check first, or a bad pointer would leave both open
+    user_slice_mut(fds, 8)?;
+    let mut files = process.files();
+    let first_fd = files.open(first)?;
+    let second_fd = match files.open(second) {
+        Ok(fd) => fd,
+        Err(err) => {
+            files.close(first_fd)?;
+            return Err(err);
+        }
+    };
+    drop(files);
+    write_user(fds, [first_fd as u32, second_fd as u32])?;
+    Ok(0)
+}
+
+fn sys_close(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let process = process::current().ok_or(Error::BadFileDescriptor)?;
+    // the file may go away with it, and is best dropped unlocked
+    let file = process.files().get(frame.rdi)?;
+    process.files().close(frame.rdi)?;
+    drop(file);
+    Ok(0)
+}
+
+fn sys_dup2(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [fd, new_fd, ..] = frame.args();
+    let process = process::current().ok_or(Error::BadFileDescriptor)?;
+    let mut files = process.files();
+    let old = files.get(new_fd).ok();
+    files.dup2(fd, new_fd)?;
+    drop(files);
+    drop(old);
+    Ok(new_fd)
+}
+
+fn sys_channel(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let (first, second) = channel::channel();
+    open_pair(frame.rdi, Arc::new(first), Arc::new(second))
+}
+
+fn sys_send(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [fd, message, ..] = frame.args();
+    let endpoint = file(fd)?;
+    let endpoint = endpoint.as_endpoint().ok_or(Error::BadFileDescriptor)?;
+    let raw: RawMessage = unsafe { read_user(message)? };
+    let handle = match raw.handle {
+        -1 => None,
+        handle => Some(file(handle as u64)?),
+    };
+    endpoint.send(Message { data: raw.data, handle })?;
+    Ok(0)
+}
+
+fn sys_recv(frame: &mut SyscallFrame) -> Result<u64, Error> {
+    let [fd, message, flags, ..] = frame.args();
+    if flags & !RECV_NONBLOCK != 0 {
+        return Err(Error::InvalidArgument);
+    }
+    // only a process has somewhere to put a handle
+    let process = process::current().ok_or(Error::InvalidArgument)?;
+    let endpoint = process.files().get(fd)?;
+    let endpoint = endpoint.as_endpoint().ok_or(Error::BadFileDescriptor)?;
+    // check first, so as not to lose a message to a bad pointer
+    user_slice_mut(message, mem::size_of::<RawMessage>() as u64)?;
+    let received = if flags & RECV_NONBLOCK != 0 {
+        endpoint.try_recv()?
+    } else {
+        endpoint.recv()?
+    };
+    let handle = match received.handle.clone() {
+        None => -1,
+        Some(handle) => match process.files().open(handle) {
+            Ok(fd) => fd as i64,
+            Err(err) => {
+                // leave it for when there's room
+                endpoint.unreceive(received);
+                return Err(err);
+            }
+        },
+    };
+    write_user(message, RawMessage { data: received.data, handle })?;
+    Ok(0)
+}
+
+#[test_case]
+fn test_unknown_number_fails() {
+    let mut frame = SyscallFrame {
+        rax: TABLE.len() as u64,
+        ..SyscallFrame::default()
+    };
+    mooos_syscall_dispatch(&mut frame);
+    assert_eq!(frame.rax as i64, Error::NoSuchSystemCall.code());
+    assert!(!interrupts::are_enabled());
+    interrupts::enable();
+}
+
+#[test_case]
+fn test_rejects_kernel_pointers() {
+    let kernel_data = [0u8; 8];
+    assert_eq!(user_slice(kernel_data.as_ptr() as u64, 8), Err(Error::BadAddress));
+    assert!(user_slice(usermode::USER_END - 4, 8).is_err());
+}
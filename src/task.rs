@@ -0,0 +1,51 @@
+//! Cooperative kernel tasks.
+//!
+//! A `Task` is a future the kernel runs to completion, so a subsystem can be
+//! written as an `async fn` that awaits whatever it is waiting for instead of
+//! blocking the CPU. `executor::Executor` polls tasks only when their waker
+//! says they can make progress and halts the CPU when none can;
+//! `simple_executor::SimpleExecutor` just polls everything in a loop and is
+//! mostly useful for debugging. `timer` has futures for sleeping and
+//! timeouts, and `channel::Endpoint::recv_async` one for messages.
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+pub mod executor;
+pub mod simple_executor;
+pub mod timer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
@@ -0,0 +1,161 @@
+//! The kernel's main executor.
+//!
+//! A task is only polled after its waker fired. Wakers push the task's id
+//! onto a lock-free queue, so waking a task is safe from interrupt handlers.
+//! When no task is ready the executor does what `crate::idle_loop` does:
+//! runs softirqs, lets kernel threads run and halts until the next
+//! interrupt.
+
+use super::{Task, TaskId};
+use crate::{softirq, thread};
+use crate::sync::ArrayQueue;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::task::{Context, Poll, Waker};
+
+/// How many woken tasks can wait to be polled.
+const QUEUE_SIZE: usize = 100;
+
+type TaskQueue = ArrayQueue<TaskId, QUEUE_SIZE>;
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    task_queue: Arc<TaskQueue>,
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            task_queue: Arc::new(ArrayQueue::new()),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `task` and schedules its first poll.
+    pub fn spawn(&mut self, task: Task) {
+        let task_id = task.id;
+        if self.tasks.insert(task.id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.task_queue.push(task_id).expect("task queue full");
+    }
+
+    /// Number of tasks that haven't completed yet.
+    pub fn tasks(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Runs tasks forever, halting while none of them can make progress.
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            softirq::run_pending();
+            yield_to_threads();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Runs tasks until all of them have completed, halting while none of
+    /// them can make progress.
+    pub fn run_until_complete(&mut self) {
+        loop {
+            self.run_ready_tasks();
+            if self.tasks.is_empty() {
+                return;
+            }
+            softirq::run_pending();
+            yield_to_threads();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// Polls tasks until none is left that has been woken, then returns.
+    ///
+    /// Doesn't wait for interrupts, so tasks waiting on one stay pending.
+    pub fn run_until_idle(&mut self) {
+        while !self.task_queue.is_empty() {
+            self.run_ready_tasks();
+            softirq::run_pending();
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        // destructure `self` to avoid borrow checker errors
+        let Self {
+            tasks,
+            task_queue,
+            waker_cache,
+        } = self;
+
+        while let Some(task_id) = task_queue.pop() {
+            let task = match tasks.get_mut(&task_id) {
+                Some(task) => task,
+                None => continue, // task no longer exists
+            };
+            let waker = waker_cache
+                .entry(task_id)
+                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+            let mut context = Context::from_waker(waker);
+            match task.poll(&mut context) {
+                Poll::Ready(()) => {
+                    // task done -> remove it and its cached waker
+                    tasks.remove(&task_id);
+                    waker_cache.remove(&task_id);
+                }
+                Poll::Pending => {}
+            }
+        }
+    }
+
+    fn sleep_if_idle(&self) {
+        use x86_64::instructions::interrupts;
+
+        // check and halt with interrupts off, or a wakeup in between would
+        // wait for the next interrupt
+        interrupts::disable();
+        if self.task_queue.is_empty() && !softirq::is_pending() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+/// Gives the CPU to kernel threads that want it, if there are threads. The
+/// scheduler feeds the watchdog on the way.
+fn yield_to_threads() {
+    if thread::is_initialized() {
+        thread::yield_now();
+    }
+}
+
+struct TaskWaker {
+    task_id: TaskId,
+    task_queue: Arc<TaskQueue>,
+}
+
+impl TaskWaker {
+    fn new(task_id: TaskId, task_queue: Arc<TaskQueue>) -> Waker {
+        Waker::from(Arc::new(TaskWaker {
+            task_id,
+            task_queue,
+        }))
+    }
+
+    fn wake_task(&self) {
+        self.task_queue.push(self.task_id).expect("task_queue full");
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
@@ -0,0 +1,148 @@
+//! Futures that wait for time to pass.
+//!
+//! A pending `Sleep` leaves its waker in a table ordered by deadline tick.
+//! The timer interrupt calls `wake_expired`, which wakes everything whose
+//! deadline has come. It only ever borrows the wakers: registering, replacing
+//! and dropping them, which can allocate or free, happens in the task's own
+//! context with interrupts disabled.
+
+use alloc::collections::BTreeMap;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::time;
+
+/// Wakers of pending sleeps, keyed by deadline tick and sleep id.
+static SLEEPERS: Mutex<BTreeMap<(u64, u64), Waker>> = Mutex::new(BTreeMap::new());
+/// Every sleeper with a deadline up to here has been woken.
+static WOKEN_UP_TO: AtomicU64 = AtomicU64::new(0);
+
+fn next_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Wakes the sleepers whose deadline has passed. Called from the timer
+/// interrupt.
+pub(crate) fn wake_expired() {
+    let now = time::ticks();
+    // if a task is registering right now, its tick isn't lost: the next one
+    // picks up from where we last got to
+    let sleepers = match SLEEPERS.try_lock() {
+        Some(sleepers) => sleepers,
+        None => return,
+    };
+    let from = WOKEN_UP_TO.load(Ordering::Relaxed) + 1;
+    for waker in sleepers.range((from, 0)..=(now, u64::MAX)).map(|(_, waker)| waker) {
+        waker.wake_by_ref();
+    }
+    WOKEN_UP_TO.store(now, Ordering::Relaxed);
+}
+
+/// Future returned by `sleep`.
+pub struct Sleep {
+    deadline: u64,
+    id: u64,
+    registered: bool,
+}
+
+/// Completes once at least `duration` has passed.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline: time::ticks() + time::duration_to_ticks(duration),
+        id: next_id(),
+        registered: false,
+    }
+}
+
+impl Sleep {
+    /// Tick on which the sleep completes.
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+
+    fn unregister(&mut self) {
+        if self.registered {
+            let key = (self.deadline, self.id);
+            let waker = interrupts::without_interrupts(|| SLEEPERS.lock().remove(&key));
+            self.registered = false;
+            drop(waker);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let key = (self.deadline, self.id);
+        let done = interrupts::without_interrupts(|| {
+            if time::ticks() >= key.0 {
+                return true;
+            }
+            let mut sleepers = SLEEPERS.lock();
+            match sleepers.get_mut(&key) {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                Some(waker) => *waker = cx.waker().clone(),
+                None => {
+                    sleepers.insert(key, cx.waker().clone());
+                }
+            }
+            false
+        });
+
+        if done {
+            self.unregister();
+            Poll::Ready(())
+        } else {
+            self.registered = true;
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
+/// Error returned by `timeout` when time ran out first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Future returned by `timeout`.
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+/// Runs `future`, giving up once `duration` has passed.
+pub fn timeout<F: Future>(future: F, duration: Duration) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // `future` is pinned along with us and never moved out; `sleep` is Unpin
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
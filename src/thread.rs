@@ -0,0 +1,718 @@
+//! Preemptive kernel threads.
+//!
+//! Every thread has its own kernel stack. Switching threads saves the
+//! callee-saved registers on the old thread's stack, records its stack
+//! pointer and loads the new one's; everything else either was already saved
+//! by the compiler at the call to `switch_context` or, for a thread the timer
+//! preempted, by the interrupt handler it is still inside of.
+//!
+//! Which runnable thread goes next is up to the `scheduler::Scheduler` policy
+//! picked at build time (see `scheduler`). The timer interrupt calls
+//! `preempt`, which charges the running thread for its time and asks the
+//! policy whether to switch; `yield_now` gives up the CPU early.
+//!
+//! The scheduler never allocates or frees with interrupts disabled: the
+//! thread it interrupted could be holding the allocator lock. That's why the
+//! queues have a fixed capacity, and why exited threads are only freed later,
+//! by `spawn` or `yield_now`.
+
+use crate::{gdt, watchdog};
+use crate::memory::{self, AddressSpace};
+use crate::process::Process;
+use crate::sync::{ArrayQueue, Interrupted, WaitQueue};
+use crate::time::{self, tsc};
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+use scheduler::{Policy, Scheduler};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::registers::control::{Cr3, Cr3Flags};
+use x86_64::VirtAddr;
+
+pub mod scheduler;
+
+/// Threads that can exist at once, including the boot thread.
+pub const MAX_THREADS: usize = 32;
+
+/// Size of each thread's kernel stack. Overflowing it goes unnoticed, there
+/// is no guard page.
+pub const STACK_SIZE: usize = 16 * 1024;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+/// How important a thread is to the scheduling policy; higher runs first
+/// (fixed priority) or gets a bigger share (fair). Round robin ignores it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(u8);
+
+impl Priority {
+    pub const MIN: Priority = Priority(0);
+    pub const NORMAL: Priority = Priority(4);
+    pub const MAX: Priority = Priority(7);
+    /// Number of distinct priorities.
+    pub const LEVELS: usize = 8;
+
+    pub fn new(level: u8) -> Option<Priority> {
+        if level <= Priority::MAX.0 {
+            Some(Priority(level))
+        } else {
+            None
+        }
+    }
+
+    pub fn level(self) -> u8 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThreadStats {
+    /// Time spent running.
+    pub runtime: Duration,
+    /// Number of times the thread was switched to.
+    pub switches: u64,
+}
+
+pub struct Thread {
+    id: ThreadId,
+    priority: Priority,
+    /// `None` for the boot thread, which runs on the bootloader's stack.
+    stack: Option<Box<[u8]>>,
+    /// Saved stack pointer while the thread isn't running.
+    rsp: u64,
+    runtime_ns: u64,
+    switches: u64,
+    /// `tsc::now` when the thread's runtime was last brought up to date.
+    charged_at: u64,
+    /// Weighted runtime, for the fair policy.
+    vruntime: u64,
+    /// The user address space loaded while the thread runs; `None` for the
+    /// kernel's.
+    address_space: Option<Arc<AddressSpace>>,
+    /// The process the thread belongs to; `None` for kernel threads.
+    process: Option<Arc<Process>>,
+    /// Link for the `ThreadList` the thread is blocked on.
+    next: Option<Box<Thread>>,
+}
+
+impl Thread {
+    fn new(priority: Priority, stack: Option<Box<[u8]>>, rsp: u64) -> Box<Thread> {
+        Box::new(Thread {
+            id: ThreadId::new(),
+            priority,
+            stack,
+            rsp,
+            runtime_ns: 0,
+            switches: 0,
+            charged_at: tsc::now(),
+            vruntime: 0,
+            address_space: None,
+            process: None,
+            next: None,
+        })
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    pub fn stats(&self) -> ThreadStats {
+        ThreadStats {
+            runtime: Duration::from_nanos(self.runtime_ns),
+            switches: self.switches,
+        }
+    }
+
+    /// Where the CPU should switch to when the thread enters the kernel from
+    /// ring 3.
+    fn kernel_stack_top(&self) -> VirtAddr {
+        match &self.stack {
+            Some(stack) => (VirtAddr::from_ptr(stack.as_ptr()) + stack.len()).align_down(16u64),
+            None => gdt::boot_kernel_stack(),
+        }
+    }
+
+    /// Loads the thread's page table, unless it is loaded already.
+    fn activate_address_space(&self) {
+        match &self.address_space {
+            Some(address_space) => address_space.activate(),
+            None => {
+                let kernel = memory::kernel_level_4_table();
+                if Cr3::read().0 != kernel {
+                    unsafe { Cr3::write(kernel, Cr3Flags::empty()) };
+                }
+            }
+        }
+    }
+
+    /// Adds the time since the last charge to the runtime, and returns it.
+    fn charge(&mut self, now: u64) -> u64 {
+        let ran = now.saturating_sub(self.charged_at);
+        self.runtime_ns += ran;
+        self.charged_at = now;
+        ran
+    }
+}
+
+/// Returned by `spawn` when `MAX_THREADS` threads already exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyThreads;
+
+// only ever locked with interrupts disabled, and `CURRENT` before `SCHEDULER`
+static CURRENT: Mutex<Option<Box<Thread>>> = Mutex::new(None);
+static SCHEDULER: Mutex<Policy> = Mutex::new(Policy::new());
+/// Runs when nothing else can; never in the scheduler's run queue.
+static IDLE: Mutex<Option<Box<Thread>>> = Mutex::new(None);
+static IDLE_ID: AtomicU64 = AtomicU64::new(u64::MAX);
+/// Threads that have exited but whose stacks haven't been freed yet.
+static DEAD: ArrayQueue<Box<Thread>, MAX_THREADS> = ArrayQueue::new();
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+/// `tsc::now` when the running thread was switched to.
+static SLICE_START: AtomicU64 = AtomicU64::new(0);
+/// Deadline tick and thread id of every thread in `sleep`.
+static SLEEPERS: Mutex<BTreeSet<(u64, u64)>> = Mutex::new(BTreeSet::new());
+/// Where threads in `sleep` wait; woken whenever one of them is due.
+static SLEEPING: WaitQueue = WaitQueue::new();
+
+global_asm!(
+    r#"
+// switch_context(save_rsp: *mut u64, new_rsp: u64)
+.global mooos_switch_context
+mooos_switch_context:
+    push rbp
+    push rbx
+    push r12
+    push r13
+    push r14
+    push r15
+    mov [rdi], rsp
+    mov rsp, rsi
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop rbx
+    pop rbp
+    ret
+
+// first thing a new thread runs; its entry closure is in r12
+.global mooos_thread_trampoline
+mooos_thread_trampoline:
+    mov rdi, r12
+    call {entry}
+    ud2
+"#,
+    entry = sym thread_entry,
+);
+
+extern "C" {
+    fn mooos_switch_context(save_rsp: *mut u64, new_rsp: u64);
+    fn mooos_thread_trampoline();
+}
+
+type Entry = Box<dyn FnOnce() + Send + 'static>;
+
+extern "C" fn thread_entry(entry: *mut Entry) -> ! {
+    // we were switched to with interrupts disabled
+    interrupts::enable();
+    let entry = unsafe { Box::from_raw(entry) };
+    entry();
+    exit();
+}
+
+/// Turns the code that calls this into the boot thread, so others can be
+/// spawned alongside it. Needs the heap.
+pub fn init() {
+    let boot = Thread::new(Priority::NORMAL, None, 0);
+    let idle = create(Priority::MIN, || idle());
+    IDLE_ID.store(idle.id.0, Ordering::Relaxed);
+    interrupts::without_interrupts(|| {
+        let mut current = CURRENT.lock();
+        assert!(current.is_none(), "thread::init called twice");
+        *current = Some(boot);
+        *IDLE.lock() = Some(idle);
+    });
+    THREADS.store(1, Ordering::Relaxed);
+}
+
+/// Whether `init` has run, so threads can block.
+pub fn is_initialized() -> bool {
+    THREADS.load(Ordering::Relaxed) > 0
+}
+
+/// Starts a new thread running `f` at normal priority. It exits when `f`
+/// returns.
+pub fn spawn<F>(f: F) -> Result<ThreadId, TooManyThreads>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with_priority(Priority::NORMAL, f)
+}
+
+/// Starts a new thread running `f` at `priority`.
+pub fn spawn_with_priority<F>(priority: Priority, f: F) -> Result<ThreadId, TooManyThreads>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_inner(priority, None, None, f)
+}
+
+/// Starts a new thread running `f` at normal priority, with `address_space`
+/// loaded whenever it runs. The thread holds on to the address space until
+/// it has exited.
+pub fn spawn_in<F>(address_space: Arc<AddressSpace>, f: F) -> Result<ThreadId, TooManyThreads>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_inner(Priority::NORMAL, Some(address_space), None, f)
+}
+
+/// Starts a new thread in `process`, running `f` at normal priority in the
+/// process's address space. Keeping count of the process's threads is up to
+/// the caller.
+pub(crate) fn spawn_for<F>(process: Arc<Process>, f: F) -> Result<ThreadId, TooManyThreads>
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_inner(Priority::NORMAL, process.address_space(), Some(process), f)
+}
+
+fn spawn_inner<F>(
+    priority: Priority,
+    address_space: Option<Arc<AddressSpace>>,
+    process: Option<Arc<Process>>,
+    f: F,
+) -> Result<ThreadId, TooManyThreads>
+where
+    F: FnOnce() + Send + 'static,
+{
+    reap();
+    let mut threads = THREADS.load(Ordering::Relaxed);
+    assert!(threads > 0, "thread::init wasn't called");
+    loop {
+        if threads >= MAX_THREADS {
+            return Err(TooManyThreads);
+        }
+        match THREADS.compare_exchange_weak(threads, threads + 1, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(current) => threads = current,
+        }
+    }
+
+    let mut thread = create(priority, f);
+    thread.address_space = address_space;
+    thread.process = process;
+    let id = thread.id;
+    interrupts::without_interrupts(|| SCHEDULER.lock().enqueue(thread));
+    Ok(id)
+}
+
+/// Builds a thread whose first switch-in runs `f`.
+fn create<F>(priority: Priority, f: F) -> Box<Thread>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let entry: *mut Entry = Box::into_raw(Box::new(Box::new(f)));
+    let mut stack = vec![0u8; STACK_SIZE].into_boxed_slice();
+    let top = (stack.as_mut_ptr() as u64 + STACK_SIZE as u64) & !0xf;
+
+    // what `mooos_switch_context` pops: r15, r14, r13, r12, rbx, rbp and the
+    // return address, placed so the trampoline's call is 16 byte aligned
+    let rsp = top - 72;
+    let frame = rsp as *mut u64;
+    unsafe {
+        for i in 0..6 {
+            frame.add(i).write(0);
+        }
+        frame.add(3).write(entry as u64);
+        frame.add(6).write(mooos_thread_trampoline as usize as u64);
+    }
+
+    Thread::new(priority, Some(stack), rsp)
+}
+
+/// The idle thread: halts until something is runnable. Yielding after
+/// every wakeup keeps the watchdog fed while the system is idle.
+fn idle() -> ! {
+    loop {
+        interrupts::disable();
+        if SCHEDULER.lock().has_ready() {
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+        yield_now();
+    }
+}
+
+/// The thread that is running right now.
+pub fn current() -> ThreadId {
+    interrupts::without_interrupts(|| {
+        CURRENT.lock().as_ref().expect("thread::init wasn't called").id
+    })
+}
+
+/// Number of threads, including the boot thread and threads that exited but
+/// haven't been freed yet. The idle thread doesn't count.
+pub fn count() -> usize {
+    THREADS.load(Ordering::Relaxed)
+}
+
+/// Whether another thread is waiting to run.
+pub fn has_ready() -> bool {
+    interrupts::without_interrupts(|| SCHEDULER.lock().has_ready())
+}
+
+/// Changes the priority of the calling thread.
+pub fn set_priority(priority: Priority) {
+    interrupts::without_interrupts(|| {
+        if let Some(current) = CURRENT.lock().as_mut() {
+            current.priority = priority;
+        }
+    });
+}
+
+/// Name of the scheduling policy this kernel was built with.
+pub fn policy() -> &'static str {
+    Policy::NAME
+}
+
+/// Lets another runnable thread run, if the policy has one that should.
+pub fn yield_now() {
+    reap();
+    interrupts::without_interrupts(|| switch(After::Yield));
+}
+
+/// The user address space of the calling thread, if it has one.
+pub fn address_space() -> Option<Arc<AddressSpace>> {
+    interrupts::without_interrupts(|| {
+        let current = CURRENT.lock();
+        current.as_ref().expect("thread::init wasn't called").address_space.clone()
+    })
+}
+
+/// Gives the calling thread `address_space` instead of the one it has, and
+/// loads it. Returns the old one, which is best dropped with interrupts
+/// enabled.
+pub(crate) fn replace_address_space(
+    address_space: Option<Arc<AddressSpace>>,
+) -> Option<Arc<AddressSpace>> {
+    interrupts::without_interrupts(|| {
+        let mut current = CURRENT.lock();
+        let current = current.as_mut().expect("thread::init wasn't called");
+        let old = core::mem::replace(&mut current.address_space, address_space);
+        current.activate_address_space();
+        old
+    })
+}
+
+/// The process the calling thread belongs to, if any.
+pub fn process() -> Option<Arc<Process>> {
+    interrupts::without_interrupts(|| {
+        let current = CURRENT.lock();
+        current.as_ref().expect("thread::init wasn't called").process.clone()
+    })
+}
+
+/// Blocks the calling thread for at least `duration`.
+///
+/// The timer interrupt wakes it, see `wake_sleepers`, so this works whether
+/// or not anything runs softirqs.
+pub fn sleep(duration: Duration) {
+    let deadline = time::ticks().saturating_add(time::duration_to_ticks(duration));
+    as_sleeper(deadline, || SLEEPING.wait_until(|| time::ticks() >= deadline));
+}
+
+/// Like `sleep`, but returns early if the calling process is interrupted,
+/// see `WaitQueue::wait_until_interruptible`.
+pub fn sleep_interruptible(duration: Duration) -> Result<(), Interrupted> {
+    let deadline = time::ticks().saturating_add(time::duration_to_ticks(duration));
+    as_sleeper(deadline, || {
+        SLEEPING.wait_until_interruptible(|| time::ticks() >= deadline)
+    })
+}
+
+/// Runs `wait` with the calling thread registered for `wake_sleepers` as
+/// due at `deadline`.
+fn as_sleeper<T>(deadline: u64, wait: impl FnOnce() -> T) -> T {
+    let key = (deadline, current().0);
+    // registering may allocate, so it happens here rather than in the
+    // interrupt handler, which only ever looks
+    interrupts::without_interrupts(|| SLEEPERS.lock().insert(key));
+    let result = wait();
+    interrupts::without_interrupts(|| SLEEPERS.lock().remove(&key));
+    result
+}
+
+/// Wakes the threads in `sleep` if one of them is due. Called from the timer
+/// interrupt.
+pub(crate) fn wake_sleepers() {
+    let now = time::ticks();
+    // if a thread is registering right now, the next tick sees it
+    let due = match SLEEPERS.try_lock() {
+        Some(sleepers) => sleepers.iter().next().map_or(false, |&(deadline, _)| deadline <= now),
+        None => return,
+    };
+    if due {
+        SLEEPING.notify_all();
+    }
+}
+
+/// Ends the calling thread.
+pub fn exit() -> ! {
+    interrupts::disable();
+    let is_boot = CURRENT.lock().as_ref().map_or(true, |thread| thread.stack.is_none());
+    assert!(!is_boot, "the boot thread can't exit");
+    switch(After::Exit);
+    unreachable!("exited thread was resumed");
+}
+
+/// Called from the timer interrupt, after the end of interrupt was sent.
+/// Switches threads if the policy says so.
+pub(crate) fn preempt() {
+    let now = tsc::now();
+    let preempt = {
+        let mut current = CURRENT.lock();
+        let current = match current.as_mut() {
+            Some(current) => current,
+            None => return,
+        };
+        let mut scheduler = SCHEDULER.lock();
+        let ran = current.charge(now);
+        scheduler.charge(current, ran);
+        let slice = now.saturating_sub(SLICE_START.load(Ordering::Relaxed));
+        scheduler.should_preempt(current, slice)
+    };
+    if preempt {
+        switch(After::Preempt);
+    }
+}
+
+/// What `switch` does with the thread it switches away from.
+enum After<'a> {
+    /// It yielded: hand it back to the scheduler, as one that did.
+    Yield,
+    /// It was preempted: hand it back to the scheduler.
+    Preempt,
+    /// Put it on the dead list.
+    Exit,
+    /// Give it to the closure, which parks it somewhere until it's woken.
+    Park(&'a mut dyn FnMut(Box<Thread>)),
+}
+
+/// Switches to the thread the policy picks, or the idle thread if there is
+/// none. Interrupts must be off.
+fn switch(after: After) {
+    // coming through here at all means no thread is hogging the CPU
+    watchdog::pet();
+    let now = tsc::now();
+
+    let (save_rsp, new_rsp) = {
+        let mut current = CURRENT.lock();
+        let mut scheduler = SCHEDULER.lock();
+        if let After::Yield | After::Preempt = after {
+            if !scheduler.has_ready() {
+                return;
+            }
+        }
+
+        let mut previous = current.take().expect("thread::init wasn't called");
+        let previous_id = previous.id;
+        let ran = previous.charge(now);
+        scheduler.charge(&mut previous, ran);
+        // the thread lives in a box, so this stays valid after moving it
+        let save_rsp: *mut u64 = &mut previous.rsp;
+        match after {
+            After::Yield | After::Preempt
+                if previous_id.0 == IDLE_ID.load(Ordering::Relaxed) =>
+            {
+                *IDLE.lock() = Some(previous);
+            }
+            After::Yield => {
+                scheduler.yielded(&mut previous);
+                scheduler.enqueue(previous);
+            }
+            After::Preempt => scheduler.enqueue(previous),
+            After::Exit => {
+                if DEAD.push(previous).is_err() {
+                    unreachable!("dead list holds MAX_THREADS");
+                }
+            }
+            After::Park(park) => park(previous),
+        }
+
+        let mut next = match scheduler.pick_next() {
+            Some(next) => next,
+            None => IDLE.lock().take().expect("idle thread is running but blocked"),
+        };
+        SLICE_START.store(now, Ordering::Relaxed);
+        if next.id == previous_id {
+            *current = Some(next);
+            return;
+        }
+        next.charged_at = now;
+        next.switches += 1;
+        gdt::set_kernel_stack(next.kernel_stack_top());
+        next.activate_address_space();
+        let new_rsp = next.rsp;
+        *current = Some(next);
+        (save_rsp, new_rsp)
+    };
+
+    unsafe { mooos_switch_context(save_rsp, new_rsp) };
+}
+
+/// Takes the calling thread off the CPU until someone passes it to `wake`.
+///
+/// `park` gets the thread and `guard`, and must store the thread where its
+/// waker will find it; `guard` is typically the lock protecting that place,
+/// and is dropped once `park` returns, before the switch. Interrupts must be
+/// off, so nothing can wake the thread before it is parked.
+pub(crate) fn block<G>(guard: G, park: impl FnOnce(G, Box<Thread>)) {
+    debug_assert!(!interrupts::are_enabled(), "thread::block with interrupts enabled");
+    let mut guard = Some(guard);
+    let mut park = Some(park);
+    switch(After::Park(&mut |thread| {
+        let park = park.take().expect("thread parked twice");
+        park(guard.take().expect("thread parked twice"), thread);
+    }));
+}
+
+/// Makes a thread that `block` parked runnable again. Safe to call from
+/// interrupt handlers.
+pub(crate) fn wake(thread: Box<Thread>) {
+    interrupts::without_interrupts(|| SCHEDULER.lock().enqueue(thread));
+}
+
+/// First in, first out list of blocked threads, linked through the threads
+/// themselves so that blocking never allocates.
+pub(crate) struct ThreadList {
+    head: Option<Box<Thread>>,
+    tail: *mut Thread,
+    len: usize,
+}
+
+// the raw tail pointer points into `head`'s chain, which moves with the list
+unsafe impl Send for ThreadList {}
+
+impl ThreadList {
+    pub const fn new() -> Self {
+        ThreadList {
+            head: None,
+            tail: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, mut thread: Box<Thread>) {
+        thread.next = None;
+        let raw: *mut Thread = &mut *thread;
+        match unsafe { self.tail.as_mut() } {
+            Some(tail) => tail.next = Some(thread),
+            None => self.head = Some(thread),
+        }
+        self.tail = raw;
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<Box<Thread>> {
+        let mut thread = self.head.take()?;
+        self.head = thread.next.take();
+        if self.head.is_none() {
+            self.tail = ptr::null_mut();
+        }
+        self.len -= 1;
+        Some(thread)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// Frees the stacks of threads that have exited.
+fn reap() {
+    while let Some(thread) = DEAD.pop() {
+        drop(thread);
+        THREADS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Snapshot of every thread's stats that implements `Display`.
+pub struct Table {
+    rows: Vec<(ThreadId, Priority, ThreadStats, bool)>,
+}
+
+/// Takes a snapshot of the stats of all threads.
+pub fn table() -> Table {
+    // no allocating with interrupts off, so make room first
+    let mut rows = Vec::with_capacity(MAX_THREADS);
+    interrupts::without_interrupts(|| {
+        let now = tsc::now();
+        if let Some(current) = CURRENT.lock().as_mut() {
+            let ran = current.charge(now);
+            SCHEDULER.lock().charge(current, ran);
+            rows.push((current.id, current.priority, current.stats(), true));
+        }
+        SCHEDULER.lock().for_each(&mut |thread| {
+            rows.push((thread.id, thread.priority, thread.stats(), false));
+        });
+    });
+    rows.sort_by_key(|row| row.0);
+    Table { rows }
+}
+
+impl Table {
+    /// The stats of thread `id`, if it existed when the snapshot was taken.
+    pub fn get(&self, id: ThreadId) -> Option<ThreadStats> {
+        self.rows.iter().find(|row| row.0 == id).map(|row| row.2)
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>4} {:>4} {:>14} {:>10}", "tid", "prio", "runtime (us)", "switches")?;
+        for (id, priority, stats, running) in &self.rows {
+            write!(
+                f,
+                "\n{:>4} {:>4} {:>14} {:>10}{}",
+                id.as_u64(),
+                priority.level(),
+                stats.runtime.as_micros(),
+                stats.switches,
+                if *running { "  (running)" } else { "" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the thread table to the VGA buffer.
+pub fn print_table() {
+    crate::println!("{}", table());
+}
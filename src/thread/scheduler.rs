@@ -0,0 +1,62 @@
+//! Scheduling policies.
+//!
+//! A policy owns the runnable threads and decides which one runs next and
+//! when the running one should be preempted. One is picked at build time with
+//! a cargo feature:
+//!
+//! - `sched-priority`: `FixedPriority`, the highest priority always runs
+//! - `sched-fair`: `Fair`, CPU time shared out by priority weight
+//! - neither: `RoundRobin`
+//!
+//! Policy methods are called with interrupts disabled and the scheduler lock
+//! held, so they must not allocate or block.
+
+use super::Thread;
+use alloc::boxed::Box;
+
+pub mod fair;
+pub mod priority;
+pub mod round_robin;
+
+pub use fair::Fair;
+pub use priority::FixedPriority;
+pub use round_robin::RoundRobin;
+
+#[cfg(all(feature = "sched-priority", feature = "sched-fair"))]
+compile_error!("enable at most one of the sched-priority and sched-fair features");
+
+#[cfg(feature = "sched-priority")]
+pub type Policy = FixedPriority;
+#[cfg(feature = "sched-fair")]
+pub type Policy = Fair;
+#[cfg(not(any(feature = "sched-priority", feature = "sched-fair")))]
+pub type Policy = RoundRobin;
+
+/// Length of a time slice for policies that use a fixed one.
+pub const TIME_SLICE_NS: u64 = 10_000_000;
+
+pub trait Scheduler {
+    const NAME: &'static str;
+
+    /// Makes `thread` runnable.
+    fn enqueue(&mut self, thread: Box<Thread>);
+
+    /// Takes the thread that should run next off the run queue.
+    fn pick_next(&mut self) -> Option<Box<Thread>>;
+
+    fn has_ready(&self) -> bool;
+
+    /// Called on every timer tick: whether `current`, which has been running
+    /// for `slice` nanoseconds since it was switched to, should make way.
+    fn should_preempt(&self, current: &Thread, slice: u64) -> bool;
+
+    /// Calls `f` on every thread in the run queue.
+    fn for_each(&self, f: &mut dyn FnMut(&Thread));
+
+    /// Tells the policy `thread` ran for another `nanos`.
+    fn charge(&mut self, _thread: &mut Thread, _nanos: u64) {}
+
+    /// Called when `thread` gives up the CPU voluntarily, before it is
+    /// queued again.
+    fn yielded(&mut self, _thread: &mut Thread) {}
+}
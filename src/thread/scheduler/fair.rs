@@ -0,0 +1,116 @@
+//! Fair sharing, after Linux's CFS.
+//!
+//! Each thread accumulates virtual runtime: its real runtime scaled down by
+//! its priority's weight. The thread with the least virtual runtime runs
+//! next, so over time every thread gets CPU in proportion to its weight.
+//! Threads that become runnable start no further behind than the least
+//! virtual runtime in the queue, so sleeping doesn't bank credit.
+
+use super::Scheduler;
+use crate::thread::{Priority, Thread, MAX_THREADS};
+use alloc::boxed::Box;
+
+/// Weight of `Priority::NORMAL`; virtual runtime passes at real speed for it.
+const NORMAL_WEIGHT: u64 = 1024;
+
+/// Per priority level, about 1.25 times the one below, like CFS's nice
+/// levels 4 down to -3.
+const WEIGHTS: [u64; Priority::LEVELS] = [423, 526, 655, 820, 1024, 1277, 1586, 1991];
+
+/// Minimum time a thread runs before it can be preempted.
+const MIN_GRANULARITY_NS: u64 = 1_000_000;
+/// How far ahead of the most deserving thread the running one may get.
+const WAKEUP_GRANULARITY_NS: u64 = 4_000_000;
+
+pub struct Fair {
+    /// Runnable threads. The run queue is small, so it is simply scanned.
+    threads: [Option<Box<Thread>>; MAX_THREADS],
+    len: usize,
+    /// Never decreases; where new threads are placed.
+    min_vruntime: u64,
+}
+
+impl Fair {
+    pub const fn new() -> Self {
+        const NONE: Option<Box<Thread>> = None;
+        Fair {
+            threads: [NONE; MAX_THREADS],
+            len: 0,
+            min_vruntime: 0,
+        }
+    }
+
+    fn weight(thread: &Thread) -> u64 {
+        WEIGHTS[usize::from(thread.priority().level())]
+    }
+
+    /// Index of the queued thread with the least virtual runtime.
+    fn leftmost(&self) -> Option<usize> {
+        self.threads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, thread)| thread.as_ref().map(|thread| (i, thread.vruntime)))
+            .min_by_key(|&(_, vruntime)| vruntime)
+            .map(|(i, _)| i)
+    }
+
+    fn leftmost_vruntime(&self) -> Option<u64> {
+        self.leftmost()
+            .and_then(|i| self.threads[i].as_ref())
+            .map(|thread| thread.vruntime)
+    }
+}
+
+impl Scheduler for Fair {
+    const NAME: &'static str = "fair";
+
+    fn enqueue(&mut self, mut thread: Box<Thread>) {
+        thread.vruntime = thread.vruntime.max(self.min_vruntime);
+        let slot = self
+            .threads
+            .iter()
+            .position(Option::is_none)
+            .expect("run queue holds MAX_THREADS");
+        self.threads[slot] = Some(thread);
+        self.len += 1;
+    }
+
+    fn pick_next(&mut self) -> Option<Box<Thread>> {
+        let thread = self.threads[self.leftmost()?].take()?;
+        self.len -= 1;
+        self.min_vruntime = self.min_vruntime.max(thread.vruntime);
+        Some(thread)
+    }
+
+    fn has_ready(&self) -> bool {
+        self.len > 0
+    }
+
+    fn should_preempt(&self, current: &Thread, slice: u64) -> bool {
+        if slice < MIN_GRANULARITY_NS {
+            return false;
+        }
+        match self.leftmost_vruntime() {
+            Some(vruntime) => current.vruntime > vruntime + WAKEUP_GRANULARITY_NS,
+            None => false,
+        }
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&Thread)) {
+        for thread in self.threads.iter().flatten() {
+            f(thread);
+        }
+    }
+
+    fn charge(&mut self, thread: &mut Thread, nanos: u64) {
+        thread.vruntime += nanos * NORMAL_WEIGHT / Self::weight(thread);
+    }
+
+    fn yielded(&mut self, thread: &mut Thread) {
+        // go behind everyone who is waiting, like round robin would
+        let rightmost = self.threads.iter().flatten().map(|thread| thread.vruntime).max();
+        if let Some(rightmost) = rightmost {
+            thread.vruntime = thread.vruntime.max(rightmost + 1);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Fixed priorities: the highest priority runnable thread always runs, and
+//! threads of equal priority take turns. Lower priorities starve for as long
+//! as a higher one has work.
+
+use super::{Scheduler, TIME_SLICE_NS};
+use crate::sync::ArrayQueue;
+use crate::thread::{Priority, Thread, MAX_THREADS};
+use alloc::boxed::Box;
+
+type Queue = ArrayQueue<Box<Thread>, MAX_THREADS>;
+
+pub struct FixedPriority {
+    /// One round-robin queue per priority level.
+    queues: [Queue; Priority::LEVELS],
+}
+
+impl FixedPriority {
+    pub const fn new() -> Self {
+        const EMPTY: Queue = ArrayQueue::new();
+        FixedPriority {
+            queues: [EMPTY; Priority::LEVELS],
+        }
+    }
+
+    /// The highest priority with a runnable thread.
+    fn highest_ready(&self) -> Option<usize> {
+        (0..Priority::LEVELS).rev().find(|&level| !self.queues[level].is_empty())
+    }
+}
+
+impl Scheduler for FixedPriority {
+    const NAME: &'static str = "fixed priority";
+
+    fn enqueue(&mut self, thread: Box<Thread>) {
+        let level = usize::from(thread.priority().level());
+        if self.queues[level].push(thread).is_err() {
+            unreachable!("run queue holds MAX_THREADS");
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<Box<Thread>> {
+        self.queues[self.highest_ready()?].pop()
+    }
+
+    fn has_ready(&self) -> bool {
+        self.highest_ready().is_some()
+    }
+
+    fn should_preempt(&self, current: &Thread, slice: u64) -> bool {
+        let current = usize::from(current.priority().level());
+        match self.highest_ready() {
+            Some(level) if level > current => true,
+            Some(level) if level == current => slice >= TIME_SLICE_NS,
+            _ => false,
+        }
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&Thread)) {
+        for queue in &self.queues {
+            for _ in 0..queue.len() {
+                if let Some(thread) = queue.pop() {
+                    f(&thread);
+                    let _ = queue.push(thread);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Every thread gets the same time slice, in turn.
+
+use super::{Scheduler, TIME_SLICE_NS};
+use crate::sync::ArrayQueue;
+use crate::thread::{Thread, MAX_THREADS};
+use alloc::boxed::Box;
+
+pub struct RoundRobin {
+    queue: ArrayQueue<Box<Thread>, MAX_THREADS>,
+}
+
+impl RoundRobin {
+    pub const fn new() -> Self {
+        RoundRobin {
+            queue: ArrayQueue::new(),
+        }
+    }
+}
+
+impl Scheduler for RoundRobin {
+    const NAME: &'static str = "round robin";
+
+    fn enqueue(&mut self, thread: Box<Thread>) {
+        if self.queue.push(thread).is_err() {
+            unreachable!("run queue holds MAX_THREADS");
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<Box<Thread>> {
+        self.queue.pop()
+    }
+
+    fn has_ready(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    fn should_preempt(&self, _current: &Thread, slice: u64) -> bool {
+        slice >= TIME_SLICE_NS && self.has_ready()
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&Thread)) {
+        // rotate the whole queue once; we hold the only reference to it
+        for _ in 0..self.queue.len() {
+            if let Some(thread) = self.queue.pop() {
+                f(&thread);
+                let _ = self.queue.push(thread);
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Monotonic kernel clock driven by the timer interrupt.
+//!
+//! `init` reprograms channel 0 of the 8253/8254 PIT to fire IRQ 0 at the
+//! requested rate (the HPET can take over later, see
+//! `hpet::use_as_system_timer`) and the timer interrupt handler calls `tick`
+//! once per interrupt. Everything else in here converts the resulting tick
+//! count into something useful.
+
+use core::convert::TryFrom;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use x86_64::instructions::port::Port;
+
+pub mod tsc;
+pub mod wheel;
+
+/// Frequency of the oscillator feeding the PIT.
+pub const PIT_FREQUENCY_HZ: u32 = 1_193_182;
+
+/// Tick rate set up by `crate::init`.
+pub const DEFAULT_FREQUENCY_HZ: u32 = 1000;
+
+const PIT_CHANNEL0_DATA: u16 = 0x40;
+const PIT_COMMAND: u16 = 0x43;
+
+/// channel 0, access lobyte/hibyte, mode 2 (rate generator), binary
+const PIT_CHANNEL0_RATE_GENERATOR: u8 = 0b0011_0100;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Length of one tick in femtoseconds. The BIOS leaves the PIT at its
+/// slowest rate of 65536 input cycles per interrupt (~18.2 Hz).
+static TICK_PERIOD_FS: AtomicU64 = AtomicU64::new(pit_period_fs(65536));
+
+const FEMTOS_PER_SECOND: u64 = 1_000_000_000_000_000;
+const FEMTOS_PER_NANO: u128 = 1_000_000;
+
+const fn pit_period_fs(divisor: u32) -> u64 {
+    divisor as u64 * FEMTOS_PER_SECOND / PIT_FREQUENCY_HZ as u64
+}
+
+/// Programs PIT channel 0 to interrupt `frequency_hz` times per second.
+///
+/// The PIT can only divide its input clock by an integer in 1..=65536, so the
+/// actual rate is the closest one it can do; see `frequency`.
+pub fn init(frequency_hz: u32) {
+    use x86_64::instructions::interrupts;
+
+    assert!(frequency_hz > 0, "timer frequency must be non-zero");
+    let divisor = ((PIT_FREQUENCY_HZ + frequency_hz / 2) / frequency_hz).clamp(1, 65536);
+
+    let mut command: Port<u8> = Port::new(PIT_COMMAND);
+    let mut data: Port<u8> = Port::new(PIT_CHANNEL0_DATA);
+    interrupts::without_interrupts(|| {
+        set_tick_period_fs(pit_period_fs(divisor));
+        // a reload value of 0 means 65536
+        let reload = divisor as u16;
+        unsafe {
+            command.write(PIT_CHANNEL0_RATE_GENERATOR);
+            data.write(reload as u8);
+            data.write((reload >> 8) as u8);
+        }
+    });
+}
+
+/// Tells the clock how long a tick is, for timer sources other than the PIT.
+///
+/// Tick counts taken before the change are converted at the new rate too, so
+/// this should only happen early during boot.
+pub fn set_tick_period_fs(period_fs: u64) {
+    assert!(period_fs > 0, "tick period must be non-zero");
+    TICK_PERIOD_FS.store(period_fs, Ordering::Relaxed);
+}
+
+/// Advances the clock by one tick. Only the timer interrupt should call this.
+pub fn tick() {
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    tsc::on_tick(ticks);
+    crate::softirq::raise(crate::softirq::Softirq::Timer);
+}
+
+/// Number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// The rate the timer interrupt actually fires at, rounded to whole hertz.
+pub fn frequency() -> u32 {
+    let period_fs = TICK_PERIOD_FS.load(Ordering::Relaxed);
+    ((FEMTOS_PER_SECOND + period_fs / 2) / period_fs) as u32
+}
+
+/// Length of `ticks` timer ticks.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    let period_fs = u128::from(TICK_PERIOD_FS.load(Ordering::Relaxed));
+    let nanos = u128::from(ticks) * period_fs / FEMTOS_PER_NANO;
+    Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32)
+}
+
+/// Number of ticks needed to cover at least `duration`, or `u64::MAX` if
+/// that many don't.
+pub fn duration_to_ticks(duration: Duration) -> u64 {
+    let period_fs = u128::from(TICK_PERIOD_FS.load(Ordering::Relaxed));
+    let femtos = duration.as_nanos() * FEMTOS_PER_NANO;
+    u64::try_from((femtos + period_fs - 1) / period_fs).unwrap_or(u64::MAX)
+}
+
+/// Time since boot, at tick granularity.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Halts until at least `ticks` more timer interrupts have happened.
+///
+/// Interrupts must be enabled, otherwise this never returns.
+pub fn sleep_ticks(ticks: u64) {
+    use x86_64::instructions::interrupts;
+
+    debug_assert!(interrupts::are_enabled(), "sleep_ticks with interrupts disabled");
+    let deadline = self::ticks() + ticks;
+    while self::ticks() < deadline {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Halts for at least `duration`.
+pub fn sleep(duration: Duration) {
+    sleep_ticks(duration_to_ticks(duration));
+}
+
+#[test_case]
+fn test_ticks_advance() {
+    let start = ticks();
+    sleep_ticks(3);
+    assert!(ticks() >= start + 3);
+}
+
+#[test_case]
+fn test_duration_round_trip() {
+    let duration = Duration::from_millis(250);
+    let ticks = duration_to_ticks(duration);
+    assert!(ticks_to_duration(ticks) >= duration);
+    assert!(ticks_to_duration(ticks - 1) < duration);
+    assert_eq!(duration_to_ticks(Duration::from_millis(u64::MAX)), u64::MAX);
+}
+
+#[test_case]
+fn test_sleep_duration() {
+    let start = uptime();
+    sleep(Duration::from_millis(20));
+    assert!(uptime() - start >= Duration::from_millis(20));
+}
@@ -0,0 +1,247 @@
+//! High resolution timestamps from the time stamp counter.
+//!
+//! `init` works out how fast the TSC ticks: from CPUID leaf 0x15 (crystal
+//! ratio) or 0x16 (base frequency) when the CPU reports them, otherwise by
+//! counting cycles across a known PIT channel 2 interval. Once the HPET is
+//! up, `calibrate_with_hpet` refines a PIT measurement against it.
+//!
+//! If the TSC is invariant it is used directly as the clock. Otherwise its
+//! rate may drift with power states, so `now` only uses it to interpolate
+//! between timer ticks and the tick clock keeps the long term time.
+
+use super::PIT_FREQUENCY_HZ;
+use crate::hpet::Hpet;
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Source {
+    /// `init` hasn't run yet.
+    Uncalibrated,
+    /// Reported by CPUID leaf 0x15 or 0x16.
+    Cpuid,
+    /// Measured against PIT channel 2.
+    Pit,
+    /// Measured against the HPET main counter.
+    Hpet,
+}
+
+static FREQUENCY_HZ: AtomicU64 = AtomicU64::new(0);
+static SOURCE: AtomicU8 = AtomicU8::new(Source::Uncalibrated as u8);
+static INVARIANT: AtomicBool = AtomicBool::new(false);
+
+/// Counter value when `init` ran; `now` counts from here.
+static BASE: AtomicU64 = AtomicU64::new(0);
+/// Tick count and counter value as of the most recent timer tick.
+static LAST_TICK: AtomicU64 = AtomicU64::new(0);
+static LAST_TICK_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// Length of one PIT calibration run.
+const CALIBRATION_MS: u32 = 10;
+const CALIBRATION_RUNS: usize = 3;
+
+/// Reads the raw time stamp counter.
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Determines the TSC frequency and starts the nanosecond clock.
+pub fn init() {
+    INVARIANT.store(detect_invariant(), Ordering::Relaxed);
+
+    let (hz, source) = match frequency_from_cpuid() {
+        Some(hz) => (hz, Source::Cpuid),
+        None => (calibrate_with_pit(), Source::Pit),
+    };
+    FREQUENCY_HZ.store(hz, Ordering::Relaxed);
+    SOURCE.store(source as u8, Ordering::Relaxed);
+    BASE.store(read(), Ordering::Relaxed);
+}
+
+/// Cycles per second, once `init` has run.
+pub fn frequency() -> Option<u64> {
+    match FREQUENCY_HZ.load(Ordering::Relaxed) {
+        0 => None,
+        hz => Some(hz),
+    }
+}
+
+/// Where the frequency returned by `frequency` came from.
+pub fn source() -> Source {
+    match SOURCE.load(Ordering::Relaxed) {
+        1 => Source::Cpuid,
+        2 => Source::Pit,
+        3 => Source::Hpet,
+        _ => Source::Uncalibrated,
+    }
+}
+
+/// Whether the TSC runs at a constant rate regardless of power state.
+pub fn is_invariant() -> bool {
+    INVARIANT.load(Ordering::Relaxed)
+}
+
+pub fn cycles_to_nanos(cycles: u64) -> u64 {
+    match frequency() {
+        Some(hz) => (u128::from(cycles) * 1_000_000_000 / u128::from(hz)) as u64,
+        None => 0,
+    }
+}
+
+/// Nanoseconds since the clock was initialized.
+pub fn now() -> u64 {
+    let hz = match frequency() {
+        Some(hz) => hz,
+        None => return super::uptime().as_nanos() as u64,
+    };
+    if is_invariant() {
+        return cycles_to_nanos(read().wrapping_sub(BASE.load(Ordering::Relaxed)));
+    }
+
+    // read a consistent (tick, tsc) pair, retrying if a tick lands in between
+    let (ticks, tick_tsc) = loop {
+        let ticks = LAST_TICK.load(Ordering::Acquire);
+        let tick_tsc = LAST_TICK_TSC.load(Ordering::Acquire);
+        if LAST_TICK.load(Ordering::Acquire) == ticks {
+            break (ticks, tick_tsc);
+        }
+    };
+    let tick_start = super::ticks_to_duration(ticks).as_nanos() as u64;
+    let tick_length = super::ticks_to_duration(1).as_nanos() as u64;
+    let since_tick = u128::from(read().wrapping_sub(tick_tsc)) * 1_000_000_000 / u128::from(hz);
+    tick_start + (since_tick as u64).min(tick_length)
+}
+
+/// Records the counter at a timer tick. Called from `time::tick`.
+pub(super) fn on_tick(ticks: u64) {
+    // publish the tsc before the tick number `now` keys off
+    LAST_TICK_TSC.store(read(), Ordering::Release);
+    LAST_TICK.store(ticks, Ordering::Release);
+}
+
+fn detect_invariant() -> bool {
+    let max_extended_leaf = unsafe { __cpuid(0x8000_0000) }.eax;
+    if max_extended_leaf < 0x8000_0007 {
+        return false;
+    }
+    // advanced power management: EDX bit 8 is "invariant TSC"
+    unsafe { __cpuid(0x8000_0007) }.edx & (1 << 8) != 0
+}
+
+fn frequency_from_cpuid() -> Option<u64> {
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+
+    if max_leaf >= 0x15 {
+        // TSC / crystal clock ratio is EBX / EAX; ECX is the crystal in Hz
+        let leaf = unsafe { __cpuid(0x15) };
+        if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+            return Some(u64::from(leaf.ecx) * u64::from(leaf.ebx) / u64::from(leaf.eax));
+        }
+    }
+
+    if max_leaf >= 0x16 {
+        // processor base frequency in MHz
+        let base_mhz = unsafe { __cpuid(0x16) }.eax & 0xffff;
+        if base_mhz != 0 {
+            return Some(u64::from(base_mhz) * 1_000_000);
+        }
+    }
+
+    None
+}
+
+/// Counts TSC cycles across a fixed PIT channel 2 countdown.
+///
+/// Channel 2 is only wired to the PC speaker, so it can be used without
+/// disturbing the timer interrupt on channel 0. Takes the fastest of a few
+/// runs since anything that delays us only ever inflates the count.
+fn calibrate_with_pit() -> u64 {
+    let count = PIT_FREQUENCY_HZ * CALIBRATION_MS / 1000;
+    let mut control: Port<u8> = Port::new(0x61);
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut data: Port<u8> = Port::new(0x42);
+
+    interrupts::without_interrupts(|| {
+        let saved = unsafe { control.read() };
+        let mut best = u64::MAX;
+
+        for _ in 0..CALIBRATION_RUNS {
+            unsafe {
+                // gate channel 2 on, keep the speaker off
+                control.write((saved & !0x02) | 0x01);
+                // channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count)
+                command.write(0b1011_0000);
+                data.write(count as u8);
+                data.write((count >> 8) as u8);
+
+                let start = read();
+                // bit 5 mirrors channel 2's output, which goes high at zero
+                while control.read() & 0x20 == 0 {}
+                best = best.min(read() - start);
+            }
+        }
+
+        unsafe { control.write(saved) };
+        best * 1000 / u64::from(CALIBRATION_MS)
+    })
+}
+
+/// Measures the TSC against the HPET main counter.
+///
+/// The HPET is far more precise than our PIT measurement, but CPUID, where
+/// available, is exact, so this only replaces a PIT calibration. `now`
+/// carries on from where it was instead of jumping to the new rate.
+pub fn calibrate_with_hpet(hpet: &Hpet) {
+    if source() == Source::Cpuid {
+        return;
+    }
+
+    let target = hpet.duration_to_counter(Duration::from_millis(CALIBRATION_MS.into()));
+    let (cycles, counted) = interrupts::without_interrupts(|| {
+        let start = hpet.counter();
+        let tsc_start = read();
+        let mut counted = 0;
+        while counted < target {
+            counted = hpet.counter().wrapping_sub(start);
+        }
+        (read() - tsc_start, counted)
+    });
+    let hz = u128::from(cycles) * 1_000_000_000_000_000
+        / (u128::from(counted) * u128::from(hpet.period_fs()));
+
+    interrupts::without_interrupts(|| {
+        let elapsed = now();
+        let elapsed_cycles = u128::from(elapsed) * hz / 1_000_000_000;
+        FREQUENCY_HZ.store(hz as u64, Ordering::Relaxed);
+        SOURCE.store(Source::Hpet as u8, Ordering::Relaxed);
+        BASE.store(read().wrapping_sub(elapsed_cycles as u64), Ordering::Relaxed);
+    });
+}
+
+#[test_case]
+fn test_calibrated() {
+    assert!(source() != Source::Uncalibrated);
+    assert!(frequency().unwrap() > 1_000_000);
+}
+
+#[test_case]
+fn test_now_is_monotonic() {
+    let mut last = now();
+    for _ in 0..1000 {
+        let current = now();
+        assert!(current >= last);
+        last = current;
+    }
+}
+
+#[test_case]
+fn test_now_tracks_ticks() {
+    let start = now();
+    super::sleep_ticks(10);
+    let elapsed = now() - start;
+    assert!(elapsed >= super::ticks_to_duration(9).as_nanos() as u64);
+}
@@ -0,0 +1,227 @@
+//! Hierarchical timer wheel for kernel timeouts.
+//!
+//! Timers are kept in `LEVELS` wheels of `SLOTS` slots each. Level 0 has one
+//! slot per tick; every level above covers `SLOTS` times the range of the one
+//! below. Whenever the level below wraps around, the next slot of a level is
+//! emptied and its timers redistributed ("cascaded") to lower levels, so
+//! adding and expiring timers is O(1) no matter how far out they are.
+//!
+//! The timer interrupt only raises `Softirq::Timer`; the wheel is advanced
+//! and callbacks run from `softirq::run_pending`, never in interrupt context.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::mem;
+use core::time::Duration;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+const LEVELS: usize = 4;
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+
+/// Timers further out than this are parked at the edge of the wheel and
+/// re-sorted as it turns.
+const MAX_DELTA: u64 = (1 << (SLOT_BITS * LEVELS as u32)) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+struct Timer {
+    id: TimerId,
+    /// Tick on which the timer fires.
+    expires: u64,
+    period: Option<u64>,
+    callback: Box<dyn FnMut() + Send>,
+}
+
+struct Wheel {
+    /// The last tick that has been processed.
+    now: u64,
+    levels: [[Vec<Timer>; SLOTS]; LEVELS],
+    /// Where every timer that is yet to fire is, for `cancel`.
+    index: BTreeMap<TimerId, Place>,
+    next_id: u64,
+}
+
+enum Place {
+    /// On the wheel, at this level and slot.
+    Wheel(usize, usize),
+    /// Taken off the wheel by `advance`, to be run. A periodic timer stays
+    /// here while its callback runs, so that cancelling it then keeps it
+    /// from being re-added.
+    Due { cancelled: bool },
+}
+
+impl Wheel {
+    fn new() -> Self {
+        const EMPTY: Vec<Timer> = Vec::new();
+        const LEVEL: [Vec<Timer>; SLOTS] = [EMPTY; SLOTS];
+        Wheel {
+            now: 0,
+            levels: [LEVEL; LEVELS],
+            index: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+
+    fn slot_for(&self, expires: u64) -> (usize, usize) {
+        let base = self.now + 1;
+        let delta = expires.saturating_sub(base).min(MAX_DELTA);
+        let expires = base + delta;
+        let level = (0..LEVELS)
+            .find(|&level| delta < 1 << (SLOT_BITS * (level as u32 + 1)))
+            .unwrap_or(LEVELS - 1);
+        let slot = (expires >> (SLOT_BITS * level as u32)) & SLOT_MASK;
+        (level, slot as usize)
+    }
+
+    fn insert(&mut self, timer: Timer) {
+        let (level, slot) = self.slot_for(timer.expires);
+        self.index.insert(timer.id, Place::Wheel(level, slot));
+        self.levels[level][slot].push(timer);
+    }
+
+    /// Takes timer `id` off the wheel, or keeps it from running if it's
+    /// due. Returns `false` if it's gone already.
+    fn cancel(&mut self, id: TimerId) -> bool {
+        match self.index.get_mut(&id) {
+            Some(&mut Place::Wheel(level, slot)) => {
+                self.index.remove(&id);
+                self.levels[level][slot].retain(|timer| timer.id != id);
+                true
+            }
+            Some(Place::Due { cancelled }) => !mem::replace(cancelled, true),
+            None => false,
+        }
+    }
+
+    /// Processes the next tick and returns the timers that expire on it.
+    fn advance(&mut self) -> Vec<Timer> {
+        let tick = self.now + 1;
+
+        // when level n - 1 wraps around, refill it from the next slot of level n
+        for level in 1..LEVELS {
+            if tick & ((1 << (SLOT_BITS * level as u32)) - 1) != 0 {
+                break;
+            }
+            let slot = ((tick >> (SLOT_BITS * level as u32)) & SLOT_MASK) as usize;
+            for timer in mem::take(&mut self.levels[level][slot]) {
+                self.insert(timer);
+            }
+        }
+
+        self.now = tick;
+        let expired = mem::take(&mut self.levels[0][(tick & SLOT_MASK) as usize]);
+        for timer in &expired {
+            self.index.insert(timer.id, Place::Due { cancelled: false });
+        }
+        expired
+    }
+}
+
+lazy_static! {
+    static ref WHEEL: Mutex<Wheel> = Mutex::new(Wheel::new());
+}
+
+fn add(delay: Duration, period: Option<Duration>, callback: Box<dyn FnMut() + Send>) -> TimerId {
+    let now = super::ticks();
+    let mut wheel = WHEEL.lock();
+    if wheel.index.is_empty() && wheel.now < now {
+        // nothing to catch up on, skip straight to the present
+        wheel.now = now;
+    }
+
+    let id = TimerId(wheel.next_id);
+    wheel.next_id += 1;
+    wheel.insert(Timer {
+        id,
+        expires: now.saturating_add(super::duration_to_ticks(delay).max(1)),
+        period: period.map(|period| super::duration_to_ticks(period).max(1)),
+        callback,
+    });
+    id
+}
+
+/// Runs `callback` once, `delay` from now.
+pub fn add_timer<F>(delay: Duration, callback: F) -> TimerId
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut callback = Some(callback);
+    add(
+        delay,
+        None,
+        Box::new(move || {
+            if let Some(callback) = callback.take() {
+                callback()
+            }
+        }),
+    )
+}
+
+/// Runs `callback` every `period`, starting one period from now, until the
+/// timer is cancelled.
+pub fn add_periodic<F>(period: Duration, callback: F) -> TimerId
+where
+    F: FnMut() + Send + 'static,
+{
+    add(period, Some(period), Box::new(callback))
+}
+
+/// Stops a timer. Returns `false` if it was cancelled before or, for a
+/// one-shot timer, if its callback has started running.
+///
+/// A periodic timer may cancel itself from its own callback.
+pub fn cancel_timer(id: TimerId) -> bool {
+    WHEEL.lock().cancel(id)
+}
+
+/// Number of timers waiting to fire.
+pub fn pending() -> usize {
+    WHEEL.lock().index.len()
+}
+
+/// Catches the wheel up with the tick clock and runs every callback that
+/// came due. This is the `Softirq::Timer` handler.
+pub(crate) fn run_expired() {
+    let target = super::ticks();
+    loop {
+        let expired = {
+            let mut wheel = WHEEL.lock();
+            if wheel.index.is_empty() {
+                wheel.now = wheel.now.max(target);
+            }
+            if wheel.now >= target {
+                return;
+            }
+            wheel.advance()
+        };
+
+        for mut timer in expired {
+            {
+                let mut wheel = WHEEL.lock();
+                // an earlier callback may have cancelled it
+                if let Some(Place::Due { cancelled: true }) = wheel.index.get(&timer.id) {
+                    wheel.index.remove(&timer.id);
+                    continue;
+                }
+                // a one-shot timer has fired as far as `cancel_timer` goes
+                if timer.period.is_none() {
+                    wheel.index.remove(&timer.id);
+                }
+            }
+            (timer.callback)();
+
+            if let Some(period) = timer.period {
+                let mut wheel = WHEEL.lock();
+                if let Some(Place::Due { cancelled: false }) = wheel.index.remove(&timer.id) {
+                    timer.expires = timer.expires.saturating_add(period);
+                    wheel.insert(timer);
+                }
+            }
+        }
+    }
+}
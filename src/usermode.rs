@@ -0,0 +1,108 @@
+//! Running code in ring 3.
+//!
+//! User code lives in its own part of the lower half, away from the kernel,
+//! the heap and the MMIO window, and only pages mapped with `map_user` are
+//! reachable from it. `enter` drops the CPU into ring 3; it comes back to
+//! the kernel through interrupts and exceptions, on the stack set with
+//! `gdt::set_kernel_stack`.
+
+use crate::{gdt, memory};
+use core::arch::asm;
+use x86_64::structures::paging::{
+    mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+/// First address user code may use.
+pub const USER_START: u64 = 0x0000_1000_0000_0000;
+/// End of the user range, exclusive.
+pub const USER_END: u64 = 0x0000_4000_0000_0000;
+
+/// RFLAGS user code starts with: interrupts enabled, plus the reserved bit
+/// that always reads as one.
+pub const USER_RFLAGS: u64 = 0x202;
+
+/// Whether `[start, start + len)` lies entirely within the user range.
+pub fn is_user_range(start: VirtAddr, len: u64) -> bool {
+    let start = start.as_u64();
+    match start.checked_add(len) {
+        Some(end) => start >= USER_START && end <= USER_END,
+        None => false,
+    }
+}
+
+/// Backs `[start, start + len)` with zeroed frames that ring 3 can access.
+///
+/// Pages are always readable; `flags` adds to that, e.g. `WRITABLE`. Fails
+/// with `PageAlreadyMapped` if the range overlaps an existing mapping, and
+/// panics if it leaves the user range.
+pub fn map_user(
+    start: VirtAddr,
+    len: u64,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    assert!(is_user_range(start, len), "{:?} + {:#x} isn't in the user range", start, len);
+    if len == 0 {
+        return Ok(());
+    }
+
+    let first = Page::<Size4KiB>::containing_address(start);
+    let last = Page::<Size4KiB>::containing_address(start + (len - 1));
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    for page in Page::range_inclusive(first, last) {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        // whatever the frame held before must not leak to user code
+        let frame_ptr: *mut u8 = memory::phys_to_virt(frame.start_address()).as_mut_ptr();
+        unsafe {
+            frame_ptr.write_bytes(0, 4096);
+            mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+        }
+    }
+    Ok(())
+}
+
+/// Jumps to `entry` in ring 3, with the stack pointer at `stack_top` and
+/// interrupts enabled.
+///
+/// # Safety
+///
+/// `entry` and the stack below `stack_top` must be mapped for user access,
+/// and the code at `entry` mustn't expect anything else of the machine
+/// state: all other registers hold whatever the kernel left in them.
+pub unsafe fn enter(entry: VirtAddr, stack_top: VirtAddr) -> ! {
+    let selectors = gdt::selectors();
+    let code = u64::from(selectors.user_code.0);
+    let data = u64::from(selectors.user_data.0);
+
+    // `iretq` pops rip, cs, rflags, rsp and ss, switching privilege level
+    // along the way
+    asm!(
+        "mov ds, {data:x}",
+        "mov es, {data:x}",
+        "push {data}",
+        "push {stack}",
+        "push {rflags}",
+        "push {code}",
+        "push {entry}",
+        "iretq",
+        data = in(reg) data,
+        stack = in(reg) stack_top.as_u64(),
+        rflags = in(reg) USER_RFLAGS,
+        code = in(reg) code,
+        entry = in(reg) entry.as_u64(),
+        options(noreturn),
+    );
+}
+
+#[test_case]
+fn test_user_range() {
+    assert!(is_user_range(VirtAddr::new(USER_START), 4096));
+    assert!(is_user_range(VirtAddr::new(USER_END - 4096), 4096));
+    assert!(!is_user_range(VirtAddr::new(USER_END - 4096), 4097));
+    assert!(!is_user_range(VirtAddr::new(USER_START - 1), 2));
+    assert!(!is_user_range(VirtAddr::new(0), 4096));
+}
@@ -1,10 +1,10 @@
 use core::fmt;
+use crate::sync::IrqSpinLock;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile;
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
+    pub static ref WRITER: IrqSpinLock<Writer> = IrqSpinLock::named("WRITER", Writer {
         column_position: 0,
         color_code: ColorCode::new(Color::Yellow, Color::Black),
         /// this is where our code accesses the hardware directly
@@ -176,11 +176,8 @@ macro_rules! println {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
-    interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
-    })
+    WRITER.lock().write_fmt(args).unwrap();
 }
 
 #[test_case]
@@ -198,17 +195,17 @@ fn test_println_many() {
 #[test_case]
 fn test_println_output() {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     let s = "Some test string that fits on a single line";
-    interrupts::without_interrupts(|| {
+    {
+        // holding the lock keeps the timer from printing in between
         let mut writer = WRITER.lock();
         writeln!(writer, "\n{}", s).expect("writeln failed");
         for (i, c) in s.chars().enumerate() {
             let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
             assert_eq!(char::from(screen_char.ascii_character), c);
         }
-    });
+    }
     println!("{}", s);
     
 }
@@ -0,0 +1,197 @@
+//! Lockup detection.
+//!
+//! Once started, the watchdog expects `pet` to be called regularly. The
+//! scheduler does that whenever it's asked to switch threads, and the idle
+//! thread, `idle_loop` and the executor ask every time they wake up, so it
+//! only starves if a single thread keeps the CPU without yielding, blocking
+//! or being preempted. Kernels without `thread::init` pet it themselves. If
+//! nothing pets it for the whole timeout, the CPU is considered stuck and a
+//! backtrace of whatever it was running is dumped over serial.
+//!
+//! The check runs from one of two places. By default that's the timer
+//! interrupt, which catches a kernel spinning with interrupts enabled but
+//! never fires if it spins with them disabled. If `init` can route a spare
+//! HPET comparator through the I/O APIC as a non-maskable interrupt, the
+//! check runs from the NMI handler instead and catches both.
+
+use crate::{hpet, ioapic, serial, serial_println, symbols, time};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use core::time::Duration;
+use x86_64::structures::idt::InterruptStackFrameValue;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Size4KiB};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Source {
+    /// Checked from the timer interrupt.
+    Tick,
+    /// Checked from a periodic NMI.
+    Nmi,
+}
+
+/// How often the NMI source checks in.
+const NMI_PERIOD: Duration = Duration::from_millis(100);
+
+/// Not an HPET timer number: the NMI source isn't set up.
+const NO_TIMER: u8 = u8::MAX;
+
+static SOURCE: AtomicU8 = AtomicU8::new(Source::Tick as u8);
+/// HPET comparator behind the NMI source, and whether it rearms itself.
+static NMI_TIMER: AtomicU8 = AtomicU8::new(NO_TIMER);
+static NMI_PERIODIC: AtomicBool = AtomicBool::new(false);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TIMEOUT_NANOS: AtomicU64 = AtomicU64::new(0);
+static HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+/// Heartbeat as of the previous check.
+static SEEN: AtomicU64 = AtomicU64::new(0);
+/// Checks in a row that found the heartbeat unchanged.
+static STALE_CHECKS: AtomicU64 = AtomicU64::new(0);
+/// Stale checks that add up to the timeout.
+static THRESHOLD: AtomicU64 = AtomicU64::new(u64::MAX);
+static LOCKUPS: AtomicU64 = AtomicU64::new(0);
+
+/// Sets up the NMI source if the hardware allows it, and returns the source
+/// the watchdog will use.
+///
+/// Needs the HPET, so call it after `hpet::init`. Without one, or without a
+/// comparator that can reach an I/O APIC input of its own, the watchdog stays
+/// on `Source::Tick`.
+pub fn init(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Source {
+    if source() == Source::Nmi {
+        return Source::Nmi;
+    }
+    let hpet = match hpet::get() {
+        Some(hpet) => hpet,
+        None => return Source::Tick,
+    };
+    let ioapic = match ioapic::init(mapper, frame_allocator) {
+        Ok(ioapic) => ioapic,
+        Err(_) => return Source::Tick,
+    };
+
+    // timers 0 and 1 belong to legacy replacement mode, and inputs below 16
+    // are shared with ISA devices, so look for a pair that's free of both
+    let route = (2..hpet.timers()).find_map(|timer| {
+        let usable = hpet.route_capabilities(timer) & !0xffff;
+        (16..ioapic.inputs().min(32))
+            .rev()
+            .find(|&pin| usable & (1 << pin) != 0)
+            .map(|pin| (timer, pin))
+    });
+    let (timer, pin) = match route {
+        Some(route) => route,
+        None => return Source::Tick,
+    };
+
+    let periodic = hpet.is_periodic_capable(timer);
+    NMI_TIMER.store(timer, Ordering::Relaxed);
+    NMI_PERIODIC.store(periodic, Ordering::Relaxed);
+    SOURCE.store(Source::Nmi as u8, Ordering::Release);
+
+    ioapic.route_nmi(pin);
+    hpet.set_route(timer, pin);
+    if periodic {
+        hpet.set_periodic(timer, NMI_PERIOD);
+    } else {
+        hpet.set_one_shot(timer, NMI_PERIOD);
+    }
+    Source::Nmi
+}
+
+/// Where the watchdog checks for lockups.
+pub fn source() -> Source {
+    match SOURCE.load(Ordering::Acquire) {
+        1 => Source::Nmi,
+        _ => Source::Tick,
+    }
+}
+
+/// Starts reporting if `pet` isn't called for `timeout`.
+pub fn start(timeout: Duration) {
+    let period = match source() {
+        Source::Tick => time::ticks_to_duration(1),
+        Source::Nmi => NMI_PERIOD,
+    };
+    let period = period.as_nanos().max(1);
+    let threshold = (timeout.as_nanos() + period - 1) / period;
+
+    ENABLED.store(false, Ordering::SeqCst);
+    TIMEOUT_NANOS.store(timeout.as_nanos() as u64, Ordering::Relaxed);
+    THRESHOLD.store((threshold as u64).max(1), Ordering::Relaxed);
+    SEEN.store(HEARTBEAT.load(Ordering::Relaxed), Ordering::Relaxed);
+    STALE_CHECKS.store(0, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn stop() {
+    ENABLED.store(false, Ordering::SeqCst);
+}
+
+/// Tells the watchdog the kernel is making progress.
+pub fn pet() {
+    HEARTBEAT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of lockups reported since boot.
+pub fn lockups() -> u64 {
+    LOCKUPS.load(Ordering::Relaxed)
+}
+
+/// Called from the timer interrupt with the interrupted frame pointer.
+pub(crate) fn on_tick(stack_frame: &InterruptStackFrameValue, rbp: u64) {
+    if source() == Source::Tick {
+        check(stack_frame, rbp);
+    }
+}
+
+/// Called from the NMI handler with the interrupted frame pointer. Returns
+/// `false` if the NMI wasn't the watchdog's.
+pub(crate) fn on_nmi(stack_frame: &InterruptStackFrameValue, rbp: u64) -> bool {
+    if source() != Source::Nmi {
+        return false;
+    }
+    if !NMI_PERIODIC.load(Ordering::Relaxed) {
+        if let Some(hpet) = hpet::get() {
+            hpet.set_one_shot(NMI_TIMER.load(Ordering::Relaxed), NMI_PERIOD);
+        }
+    }
+    check(stack_frame, rbp);
+    true
+}
+
+fn check(stack_frame: &InterruptStackFrameValue, rbp: u64) {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+    let heartbeat = HEARTBEAT.load(Ordering::Relaxed);
+    if SEEN.swap(heartbeat, Ordering::Relaxed) != heartbeat {
+        STALE_CHECKS.store(0, Ordering::Relaxed);
+        return;
+    }
+    if STALE_CHECKS.fetch_add(1, Ordering::Relaxed) + 1 < THRESHOLD.load(Ordering::Relaxed) {
+        return;
+    }
+
+    // report, then give it another full timeout before reporting again
+    STALE_CHECKS.store(0, Ordering::Relaxed);
+    LOCKUPS.fetch_add(1, Ordering::Relaxed);
+    report(stack_frame, rbp);
+}
+
+fn report(stack_frame: &InterruptStackFrameValue, rbp: u64) {
+    let rip = stack_frame.instruction_pointer.as_u64();
+    let stuck_for = Duration::from_nanos(TIMEOUT_NANOS.load(Ordering::Relaxed));
+
+    // whoever holds the serial port is likely the code that's stuck
+    unsafe { serial::force_unlock() };
+    serial_println!("watchdog: no progress for {:?}", stuck_for);
+    match symbols::lookup(rip) {
+        Some(symbol) => serial_println!("  stuck at {:#x} {}", rip, symbol),
+        None => serial_println!("  stuck at {:#x}", rip),
+    }
+    crate::backtrace::serial_print_from(rbp);
+}
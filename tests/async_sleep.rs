@@ -0,0 +1,105 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use mooos::task::executor::Executor;
+use mooos::task::timer::{self, Elapsed};
+use mooos::task::Task;
+use mooos::time;
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+async fn sleep_and_record(log: Arc<Mutex<Vec<u32>>>, id: u32, millis: u64) {
+    timer::sleep(Duration::from_millis(millis)).await;
+    log.lock().push(id);
+}
+
+#[test_case]
+fn sleepers_wake_in_deadline_order() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut executor = Executor::new();
+    for &(id, millis) in &[(1, 30), (2, 10), (3, 50), (4, 20), (5, 40)] {
+        executor.spawn(Task::new(sleep_and_record(log.clone(), id, millis)));
+    }
+    executor.run_until_complete();
+    assert_eq!(*log.lock(), [2, 4, 1, 5, 3]);
+}
+
+#[test_case]
+fn sleep_waits_at_least_duration() {
+    let mut executor = Executor::new();
+    let start = time::ticks();
+    executor.spawn(Task::new(timer::sleep(Duration::from_millis(20))));
+    executor.run_until_complete();
+    assert!(time::ticks() - start >= time::duration_to_ticks(Duration::from_millis(20)));
+}
+
+#[test_case]
+fn zero_sleep_is_ready_at_once() {
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(timer::sleep(Duration::from_millis(0))));
+    executor.run_until_idle();
+    assert_eq!(executor.tasks(), 0);
+}
+
+#[test_case]
+fn timeout_elapses() {
+    let result = Arc::new(Mutex::new(None));
+    let mut executor = Executor::new();
+    let slot = result.clone();
+    executor.spawn(Task::new(async move {
+        let slow = timer::sleep(Duration::from_millis(100));
+        *slot.lock() = Some(timer::timeout(slow, Duration::from_millis(10)).await);
+    }));
+    executor.run_until_complete();
+    assert_eq!(*result.lock(), Some(Err(Elapsed)));
+}
+
+#[test_case]
+fn timeout_passes_result_through() {
+    let result = Arc::new(Mutex::new(None));
+    let mut executor = Executor::new();
+    let slot = result.clone();
+    executor.spawn(Task::new(async move {
+        let fast = async {
+            timer::sleep(Duration::from_millis(10)).await;
+            7
+        };
+        *slot.lock() = Some(timer::timeout(fast, Duration::from_millis(100)).await);
+    }));
+    executor.run_until_complete();
+    assert_eq!(*result.lock(), Some(Ok(7)));
+}
@@ -0,0 +1,156 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use mooos::channel::{self, Message};
+use mooos::syscall::Error;
+use mooos::task::executor::Executor;
+use mooos::task::simple_executor::SimpleExecutor;
+use mooos::task::Task;
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+/// Returns `Pending` `count` times, waking itself each time.
+struct YieldNow {
+    count: usize,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.count == 0 {
+            return Poll::Ready(());
+        }
+        self.count -= 1;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+async fn record(log: Arc<Mutex<Vec<u32>>>, id: u32, yields: usize) {
+    YieldNow { count: yields }.await;
+    log.lock().push(id);
+}
+
+#[test_case]
+fn simple_executor_runs_to_completion() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut executor = SimpleExecutor::new();
+    executor.spawn(Task::new(record(log.clone(), 1, 3)));
+    executor.spawn(Task::new(record(log.clone(), 2, 0)));
+    executor.run();
+    assert_eq!(*log.lock(), [2, 1]);
+}
+
+#[test_case]
+fn executor_polls_woken_tasks() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(record(log.clone(), 1, 2)));
+    executor.spawn(Task::new(record(log.clone(), 2, 1)));
+    executor.spawn(Task::new(record(log.clone(), 3, 0)));
+    executor.run_until_idle();
+    assert_eq!(*log.lock(), [3, 2, 1]);
+    assert_eq!(executor.tasks(), 0);
+}
+
+/// Pending until something outside the executor wakes it.
+struct WaitForFlag {
+    flag: Arc<Mutex<(bool, Option<core::task::Waker>)>>,
+}
+
+impl Future for WaitForFlag {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let mut flag = self.flag.lock();
+        if flag.0 {
+            Poll::Ready(())
+        } else {
+            flag.1 = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[test_case]
+fn executor_leaves_unwoken_tasks_alone() {
+    let flag = Arc::new(Mutex::new((false, None)));
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(WaitForFlag { flag: flag.clone() }));
+    executor.run_until_idle();
+    assert_eq!(executor.tasks(), 1);
+
+    let waker = {
+        let mut flag = flag.lock();
+        flag.0 = true;
+        flag.1.take().unwrap()
+    };
+    waker.wake();
+    executor.run_until_idle();
+    assert_eq!(executor.tasks(), 0);
+}
+
+#[test_case]
+fn tasks_receive_from_channels() {
+    let (sender, receiver) = channel::channel();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let received = log.clone();
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        let error = loop {
+            match receiver.recv_async().await {
+                Ok(message) => received.lock().push(message.data[0]),
+                Err(error) => break error,
+            }
+        };
+        assert_eq!(error, Error::BrokenPipe);
+    }));
+    executor.run_until_idle();
+    assert_eq!(executor.tasks(), 1);
+
+    sender.send(Message::new(&[1])).unwrap();
+    sender.send(Message::new(&[2])).unwrap();
+    executor.run_until_idle();
+    assert_eq!(*log.lock(), [1, 2]);
+    // closing wakes it too
+    drop(sender);
+    executor.run_until_idle();
+    assert_eq!(executor.tasks(), 0);
+}
@@ -0,0 +1,166 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use mooos::sync::{Condvar, Mutex, RwLock, Semaphore, WaitQueue};
+use mooos::thread;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+fn wait_for_others() {
+    while thread::count() > 1 {
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn wait_queue_blocks_until_notified() {
+    static QUEUE: WaitQueue = WaitQueue::new();
+    static READY: AtomicBool = AtomicBool::new(false);
+    static WOKE: AtomicBool = AtomicBool::new(false);
+
+    thread::spawn(|| {
+        QUEUE.wait_until(|| READY.load(Ordering::Relaxed));
+        WOKE.store(true, Ordering::Relaxed);
+    })
+    .unwrap();
+    while QUEUE.is_empty() {
+        thread::yield_now();
+    }
+    // blocked, so it doesn't come back however often we yield
+    for _ in 0..10 {
+        thread::yield_now();
+    }
+    assert!(!WOKE.load(Ordering::Relaxed));
+
+    READY.store(true, Ordering::Relaxed);
+    assert!(QUEUE.notify_one());
+    wait_for_others();
+    assert!(WOKE.load(Ordering::Relaxed));
+}
+
+#[test_case]
+fn mutex_serializes_increments() {
+    let counter = Arc::new(Mutex::new(0usize));
+    for _ in 0..4 {
+        let counter = counter.clone();
+        thread::spawn(move || {
+            for _ in 0..100 {
+                let mut value = counter.lock();
+                let read = *value;
+                // invite a switch while holding the lock
+                thread::yield_now();
+                *value = read + 1;
+            }
+        })
+        .unwrap();
+    }
+    wait_for_others();
+    assert_eq!(*counter.lock(), 400);
+}
+
+#[test_case]
+fn semaphore_limits_concurrency() {
+    static SEMAPHORE: Semaphore = Semaphore::new(2);
+    static INSIDE: AtomicUsize = AtomicUsize::new(0);
+    static MOST_INSIDE: AtomicUsize = AtomicUsize::new(0);
+
+    for _ in 0..5 {
+        thread::spawn(|| {
+            SEMAPHORE.acquire();
+            let inside = INSIDE.fetch_add(1, Ordering::SeqCst) + 1;
+            MOST_INSIDE.fetch_max(inside, Ordering::SeqCst);
+            for _ in 0..5 {
+                thread::yield_now();
+            }
+            INSIDE.fetch_sub(1, Ordering::SeqCst);
+            SEMAPHORE.release();
+        })
+        .unwrap();
+    }
+    wait_for_others();
+    assert_eq!(MOST_INSIDE.load(Ordering::SeqCst), 2);
+    assert_eq!(SEMAPHORE.available(), 2);
+}
+
+#[test_case]
+fn condvar_hands_items_to_consumer() {
+    let queue = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+    let received = Arc::new(AtomicUsize::new(0));
+
+    let (consumer_queue, consumer_received) = (queue.clone(), received.clone());
+    thread::spawn(move || {
+        let (items, ready) = &*consumer_queue;
+        for expected in 0..10 {
+            let mut items = ready.wait_while(items.lock(), |items| items.is_empty());
+            assert_eq!(items.pop_front(), Some(expected));
+            consumer_received.fetch_add(1, Ordering::Relaxed);
+        }
+    })
+    .unwrap();
+
+    let (items, ready) = &*queue;
+    for item in 0..10 {
+        items.lock().push_back(item);
+        ready.notify_one();
+        thread::yield_now();
+    }
+    wait_for_others();
+    assert_eq!(received.load(Ordering::Relaxed), 10);
+}
+
+#[test_case]
+fn rwlock_writer_waits_for_readers() {
+    static LOCK: RwLock<u32> = RwLock::new(0);
+    static WRITTEN: AtomicBool = AtomicBool::new(false);
+
+    let reader = LOCK.read();
+    thread::spawn(|| {
+        *LOCK.write() = 1;
+        WRITTEN.store(true, Ordering::Relaxed);
+    })
+    .unwrap();
+    for _ in 0..10 {
+        thread::yield_now();
+    }
+    assert!(!WRITTEN.load(Ordering::Relaxed));
+    assert_eq!(*reader, 0);
+
+    drop(reader);
+    wait_for_others();
+    assert!(WRITTEN.load(Ordering::Relaxed));
+    assert_eq!(*LOCK.read(), 1);
+}
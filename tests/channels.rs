@@ -0,0 +1,172 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use mooos::channel::{self, Message, CHANNEL_CAPACITY};
+use mooos::file::{self, File, FileTable, MAX_FILES};
+use mooos::process::{self, ExitStatus};
+use mooos::syscall::Error;
+use mooos::{memory, pipe, thread};
+use x86_64::VirtAddr;
+
+/// Built from tests/user/channels.s.
+const CHANNELS_ELF: &[u8] = include_bytes!("user/channels.elf");
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::BootInfoFrameAllocator;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    thread::init();
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+fn wait_for_others() {
+    while thread::count() > 1 {
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn receives_in_order_or_not_at_all() {
+    let (first, second) = channel::channel();
+    assert_eq!(second.try_recv().err(), Some(Error::TryAgain));
+    first.send(Message::new(b"one")).unwrap();
+    first.send(Message::new(b"two")).unwrap();
+    assert_eq!(&second.try_recv().unwrap().data[..4], b"one\0");
+    assert_eq!(&second.recv().unwrap().data[..4], b"two\0");
+    assert_eq!(second.try_recv().err(), Some(Error::TryAgain));
+    // and the other way
+    second.send(Message::new(b"back")).unwrap();
+    assert_eq!(&first.recv().unwrap().data[..4], b"back");
+}
+
+#[test_case]
+fn closing_an_endpoint_breaks_the_channel() {
+    let (first, second) = channel::channel();
+    first.send(Message::new(b"last")).unwrap();
+    drop(first);
+    assert_eq!(&second.recv().unwrap().data[..4], b"last");
+    assert_eq!(second.recv().err(), Some(Error::BrokenPipe));
+    assert_eq!(second.try_recv().err(), Some(Error::BrokenPipe));
+    assert_eq!(second.send(Message::new(b"lost")), Err(Error::BrokenPipe));
+}
+
+#[test_case]
+fn sender_blocks_while_the_queue_is_full() {
+    static SENT: AtomicUsize = AtomicUsize::new(0);
+    let (first, second) = channel::channel();
+    thread::spawn(move || {
+        for i in 0..CHANNEL_CAPACITY + 2 {
+            first.send(Message::new(&[i as u8])).unwrap();
+            SENT.fetch_add(1, Ordering::Relaxed);
+        }
+    })
+    .unwrap();
+    for _ in 0..10 {
+        thread::yield_now();
+    }
+    assert_eq!(SENT.load(Ordering::Relaxed), CHANNEL_CAPACITY);
+
+    for i in 0..CHANNEL_CAPACITY + 2 {
+        assert_eq!(second.recv().unwrap().data[0], i as u8);
+    }
+    wait_for_others();
+    assert_eq!(SENT.load(Ordering::Relaxed), CHANNEL_CAPACITY + 2);
+}
+
+#[test_case]
+fn service_replies_on_a_channel_it_was_handed() {
+    // a service that doubles numbers, replying on whatever endpoint comes
+    // with each request
+    let (service, client) = channel::channel();
+    thread::spawn(move || {
+        while let Ok(request) = service.recv() {
+            let reply = request.handle.expect("no reply endpoint");
+            let reply = reply.as_endpoint().expect("not an endpoint");
+            reply.send(Message::new(&[request.data[0] * 2])).unwrap();
+        }
+    })
+    .unwrap();
+
+    for number in 1..4 {
+        let (ours, theirs) = channel::channel();
+        client.send(Message::new(&[number]).with_handle(Arc::new(theirs))).unwrap();
+        assert_eq!(ours.recv().unwrap().data[0], number * 2);
+        // the service dropped its end with the request
+        assert_eq!(ours.recv().err(), Some(Error::BrokenPipe));
+    }
+    drop(client);
+    wait_for_others();
+}
+
+#[test_case]
+fn hands_over_other_files() {
+    let (first, second) = channel::channel();
+    let (reader, writer) = pipe::pipe();
+    first.send(Message::new(b"pipe").with_handle(Arc::new(writer))).unwrap();
+    let writer = second.recv().unwrap().handle.expect("no handle");
+    assert!(writer.as_endpoint().is_none());
+    assert_eq!(writer.write(b"moo"), Ok(3));
+    let mut buf = [0; 3];
+    assert_eq!(reader.read(&mut buf), Ok(3));
+    assert_eq!(&buf, b"moo");
+}
+
+#[test_case]
+fn refuses_to_carry_its_own_endpoints() {
+    let (first, second) = channel::channel();
+    let second = Arc::new(second);
+    let message = Message::new(b"self").with_handle(second.clone());
+    assert_eq!(first.send(message), Err(Error::InvalidArgument));
+    assert_eq!(second.try_recv().err(), Some(Error::TryAgain));
+    // endpoints of other channels are fine
+    let (other, _) = channel::channel();
+    first.send(Message::new(b"other").with_handle(Arc::new(other))).unwrap();
+    assert!(second.recv().unwrap().handle.is_some());
+}
+
+#[test_case]
+fn user_programs_pass_messages_and_handles() {
+    let pid = process::spawn(CHANNELS_ELF, &["channels"], &[]).expect("spawn failed");
+    assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Exited(5))));
+}
+
+#[test_case]
+fn keeps_messages_for_a_full_file_table() {
+    let (ours, theirs) = channel::channel();
+    let mut files = FileTable::with_console();
+    assert_eq!(files.open(Arc::new(theirs)), Ok(3));
+    for _ in 4..MAX_FILES {
+        files.open(Arc::new(file::Serial)).unwrap();
+    }
+    ours.send(Message::new(b"moo").with_handle(Arc::new(file::Serial))).unwrap();
+    let pid = process::spawn_with_files(CHANNELS_ELF, &["channels", "full"], &[], files)
+        .expect("spawn failed");
+    assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Exited(4))));
+}
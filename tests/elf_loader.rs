@@ -0,0 +1,106 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::convert::TryInto;
+use core::panic::PanicInfo;
+use mooos::elf::ElfError;
+use mooos::loader::{self, LoadError};
+use mooos::{memory, thread, usermode};
+use x86_64::VirtAddr;
+
+/// Built from tests/user/args.s.
+const ARGS_ELF: &[u8] = include_bytes!("user/args.elf");
+/// Where args.s leaves its findings: `result` in its BSS.
+const RESULT: u64 = 0x1000_0000_1116;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::BootInfoFrameAllocator;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    thread::init();
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+fn wait_for_others() {
+    while thread::count() > 1 {
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn runs_with_arguments() {
+    let program = loader::load(ARGS_ELF, &["args", "hello!"], &["HOME=/"]).expect("load failed");
+    assert!(usermode::is_user_range(program.entry, 1));
+    assert_eq!(program.stack_pointer.as_u64() % 16, 0);
+
+    let address_space = program.address_space.clone();
+    let (entry, stack_pointer) = (program.entry, program.stack_pointer);
+    thread::spawn_in(program.address_space, move || unsafe {
+        usermode::enter(entry, stack_pointer)
+    })
+    .unwrap();
+    wait_for_others();
+
+    let mut result = [0u8; 40];
+    address_space.read(VirtAddr::new(RESULT), &mut result).unwrap();
+    let word = |i: usize| u64::from_le_bytes(result[i * 8..i * 8 + 8].try_into().unwrap());
+    assert_eq!(word(0), 2, "argc");
+    assert_eq!(word(1), u64::from(b'h'), "argv[1]");
+    assert_eq!(word(2), 6, "write");
+    assert_eq!(word(3), 0x1122_3344_5566_7788, "data segment");
+    assert_eq!(word(4), u64::from(b'H'), "envp[0]");
+}
+
+#[test_case]
+fn frees_frames_with_the_address_space() {
+    let free_frames = || memory::with_frames(|frames| frames.free_frames()).unwrap();
+    let program = loader::load(ARGS_ELF, &["args"], &[]).expect("load failed");
+    // loading reuses free frames first, so count from here
+    let loaded = free_frames();
+    drop(program);
+    // two segments, the stack, and at least four levels of page tables
+    assert!(free_frames() >= loaded + 2 + 16 + 4);
+}
+
+#[test_case]
+fn rejects_bad_images() {
+    assert_eq!(loader::load(&ARGS_ELF[..32], &[], &[]).err(), Some(LoadError::Elf(ElfError::Truncated)));
+
+    let mut image = alloc::vec::Vec::from(ARGS_ELF);
+    image[0] = b'X';
+    assert_eq!(loader::load(&image, &[], &[]).err(), Some(LoadError::Elf(ElfError::BadMagic)));
+
+    let too_long = alloc::vec![b'x'; loader::STACK_SIZE as usize];
+    let arg = core::str::from_utf8(&too_long).unwrap();
+    assert_eq!(loader::load(ARGS_ELF, &[arg], &[]).err(), Some(LoadError::ArgumentsTooLong));
+}
+
+#[test_case]
+fn spawn_runs_to_exit() {
+    loader::spawn(ARGS_ELF, &["args", "again!"], &["X=1"]).expect("spawn failed");
+    wait_for_others();
+}
@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use mooos::hpet;
+use mooos::time;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    hpet::init(&mut mapper, &mut frame_allocator).expect("hpet initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+#[test_case]
+fn counter_advances() {
+    let hpet = hpet::get().unwrap();
+    let start = hpet.counter();
+    time::sleep_ticks(2);
+    assert!(hpet.counter() > start);
+}
+
+#[test_case]
+fn counter_matches_tick_clock() {
+    let hpet = hpet::get().unwrap();
+    let start = hpet.counter();
+    time::sleep_ticks(11);
+    let elapsed = Duration::from_nanos(hpet.counter_to_nanos(hpet.counter() - start));
+    assert!(elapsed >= time::ticks_to_duration(10));
+}
+
+#[test_case]
+fn drives_timer_interrupt() {
+    hpet::use_as_system_timer(time::DEFAULT_FREQUENCY_HZ).expect("legacy replacement unsupported");
+    assert_eq!(time::frequency(), time::DEFAULT_FREQUENCY_HZ);
+
+    let hpet = hpet::get().unwrap();
+    let start = hpet.counter();
+    time::sleep_ticks(10);
+    let elapsed = Duration::from_nanos(hpet.counter_to_nanos(hpet.counter() - start));
+    assert!(elapsed >= time::ticks_to_duration(9));
+}
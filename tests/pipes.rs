@@ -0,0 +1,117 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use mooos::file::File;
+use mooos::pipe::{self, PIPE_SIZE};
+use mooos::process::{self, ExitStatus};
+use mooos::signal::{SIGKILL, SIGPIPE, SIGTERM};
+use mooos::syscall::Error;
+use mooos::{memory, thread};
+use x86_64::VirtAddr;
+
+/// Built from tests/user/pipes.s.
+const PIPES_ELF: &[u8] = include_bytes!("user/pipes.elf");
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::BootInfoFrameAllocator;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    thread::init();
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+#[test_case]
+fn writer_blocks_until_reader_drains() {
+    const LEN: usize = PIPE_SIZE * 2 + 100;
+    let (reader, writer) = pipe::pipe();
+    thread::spawn(move || {
+        let data: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+        assert_eq!(writer.write(&data), Ok(LEN));
+    })
+    .unwrap();
+
+    let mut received = Vec::new();
+    let mut buf = [0; 1000];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => received.extend_from_slice(&buf[..len]),
+            Err(error) => panic!("read failed: {:?}", error),
+        }
+    }
+    assert_eq!(received.len(), LEN);
+    assert!(received.iter().enumerate().all(|(i, &byte)| byte == i as u8));
+}
+
+#[test_case]
+fn reads_what_is_left_after_the_writer_closes() {
+    let (reader, writer) = pipe::pipe();
+    assert_eq!(writer.write(b"moo"), Ok(3));
+    drop(writer);
+    let mut buf = [0; 2];
+    assert_eq!(reader.read(&mut buf), Ok(2));
+    assert_eq!(&buf, b"mo");
+    assert_eq!(reader.read(&mut buf), Ok(1));
+    assert_eq!(buf[0], b'o');
+    assert_eq!(reader.read(&mut buf), Ok(0));
+}
+
+#[test_case]
+fn writing_without_a_reader_fails() {
+    let (reader, writer) = pipe::pipe();
+    drop(reader);
+    assert_eq!(writer.write(b"moo"), Err(Error::BrokenPipe));
+    assert_eq!(writer.write(b""), Ok(0));
+}
+
+#[test_case]
+fn user_programs_talk_through_pipes() {
+    let cases = [
+        (&["pipes"][..], ExitStatus::Exited(3)),
+        (&["pipes", "broken"][..], ExitStatus::Exited(32)),
+        (&["pipes", "sigpipe"][..], ExitStatus::Killed(SIGPIPE)),
+    ];
+    for (argv, status) in cases.iter() {
+        let pid = process::spawn(PIPES_ELF, argv, &[]).expect("spawn failed");
+        assert_eq!(process::waitpid(Some(pid)), Ok((pid, *status)), "{:?}", argv);
+    }
+}
+
+#[test_case]
+fn kill_wakes_a_blocked_reader() {
+    for &signal in &[SIGKILL, SIGTERM] {
+        let pid = process::spawn(PIPES_ELF, &["pipes", "hang"], &[]).expect("spawn failed");
+        // let it block in `read`
+        for _ in 0..10 {
+            thread::yield_now();
+        }
+        process::kill(pid, signal).expect("kill failed");
+        assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Killed(signal))), "{}", signal);
+    }
+}
@@ -0,0 +1,213 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use mooos::file::{self, File, FileTable};
+use mooos::process::{self, ExitStatus, Pid, WaitError};
+use mooos::signal::{SIGCHLD, SIGFPE, SIGILL, SIGINT, SIGKILL, SIGSEGV, SIGTERM};
+use mooos::syscall::Error;
+use mooos::task::{executor::Executor, Task};
+use mooos::{fs, keyboard, memory, thread, time};
+use x86_64::VirtAddr;
+
+/// Built from tests/user/exits.s.
+const EXITS_ELF: &[u8] = include_bytes!("user/exits.elf");
+/// Built from tests/user/forks.s; execs /bin/exits.
+const FORKS_ELF: &[u8] = include_bytes!("user/forks.elf");
+/// Built from tests/user/signals.s.
+const SIGNALS_ELF: &[u8] = include_bytes!("user/signals.elf");
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::BootInfoFrameAllocator;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    thread::init();
+    memory::init_global(mapper, frame_allocator);
+    fs::install("/bin/exits", EXITS_ELF);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+fn spawn(argv: &[&str]) -> Pid {
+    process::spawn(EXITS_ELF, argv, &[]).expect("spawn failed")
+}
+
+#[test_case]
+fn waits_for_exit_codes() {
+    let first = spawn(&["exits"]);
+    let second = spawn(&["exits", "a", "b"]);
+    assert_ne!(first, second);
+    assert_eq!(process::waitpid(Some(second)), Ok((second, ExitStatus::Exited(3))));
+    assert_eq!(process::wait(), Ok((first, ExitStatus::Exited(1))));
+    assert_eq!(process::wait(), Err(WaitError::NoChildren));
+    assert_eq!(process::waitpid(Some(first)), Err(WaitError::NoChildren));
+}
+
+#[test_case]
+fn kills_on_faults() {
+    for &(how, signal) in &[("fault", SIGSEGV), ("ud2", SIGILL), ("div", SIGFPE)] {
+        let pid = spawn(&["exits", how]);
+        assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Killed(signal))), "{}", how);
+    }
+}
+
+#[test_case]
+fn kill_stops_a_running_process() {
+    let pid = spawn(&["exits", "spin"]);
+    // let it get to user mode
+    thread::yield_now();
+    process::kill(pid, SIGKILL).expect("kill failed");
+    assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Killed(SIGKILL))));
+    assert!(process::kill(pid, SIGKILL).is_err());
+}
+
+#[test_case]
+fn sleeps_while_its_parent_waits() {
+    let start = time::uptime();
+    let pid = spawn(&["exits", "nap"]);
+    // the boot thread sits in `waitpid`, so nothing runs softirqs meanwhile
+    assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Exited(2))));
+    assert!(time::uptime() - start >= Duration::from_millis(20));
+}
+
+#[test_case]
+fn frees_threads_once_reaped() {
+    let pid = spawn(&["exits"]);
+    process::waitpid(Some(pid)).unwrap();
+    // it may still be on its way out, and is freed once it's gone
+    while thread::count() > 1 {
+        thread::yield_now();
+    }
+    assert!(process::current().is_none());
+}
+
+#[test_case]
+fn forks_and_execs() {
+    let free_frames = || memory::with_frames(|frames| frames.free_frames()).unwrap();
+    let before = free_frames();
+    let pid = process::spawn(FORKS_ELF, &["forks"], &[]).expect("spawn failed");
+    // the child exited with argc 3, and its write didn't reach the parent
+    assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Exited(31))));
+    while thread::count() > 1 {
+        thread::yield_now();
+    }
+    // frames shared or copied went back with the address spaces
+    assert!(free_frames() >= before);
+}
+
+#[test_case]
+fn fork_needs_a_process() {
+    assert_eq!(process::fork(|| {}), Err(process::ForkError::NotAProcess));
+    assert_eq!(
+        process::exec("/bin/exits", &[], &[]),
+        Err(process::ExecError::NotAProcess)
+    );
+}
+
+#[test_case]
+fn installs_and_looks_up_files() {
+    fs::install("/test/fs", b"contents");
+    assert_eq!(fs::lookup("/test/fs"), Some(&b"contents"[..]));
+    assert!(fs::paths().iter().any(|path| path == "/test/fs"));
+    assert!(fs::remove("/test/fs"));
+    assert_eq!(fs::lookup("/test/fs"), None);
+    assert!(!fs::remove("/test/fs"));
+}
+
+#[test_case]
+fn file_table_reuses_lowest_index() {
+    let mut table = FileTable::with_console();
+    assert_eq!(table.open(Arc::new(file::Serial)), Ok(3));
+    table.close(1).unwrap();
+    assert_eq!(table.open(Arc::new(file::Serial)), Ok(1));
+    assert!(table.get(1).is_ok());
+    assert_eq!(table.close(7), Err(Error::BadFileDescriptor));
+    assert!(table.get(7).is_err());
+}
+
+#[test_case]
+fn default_actions() {
+    let pid = spawn(&["exits", "spin"]);
+    thread::yield_now();
+    // ignored unless caught
+    process::kill(pid, SIGCHLD).expect("kill failed");
+    process::kill(pid, SIGTERM).expect("kill failed");
+    assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Killed(SIGTERM))));
+}
+
+#[test_case]
+fn ctrl_c_interrupts_a_read() {
+    let pid = spawn(&["exits", "read"]);
+    assert_eq!(keyboard::foreground(), pid);
+    // let it block in `read`
+    for _ in 0..10 {
+        thread::yield_now();
+    }
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(keyboard::print_keypresses()));
+    // Ctrl down, C down, C up, Ctrl up
+    for &scancode in &[0x1d, 0x2e, 0xae, 0x9d] {
+        keyboard::add_scancode(scancode);
+    }
+    executor.run_until_idle();
+    assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Killed(SIGINT))));
+}
+
+#[test_case]
+fn runs_signal_handlers() {
+    for &(how, code) in &[("raise", 2), ("segv", 111), ("child", 4)] {
+        let pid = process::spawn(SIGNALS_ELF, &["signals", how], &[]).expect("spawn failed");
+        assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Exited(code))), "{}", how);
+    }
+}
+
+/// Standard output that only notes that something was written.
+struct Ready(AtomicBool);
+
+impl File for Ready {
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        self.0.store(true, Ordering::Release);
+        Ok(buf.len())
+    }
+}
+
+#[test_case]
+fn interrupts_user_code_with_a_signal() {
+    let ready = Arc::new(Ready(AtomicBool::new(false)));
+    let mut files = FileTable::with_console();
+    files.close(1).unwrap();
+    files.open(ready.clone()).unwrap();
+    let pid = process::spawn_with_files(SIGNALS_ELF, &["signals", "wait"], &[], files)
+        .expect("spawn failed");
+    // it has a handler once it says so
+    while !ready.0.load(Ordering::Acquire) {
+        thread::yield_now();
+    }
+    process::kill(pid, SIGINT).expect("kill failed");
+    assert_eq!(process::waitpid(Some(pid)), Ok((pid, ExitStatus::Exited(3))));
+}
@@ -0,0 +1,179 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use mooos::thread::{self, Priority};
+use mooos::{serial_println, time};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    thread::init();
+    serial_println!("scheduling policy: {}", thread::policy());
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+/// Yields until every other thread has exited. Drops to the lowest priority
+/// meanwhile, or a fixed priority policy would keep picking us.
+fn wait_for_others() {
+    thread::set_priority(Priority::MIN);
+    while thread::count() > 1 {
+        thread::yield_now();
+    }
+    thread::set_priority(Priority::NORMAL);
+}
+
+/// Spins for `ticks` timer ticks without yielding.
+fn busy_for(ticks: u64) {
+    let end = time::ticks() + ticks;
+    while time::ticks() < end {
+        core::hint::spin_loop();
+    }
+}
+
+#[test_case]
+fn stats_count_runtime_and_switches() {
+    let id = thread::spawn(|| {
+        busy_for(5);
+        thread::yield_now();
+    })
+    .unwrap();
+    thread::yield_now();
+    let table = thread::table();
+    let row = table.get(id).expect("thread missing from table");
+    assert!(row.switches >= 1);
+    assert!(row.runtime >= time::ticks_to_duration(4));
+    wait_for_others();
+}
+
+#[test_case]
+fn prints_table() {
+    thread::print_table();
+    let text = format!("{}", thread::table());
+    assert!(text.contains("(running)"));
+}
+
+/// Runs a low and a high priority busy thread side by side and returns how
+/// many loops each got done.
+#[cfg(not(feature = "sched-priority"))]
+fn race_priorities() -> (u64, u64) {
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static LOW: AtomicU64 = AtomicU64::new(0);
+    static HIGH: AtomicU64 = AtomicU64::new(0);
+
+    for &(priority, counter) in &[(Priority::MIN, &LOW), (Priority::MAX, &HIGH)] {
+        thread::spawn_with_priority(priority, move || {
+            while !STOP.load(Ordering::Relaxed) {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .unwrap();
+    }
+    thread::set_priority(Priority::MIN);
+    busy_for(200);
+    STOP.store(true, Ordering::Relaxed);
+    wait_for_others();
+    (LOW.load(Ordering::Relaxed), HIGH.load(Ordering::Relaxed))
+}
+
+#[cfg(feature = "sched-priority")]
+#[test_case]
+fn higher_priority_starves_lower() {
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static LOW: AtomicU64 = AtomicU64::new(0);
+    static LOW_DURING_HIGH: AtomicU64 = AtomicU64::new(u64::MAX);
+
+    thread::spawn_with_priority(Priority::MIN, || {
+        while !STOP.load(Ordering::Relaxed) {
+            LOW.fetch_add(1, Ordering::Relaxed);
+        }
+    })
+    .unwrap();
+    // preempts us on the next tick and doesn't let anyone else in
+    thread::spawn_with_priority(Priority::MAX, || {
+        busy_for(50);
+        LOW_DURING_HIGH.store(LOW.load(Ordering::Relaxed), Ordering::Relaxed);
+    })
+    .unwrap();
+    busy_for(100);
+    assert_eq!(LOW_DURING_HIGH.load(Ordering::Relaxed), 0);
+
+    // down at its level, the low priority thread gets its turns
+    thread::set_priority(Priority::MIN);
+    busy_for(50);
+    STOP.store(true, Ordering::Relaxed);
+    wait_for_others();
+    assert!(LOW.load(Ordering::Relaxed) > 0);
+}
+
+#[cfg(feature = "sched-fair")]
+#[test_case]
+fn higher_priority_gets_bigger_share() {
+    let (low, high) = race_priorities();
+    assert!(low > 0);
+    assert!(high > low * 2);
+}
+
+/// Two high priority threads and a low priority one, with us at low
+/// priority too: preempting a high priority thread mustn't send it behind
+/// the low priority ones, or they all end up taking turns.
+#[cfg(feature = "sched-fair")]
+#[test_case]
+fn preempted_threads_keep_their_place() {
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static COUNTS: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+    let priorities = [Priority::MAX, Priority::MAX, Priority::MIN];
+    for (&priority, counter) in priorities.iter().zip(COUNTS.iter()) {
+        thread::spawn_with_priority(priority, move || {
+            while !STOP.load(Ordering::Relaxed) {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+        .unwrap();
+    }
+    thread::set_priority(Priority::MIN);
+    busy_for(200);
+    STOP.store(true, Ordering::Relaxed);
+    wait_for_others();
+    let [first, second, low] = [0, 1, 2].map(|i| COUNTS[i].load(Ordering::Relaxed));
+    assert!(low > 0);
+    // by weight each high one gets nearly five times as much; taking turns
+    // would make it about the same
+    assert!(first > low * 3 && second > low * 3, "{} {} {}", first, second, low);
+}
+
+#[cfg(not(any(feature = "sched-priority", feature = "sched-fair")))]
+#[test_case]
+fn round_robin_ignores_priority() {
+    let (low, high) = race_priorities();
+    assert!(low > 0 && high > 0);
+    assert!(high < low * 2 && low < high * 2);
+}
@@ -0,0 +1,170 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use mooos::syscall::Error;
+use mooos::{memory, thread, time, usermode};
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/// The test programs are loaded here...
+const CODE: u64 = usermode::USER_START;
+/// ...leave their results here...
+const DATA: u64 = usermode::USER_START + 0x1000;
+/// ...and run on a stack ending here.
+const STACK_TOP: u64 = usermode::USER_START + 0x10_0000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::{self, BootInfoFrameAllocator};
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    thread::init();
+
+    // the code page is writable so the kernel can copy programs in
+    let rw = PageTableFlags::WRITABLE;
+    usermode::map_user(VirtAddr::new(CODE), 0x2000, rw, &mut mapper, &mut frame_allocator)
+        .expect("mapping user code and data failed");
+    usermode::map_user(VirtAddr::new(STACK_TOP - 0x1000), 0x1000, rw, &mut mapper, &mut frame_allocator)
+        .expect("mapping user stack failed");
+    memory::init_global(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+/// Runs `program` in ring 3 on a thread of its own until it exits, and
+/// returns the first few words it left at `DATA`.
+fn run(program: &[u8]) -> [u64; 5] {
+    unsafe {
+        core::ptr::copy_nonoverlapping(program.as_ptr(), CODE as *mut u8, program.len());
+        (DATA as *mut u64).write_bytes(0, 5);
+    }
+    thread::spawn(|| unsafe { usermode::enter(VirtAddr::new(CODE), VirtAddr::new(STACK_TOP)) })
+        .expect("spawning the user thread failed");
+    while thread::count() > 1 {
+        thread::yield_now();
+    }
+    unsafe { (DATA as *const [u64; 5]).read() }
+}
+
+#[test_case]
+fn calls_and_errors() {
+    // movabs rbx, DATA
+    // mov r8, 0x5a5a
+    // mov eax, GETPID; syscall; mov [rbx], rax
+    // mov [rbx + 32], r8
+    // mov eax, WRITE; mov edi, 2; lea rsi, [rip + msg]; mov edx, 18; syscall
+    // mov [rbx + 8], rax
+    // mov eax, WRITE; mov edi, 2; mov esi, 0x1000; mov edx, 1; syscall
+    // mov [rbx + 16], rax
+    // mov eax, 99; syscall; mov [rbx + 24], rax
+    // mov eax, EXIT; xor edi, edi; syscall
+    // ud2
+    // msg: .ascii "hello from ring 3\n"
+    const PROGRAM: &[u8] = &[
+        0x48, 0xbb, 0x00, 0x10, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x49, 0xc7,
+        0xc0, 0x5a, 0x5a, 0x00, 0x00, 0xb8, 0x05, 0x00, 0x00, 0x00, 0x0f, 0x05,
+        0x48, 0x89, 0x03, 0x4c, 0x89, 0x43, 0x20, 0xb8, 0x00, 0x00, 0x00, 0x00,
+        0xbf, 0x02, 0x00, 0x00, 0x00, 0x48, 0x8d, 0x35, 0x3b, 0x00, 0x00, 0x00,
+        0xba, 0x12, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x89, 0x43, 0x08, 0xb8,
+        0x00, 0x00, 0x00, 0x00, 0xbf, 0x02, 0x00, 0x00, 0x00, 0xbe, 0x00, 0x10,
+        0x00, 0x00, 0xba, 0x01, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x89, 0x43,
+        0x10, 0xb8, 0x63, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x89, 0x43, 0x18,
+        0xb8, 0x02, 0x00, 0x00, 0x00, 0x31, 0xff, 0x0f, 0x05, 0x0f, 0x0b, 0x68,
+        0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x66, 0x72, 0x6f, 0x6d, 0x20, 0x72, 0x69,
+        0x6e, 0x67, 0x20, 0x33, 0x0a,
+    ];
+
+    let [pid, written, bad_address, no_such_call, r8] = run(PROGRAM);
+    assert_ne!(pid, thread::current().as_u64());
+    assert_eq!(written, 18);
+    assert_eq!(bad_address as i64, Error::BadAddress.code());
+    assert_eq!(no_such_call as i64, Error::NoSuchSystemCall.code());
+    assert_eq!(r8, 0x5a5a, "syscall clobbered r8");
+}
+
+#[test_case]
+fn nested_task_flag_stays_in_user_mode() {
+    // movabs rbx, DATA
+    // pushfq; or qword ptr [rsp], 0x4000; popfq
+    // mov eax, YIELD; syscall
+    // mov eax, GETPID; syscall; mov [rbx], rax
+    // pushfq; pop qword ptr [rbx + 8]
+    // mov eax, EXIT; xor edi, edi; syscall
+    // ud2
+    const PROGRAM: &[u8] = &[
+        0x48, 0xbb, 0x00, 0x10, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x9c, 0x48,
+        0x81, 0x0c, 0x24, 0x00, 0x40, 0x00, 0x00, 0x9d, 0xb8, 0x03, 0x00, 0x00,
+        0x00, 0x0f, 0x05, 0xb8, 0x05, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x89,
+        0x03, 0x9c, 0x8f, 0x43, 0x08, 0xb8, 0x02, 0x00, 0x00, 0x00, 0x31, 0xff,
+        0x0f, 0x05, 0x0f, 0x0b,
+    ];
+
+    // with NT still set in the kernel, the first iretq there would fault
+    let [pid, flags, ..] = run(PROGRAM);
+    assert_ne!(pid, 0);
+    assert_ne!(flags & RFlags::NESTED_TASK.bits(), 0, "sysret lost the user's NT");
+}
+
+#[test_case]
+fn unmapped_user_memory_is_inaccessible() {
+    let unmapped = VirtAddr::new(usermode::USER_END - 0x1000);
+    assert!(!memory::user_accessible(unmapped, 8, false));
+    assert!(!memory::user_accessible(unmapped, 8, true));
+}
+
+#[test_case]
+fn mmap_and_sleep() {
+    // movabs rbx, DATA
+    // mov eax, MMAP; movabs rdi, CODE + 0x20_0000; mov esi, 0x2000
+    // mov edx, MMAP_WRITE; syscall; mov [rbx], rax
+    // movabs rdi, CODE + 0x20_1000; mov qword ptr [rdi], 42; mov rax, [rdi]
+    // mov [rbx + 8], rax
+    // mov eax, MMAP; movabs rdi, CODE + 0x20_0000; mov esi, 0x1000
+    // mov edx, MMAP_WRITE; syscall; mov [rbx + 16], rax
+    // mov eax, SLEEP; mov edi, 50; syscall; mov [rbx + 24], rax
+    // mov eax, EXIT; xor edi, edi; syscall
+    // ud2
+    const PROGRAM: &[u8] = &[
+        0x48, 0xbb, 0x00, 0x10, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0xb8, 0x04,
+        0x00, 0x00, 0x00, 0x48, 0xbf, 0x00, 0x00, 0x20, 0x00, 0x00, 0x10, 0x00,
+        0x00, 0xbe, 0x00, 0x20, 0x00, 0x00, 0xba, 0x01, 0x00, 0x00, 0x00, 0x0f,
+        0x05, 0x48, 0x89, 0x03, 0x48, 0xbf, 0x00, 0x10, 0x20, 0x00, 0x00, 0x10,
+        0x00, 0x00, 0x48, 0xc7, 0x07, 0x2a, 0x00, 0x00, 0x00, 0x48, 0x8b, 0x07,
+        0x48, 0x89, 0x43, 0x08, 0xb8, 0x04, 0x00, 0x00, 0x00, 0x48, 0xbf, 0x00,
+        0x00, 0x20, 0x00, 0x00, 0x10, 0x00, 0x00, 0xbe, 0x00, 0x10, 0x00, 0x00,
+        0xba, 0x01, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48, 0x89, 0x43, 0x10, 0xb8,
+        0x06, 0x00, 0x00, 0x00, 0xbf, 0x32, 0x00, 0x00, 0x00, 0x0f, 0x05, 0x48,
+        0x89, 0x43, 0x18, 0xb8, 0x02, 0x00, 0x00, 0x00, 0x31, 0xff, 0x0f, 0x05,
+        0x0f, 0x0b,
+    ];
+
+    let start = time::uptime();
+    let [mapped, read_back, remapped, slept, _] = run(PROGRAM);
+    assert_eq!(mapped, CODE + 0x20_0000);
+    assert_eq!(read_back, 42);
+    assert_eq!(remapped as i64, Error::InvalidArgument.code());
+    assert_eq!(slept, 0);
+    assert!(time::uptime() - start >= Duration::from_millis(50));
+}
@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use mooos::{thread, time};
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    thread::init();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+/// Yields until only the boot thread is left and everything else was freed.
+fn wait_for_others() {
+    while thread::count() > 1 {
+        thread::yield_now();
+    }
+}
+
+#[test_case]
+fn spawned_threads_run_and_exit() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    for id in 0..3 {
+        let log = log.clone();
+        thread::spawn(move || log.lock().push(id)).unwrap();
+    }
+    wait_for_others();
+    let mut log = log.lock().clone();
+    log.sort();
+    assert_eq!(log, [0, 1, 2]);
+}
+
+#[test_case]
+fn threads_have_their_own_ids() {
+    let boot = thread::current();
+    let seen = Arc::new(Mutex::new(None));
+    let slot = seen.clone();
+    let spawned = thread::spawn(move || *slot.lock() = Some(thread::current())).unwrap();
+    wait_for_others();
+    assert_eq!(*seen.lock(), Some(spawned));
+    assert_ne!(spawned, boot);
+    assert_eq!(thread::current(), boot);
+}
+
+#[test_case]
+fn yielding_threads_take_turns() {
+    static TURNS: AtomicUsize = AtomicUsize::new(0);
+    for _ in 0..2 {
+        thread::spawn(|| {
+            for _ in 0..5 {
+                TURNS.fetch_add(1, Ordering::Relaxed);
+                thread::yield_now();
+            }
+        })
+        .unwrap();
+    }
+    wait_for_others();
+    assert_eq!(TURNS.load(Ordering::Relaxed), 10);
+}
+
+#[test_case]
+fn timer_preempts_busy_threads() {
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static RAN: AtomicBool = AtomicBool::new(false);
+
+    // never yields, so the other two only get to run if it is preempted
+    thread::spawn(|| {
+        while !STOP.load(Ordering::Relaxed) {
+            core::hint::spin_loop();
+        }
+    })
+    .unwrap();
+    thread::spawn(|| RAN.store(true, Ordering::Relaxed)).unwrap();
+
+    // and neither does the boot thread
+    let deadline = time::ticks() + 1000;
+    while !RAN.load(Ordering::Relaxed) && time::ticks() < deadline {
+        core::hint::spin_loop();
+    }
+    STOP.store(true, Ordering::Relaxed);
+    wait_for_others();
+    assert!(RAN.load(Ordering::Relaxed));
+}
+
+#[test_case]
+fn too_many_threads() {
+    static RELEASE: AtomicBool = AtomicBool::new(false);
+    let mut spawned = 0;
+    while thread::spawn(|| {
+        while !RELEASE.load(Ordering::Relaxed) {
+            thread::yield_now();
+        }
+    })
+    .is_ok()
+    {
+        spawned += 1;
+    }
+    assert_eq!(spawned, thread::MAX_THREADS - 1);
+    RELEASE.store(true, Ordering::Relaxed);
+    wait_for_others();
+}
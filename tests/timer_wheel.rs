@@ -0,0 +1,166 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use mooos::softirq;
+use mooos::time::{self, wheel};
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+/// Sleeps for `duration` while servicing softirqs, like the idle loop does.
+fn run_for(duration: Duration) {
+    let deadline = time::ticks() + time::duration_to_ticks(duration);
+    while time::ticks() < deadline {
+        x86_64::instructions::hlt();
+        softirq::run_pending();
+    }
+}
+
+#[test_case]
+fn one_shot_fires_once() {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let counter = fired.clone();
+    wheel::add_timer(Duration::from_millis(5), move || {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+
+    run_for(Duration::from_millis(2));
+    assert_eq!(fired.load(Ordering::Relaxed), 0);
+    run_for(Duration::from_millis(10));
+    assert_eq!(fired.load(Ordering::Relaxed), 1);
+    assert_eq!(wheel::pending(), 0);
+}
+
+#[test_case]
+fn fire_in_deadline_order() {
+    let order = Arc::new(Mutex::new(Vec::new()));
+    // far enough apart that some have to cascade down from level 1
+    for &ms in &[150, 20, 90, 5] {
+        let order = order.clone();
+        wheel::add_timer(Duration::from_millis(ms), move || order.lock().push(ms));
+    }
+
+    run_for(Duration::from_millis(200));
+    assert_eq!(*order.lock(), [5, 20, 90, 150]);
+}
+
+#[test_case]
+fn cancelled_timer_does_not_fire() {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let counter = fired.clone();
+    let id = wheel::add_timer(Duration::from_millis(5), move || {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+
+    assert!(wheel::cancel_timer(id));
+    assert!(!wheel::cancel_timer(id));
+    run_for(Duration::from_millis(10));
+    assert_eq!(fired.load(Ordering::Relaxed), 0);
+}
+
+#[test_case]
+fn cancelling_a_due_timer_stops_it() {
+    static LATER: Mutex<Option<wheel::TimerId>> = Mutex::new(None);
+    static CANCELLED: AtomicUsize = AtomicUsize::new(0);
+    let fired = Arc::new(AtomicUsize::new(0));
+    // both come due on the same tick, and the first cancels the second
+    // while it's off the wheel waiting its turn
+    let first = wheel::add_timer(Duration::from_millis(5), || {
+        if wheel::cancel_timer(LATER.lock().unwrap()) {
+            CANCELLED.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    let counter = fired.clone();
+    *LATER.lock() = Some(wheel::add_timer(Duration::from_millis(5), move || {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }));
+
+    run_for(Duration::from_millis(10));
+    assert_eq!(CANCELLED.load(Ordering::Relaxed), 1);
+    assert_eq!(fired.load(Ordering::Relaxed), 0);
+    // and a one-shot timer that ran can't be cancelled any more
+    assert!(!wheel::cancel_timer(first));
+    assert_eq!(wheel::pending(), 0);
+}
+
+#[test_case]
+fn one_shot_timer_running_counts_as_fired() {
+    static ID: Mutex<Option<wheel::TimerId>> = Mutex::new(None);
+    static CANCELLED: AtomicUsize = AtomicUsize::new(0);
+    *ID.lock() = Some(wheel::add_timer(Duration::from_millis(2), || {
+        if wheel::cancel_timer(ID.lock().unwrap()) {
+            CANCELLED.fetch_add(1, Ordering::Relaxed);
+        }
+    }));
+
+    run_for(Duration::from_millis(10));
+    assert_eq!(CANCELLED.load(Ordering::Relaxed), 0);
+}
+
+#[test_case]
+fn periodic_timer_repeats_until_cancelled() {
+    let fired = Arc::new(AtomicUsize::new(0));
+    let counter = fired.clone();
+    let id = wheel::add_periodic(Duration::from_millis(5), move || {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+
+    run_for(Duration::from_millis(27));
+    let count = fired.load(Ordering::Relaxed);
+    assert!(count >= 4 && count <= 6, "fired {} times", count);
+
+    assert!(wheel::cancel_timer(id));
+    run_for(Duration::from_millis(10));
+    assert_eq!(fired.load(Ordering::Relaxed), count);
+}
+
+#[test_case]
+fn periodic_timer_cancels_itself() {
+    static ID: Mutex<Option<wheel::TimerId>> = Mutex::new(None);
+    let fired = Arc::new(AtomicUsize::new(0));
+    let counter = fired.clone();
+    let id = wheel::add_periodic(Duration::from_millis(2), move || {
+        if counter.fetch_add(1, Ordering::Relaxed) == 2 {
+            wheel::cancel_timer(ID.lock().unwrap());
+        }
+    });
+    *ID.lock() = Some(id);
+
+    run_for(Duration::from_millis(20));
+    assert_eq!(fired.load(Ordering::Relaxed), 3);
+    assert_eq!(wheel::pending(), 0);
+}
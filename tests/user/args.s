@@ -0,0 +1,43 @@
+# Reports what it finds on its initial stack, for tests/elf_loader.rs.
+#
+# Rebuild args.elf with:
+#   as --64 args.s -o args.o
+#   ld -static -n -T args.ld -z noexecstack args.o -o args.elf
+#   strip -s args.elf
+
+.intel_syntax noprefix
+.text
+.global _start
+_start:
+    # argc
+    mov rax, [rsp]
+    mov [rip + result], rax
+    # first byte of argv[1]
+    mov rsi, [rsp + 16]
+    movzx eax, byte ptr [rsi]
+    mov [rip + result + 8], rax
+    # write(2, argv[1], 6)
+    mov eax, 0
+    mov edi, 2
+    mov edx, 6
+    syscall
+    mov [rip + result + 16], rax
+    # initialized data
+    mov rax, [rip + marker]
+    mov [rip + result + 24], rax
+    # first byte of envp[0], which follows argv and its terminating null
+    mov rcx, [rsp]
+    mov rsi, [rsp + rcx * 8 + 16]
+    movzx eax, byte ptr [rsi]
+    mov [rip + result + 32], rax
+    # exit(0)
+    mov eax, 2
+    xor edi, edi
+    syscall
+    ud2
+
+.data
+marker: .quad 0x1122334455667788
+
+.bss
+result: .skip 40
@@ -0,0 +1,139 @@
+# Passes a message through a channel, for tests/channels.rs. Finds nothing
+# waiting at first, then forks a child that sends "moo" with its standard
+# output as the handle and exits. The parent receives that, waits for the
+# child, closes its own copy of the sending endpoint and checks that
+# receiving now fails with -32. It exits with the fd the handle got, 5 as
+# 0 to 4 are taken, or with 99 if anything went wrong.
+#
+# With an argument, it expects to be started with a full file table and a
+# message waiting on fd 3, carrying a handle. Receiving must fail with -24
+# and leave the message be; once fd 4 is closed, it exits with the fd the
+# handle got, so 4.
+#
+# Rebuild channels.elf with:
+#   as --64 channels.s -o channels.o
+#   ld -static -n -T args.ld -z noexecstack channels.o -o channels.elf
+#   strip -s channels.elf
+
+.intel_syntax noprefix
+.text
+.global _start
+_start:
+    cmp qword ptr [rsp], 2
+    jae full
+    # channel(&fds)
+    lea rdi, [rip + fds]
+    mov eax, 17
+    syscall
+    test rax, rax
+    jnz fail
+    # recv(fds[0], &received, RECV_NONBLOCK)
+    mov edi, [rip + fds]
+    lea rsi, [rip + received]
+    mov edx, 1
+    mov eax, 19
+    syscall
+    cmp rax, -11
+    jne fail
+    # fork()
+    mov eax, 8
+    syscall
+    test rax, rax
+    js fail
+    jz child
+    mov r12, rax
+    # recv(fds[0], &received, 0)
+    mov edi, [rip + fds]
+    lea rsi, [rip + received]
+    xor edx, edx
+    mov eax, 19
+    syscall
+    test rax, rax
+    jnz fail
+    mov eax, [rip + received]
+    cmp eax, [rip + sent]
+    jne fail
+    # wait(child, 0)
+    mov rdi, r12
+    xor esi, esi
+    mov eax, 7
+    syscall
+    cmp rax, r12
+    jne fail
+    # close(fds[1]), the last of that endpoint
+    mov edi, [rip + fds + 4]
+    mov eax, 15
+    syscall
+    test rax, rax
+    jnz fail
+    # recv(fds[0], &scratch, 0)
+    mov edi, [rip + fds]
+    lea rsi, [rip + scratch]
+    xor edx, edx
+    mov eax, 19
+    syscall
+    cmp rax, -32
+    jne fail
+    mov rdi, [rip + received + 64]
+    jmp exit
+
+full:
+    # recv(3, &received, 0)
+    mov edi, 3
+    lea rsi, [rip + received]
+    xor edx, edx
+    mov eax, 19
+    syscall
+    cmp rax, -24
+    jne fail
+    # close(4)
+    mov edi, 4
+    mov eax, 15
+    syscall
+    test rax, rax
+    jnz fail
+    # recv(3, &received, 0)
+    mov edi, 3
+    lea rsi, [rip + received]
+    xor edx, edx
+    mov eax, 19
+    syscall
+    test rax, rax
+    jnz fail
+    mov eax, [rip + received]
+    cmp eax, [rip + sent]
+    jne fail
+    mov rdi, [rip + received + 64]
+    jmp exit
+
+child:
+    # send(fds[1], &sent)
+    mov edi, [rip + fds + 4]
+    lea rsi, [rip + sent]
+    mov eax, 18
+    syscall
+    test rax, rax
+    jnz fail
+    xor edi, edi
+    jmp exit
+
+fail:
+    mov edi, 99
+exit:
+    # exit(rdi)
+    mov eax, 2
+    syscall
+    ud2
+
+.data
+fds:
+    .long 0, 0
+# "moo" with standard output as the handle
+sent:
+    .ascii "moo"
+    .zero 61
+    .quad 1
+received:
+    .zero 72
+scratch:
+    .zero 72
@@ -0,0 +1,75 @@
+# Ends in whatever way argv[1] asks for, for tests/processes.rs:
+#   "fault"  writes to unmapped memory
+#   "ud2"    runs an invalid instruction
+#   "div"    divides by zero
+#   "spin"   loops until killed
+#   "nap"    sleeps for 20 ms before exiting
+#   "read"   reads a byte from standard input before exiting
+# and otherwise exits with argc as its status.
+#
+# Rebuild exits.elf with:
+#   as --64 exits.s -o exits.o
+#   ld -static -n -T args.ld -z noexecstack exits.o -o exits.elf
+#   strip -s exits.elf
+
+.intel_syntax noprefix
+.text
+.global _start
+_start:
+    mov rdi, [rsp]
+    cmp rdi, 2
+    jb exit
+    mov rsi, [rsp + 16]
+    movzx eax, byte ptr [rsi]
+    cmp al, 'f'
+    je fault
+    cmp al, 'u'
+    je invalid
+    cmp al, 'd'
+    je divide
+    cmp al, 's'
+    je spin
+    cmp al, 'n'
+    je nap
+    cmp al, 'r'
+    je read
+exit:
+    # exit(argc)
+    mov eax, 2
+    syscall
+    ud2
+fault:
+    # the page below the user range
+    mov rax, 0x0fffffffffff
+    mov qword ptr [rax], 1
+    ud2
+invalid:
+    ud2
+divide:
+    xor edx, edx
+    xor ecx, ecx
+    mov eax, 1
+    div ecx
+    ud2
+spin:
+    jmp spin
+nap:
+    # sleep(20), keeping argc for the exit
+    push rdi
+    mov edi, 20
+    mov eax, 6
+    syscall
+    pop rdi
+    jmp exit
+read:
+    # read(0, buf on the stack, 1), keeping argc for the exit
+    push rdi
+    push 0
+    xor edi, edi
+    mov rsi, rsp
+    mov edx, 1
+    mov eax, 1
+    syscall
+    pop rdi
+    pop rdi
+    jmp exit
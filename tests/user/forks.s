@@ -0,0 +1,62 @@
+# Forks; the child writes to memory it shares with the parent, then execs
+# /bin/exits with two arguments. The parent waits for it and exits with
+# ten times the child's exit code plus what it still sees in that memory,
+# so 31 if all went well. For tests/processes.rs.
+#
+# Rebuild forks.elf with:
+#   as --64 forks.s -o forks.o
+#   ld -static -n -T args.ld -z noexecstack forks.o -o forks.elf
+#   strip -s forks.elf
+
+.intel_syntax noprefix
+.text
+.global _start
+_start:
+    # fork()
+    mov eax, 8
+    syscall
+    test rax, rax
+    js fail
+    jz child
+    mov [rip + child_pid], rax
+    # wait(child, &status), with the status on the stack
+    sub rsp, 16
+    mov rdi, rax
+    mov rsi, rsp
+    mov eax, 7
+    syscall
+    cmp rax, [rip + child_pid]
+    jne fail
+    # exit(10 * exit code + value)
+    mov eax, [rsp]
+    shr eax, 8
+    imul edi, eax, 10
+    add edi, [rip + value]
+    mov eax, 2
+    syscall
+    ud2
+child:
+    mov qword ptr [rip + value], 2
+    # exec("/bin/exits", argv, null)
+    lea rdi, [rip + path]
+    lea rsi, [rip + argv]
+    xor edx, edx
+    mov eax, 9
+    syscall
+fail:
+    # exit(255)
+    mov edi, 255
+    mov eax, 2
+    syscall
+    ud2
+
+.data
+value: .quad 1
+argv: .quad arg0, arg1, arg2, 0
+path: .asciz "/bin/exits"
+arg0: .asciz "exits"
+arg1: .asciz "a"
+arg2: .asciz "b"
+
+.bss
+child_pid: .skip 8
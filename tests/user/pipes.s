@@ -0,0 +1,145 @@
+# Uses a pipe, for tests/pipes.rs. Without arguments it forks a child that
+# writes "moo" into the pipe and exits; the parent reads to the end of the
+# file and exits with the number of bytes it read, so 3, or 99 if they
+# weren't "moo". With argv[1]:
+#   "broken"  ignores SIGPIPE, writes to a pipe with no read end, and
+#             exits with the errno it got, 32
+#   "sigpipe" does the same without ignoring SIGPIPE, which kills it
+#   "hang"    reads from the pipe while it holds the write end itself, so
+#             it waits until killed
+#
+# Rebuild pipes.elf with:
+#   as --64 pipes.s -o pipes.o
+#   ld -static -n -T args.ld -z noexecstack pipes.o -o pipes.elf
+#   strip -s pipes.elf
+
+.intel_syntax noprefix
+.text
+.global _start
+_start:
+    # pipe(&fds)
+    lea rdi, [rip + fds]
+    mov eax, 14
+    syscall
+    test rax, rax
+    jnz fail
+    cmp qword ptr [rsp], 2
+    jb talk
+    mov rsi, [rsp + 16]
+    cmp byte ptr [rsi], 'h'
+    je hang
+    cmp byte ptr [rsi], 'b'
+    jne broken
+    # sigaction(SIGPIPE, {SIG_IGN}, 0)
+    mov edi, 13
+    lea rsi, [rip + ignore]
+    xor edx, edx
+    mov eax, 10
+    syscall
+    test rax, rax
+    jnz fail
+broken:
+    # close(read end)
+    mov edi, [rip + fds]
+    mov eax, 15
+    syscall
+    test rax, rax
+    jnz fail
+    # write(write end, "moo", 3)
+    mov edi, [rip + fds + 4]
+    lea rsi, [rip + moo]
+    mov edx, 3
+    xor eax, eax
+    syscall
+    neg rax
+    mov rdi, rax
+    jmp exit
+
+talk:
+    # fork()
+    mov eax, 8
+    syscall
+    test rax, rax
+    js fail
+    jz child
+    mov r12, rax
+    # close(write end), or the read below would never see the end
+    mov edi, [rip + fds + 4]
+    mov eax, 15
+    syscall
+    test rax, rax
+    jnz fail
+    xor ebx, ebx
+read:
+    # read(read end, buf + rbx, 16 - rbx)
+    mov edi, [rip + fds]
+    lea rsi, [rip + buf]
+    add rsi, rbx
+    mov edx, 16
+    sub rdx, rbx
+    xor eax, eax
+    inc eax
+    syscall
+    test rax, rax
+    js fail
+    jz done
+    add rbx, rax
+    jmp read
+done:
+    # wait(child, 0)
+    mov rdi, r12
+    xor esi, esi
+    mov eax, 7
+    syscall
+    cmp rax, r12
+    jne fail
+    cmp rbx, 3
+    jne fail
+    mov eax, [rip + buf]
+    cmp eax, [rip + moo]
+    jne fail
+    mov rdi, rbx
+    jmp exit
+
+child:
+    # write(write end, "moo", 3)
+    mov edi, [rip + fds + 4]
+    lea rsi, [rip + moo]
+    mov edx, 3
+    xor eax, eax
+    syscall
+    cmp rax, 3
+    jne fail
+    xor edi, edi
+    jmp exit
+
+hang:
+    # read(read end, buf, 16)
+    mov edi, [rip + fds]
+    lea rsi, [rip + buf]
+    mov edx, 16
+    xor eax, eax
+    inc eax
+    syscall
+    jmp fail
+
+fail:
+    mov edi, 99
+exit:
+    # exit(rdi)
+    mov eax, 2
+    syscall
+    ud2
+
+.data
+fds:
+    .long 0, 0
+# SIG_IGN
+ignore:
+    .quad 1, 0, 0
+# zero padded, so that it compares with the first four bytes of buf
+moo:
+    .ascii "moo"
+    .byte 0
+buf:
+    .zero 16
@@ -0,0 +1,220 @@
+# Catches signals, for tests/processes.rs. argv[1] picks what to do:
+#   "raise"  sends itself SIGUSR1, then again while it's blocked, and
+#            exits with the number of times its handler ran, 2
+#   "segv"   faults, with a SIGSEGV handler that exits with 100 + the
+#            signal, so 111
+#   "wait"   writes "ready" to standard output and spins until SIGINT,
+#            then exits with 3
+#   "child"  forks a child that exits right away, waits for it, and exits
+#            with 4 once its SIGCHLD handler has run
+# Registers it sets must survive the handlers; anything going wrong exits
+# with 99.
+#
+# Rebuild signals.elf with:
+#   as --64 signals.s -o signals.o
+#   ld -static -n -T args.ld -z noexecstack signals.o -o signals.elf
+#   strip -s signals.elf
+
+.intel_syntax noprefix
+.text
+.global _start
+_start:
+    mov rdi, [rsp]
+    cmp rdi, 2
+    jb fail
+    mov rsi, [rsp + 16]
+    movzx eax, byte ptr [rsi]
+    cmp al, 'r'
+    je raise
+    cmp al, 's'
+    je segv
+    cmp al, 'w'
+    je wait
+    cmp al, 'c'
+    je child
+fail:
+    mov edi, 99
+exit:
+    # exit(rdi)
+    mov eax, 2
+    syscall
+    ud2
+
+raise:
+    mov edi, 10
+    lea rsi, [rip + count]
+    call catch
+    call fill
+    call raise_usr1
+    cmp qword ptr [rip + caught], 1
+    jne fail
+    # sigprocmask(SIG_BLOCK, &set, 0) with SIGUSR1 in the set
+    mov qword ptr [rip + set], 1 << 9
+    xor edi, edi
+    lea rsi, [rip + set]
+    xor edx, edx
+    mov eax, 11
+    syscall
+    test rax, rax
+    jnz fail
+    call raise_usr1
+    cmp qword ptr [rip + caught], 1
+    jne fail
+    # sigprocmask(SIG_UNBLOCK, &set, 0) delivers it on the way out
+    mov edi, 1
+    lea rsi, [rip + set]
+    xor edx, edx
+    mov eax, 11
+    syscall
+    cmp qword ptr [rip + last], 10
+    jne fail
+    call check
+    mov rdi, [rip + caught]
+    jmp exit
+
+segv:
+    mov edi, 11
+    lea rsi, [rip + exit_with_signal]
+    call catch
+    # the page below the user range
+    mov rax, 0x0fffffffffff
+    mov qword ptr [rax], 1
+    jmp fail
+
+wait:
+    mov edi, 2
+    lea rsi, [rip + count]
+    call catch
+    # write(1, "ready", 5)
+    mov edi, 1
+    lea rsi, [rip + ready]
+    mov edx, 5
+    xor eax, eax
+    syscall
+    cmp rax, 5
+    jne fail
+    call fill
+    # the timer interrupt delivers it, and r11 and rcx must survive that too
+    mov r11, rbx
+    mov rcx, rbx
+spin:
+    cmp qword ptr [rip + caught], 0
+    je spin
+    cmp r11, rbx
+    jne fail
+    cmp rcx, rbx
+    jne fail
+    cmp qword ptr [rip + last], 2
+    jne fail
+    call check
+    mov edi, 3
+    jmp exit
+
+child:
+    mov edi, 17
+    lea rsi, [rip + count]
+    call catch
+    # fork()
+    mov eax, 8
+    syscall
+    test rax, rax
+    js fail
+    jz child_exits
+    # wait(child, 0)
+    mov rdi, rax
+    xor esi, esi
+    mov eax, 7
+    syscall
+    test rax, rax
+    js fail
+    cmp qword ptr [rip + caught], 1
+    jne fail
+    cmp qword ptr [rip + last], 17
+    jne fail
+    mov edi, 4
+    jmp exit
+child_exits:
+    xor edi, edi
+    jmp exit
+
+# sigaction(rdi, {handler: rsi, mask: 0, restorer}, 0)
+catch:
+    mov [rip + action], rsi
+    lea rax, [rip + restorer]
+    mov [rip + action + 16], rax
+    lea rsi, [rip + action]
+    xor edx, edx
+    mov eax, 10
+    syscall
+    test rax, rax
+    jnz fail
+    ret
+
+# kill(getpid(), SIGUSR1)
+raise_usr1:
+    mov eax, 5
+    syscall
+    mov rdi, rax
+    mov esi, 10
+    mov eax, 12
+    syscall
+    test rax, rax
+    jnz fail
+    ret
+
+# puts a pattern in the callee-saved registers, for check
+fill:
+    mov rbx, 0x0123456789abcdef
+    mov rbp, rbx
+    mov r12, rbx
+    mov r13, rbx
+    mov r14, rbx
+    mov r15, rbx
+    ret
+
+check:
+    mov rax, 0x0123456789abcdef
+    cmp rbx, rax
+    jne fail
+    cmp rbp, rax
+    jne fail
+    cmp r12, rax
+    jne fail
+    cmp r13, rax
+    jne fail
+    cmp r14, rax
+    jne fail
+    cmp r15, rax
+    jne fail
+    ret
+
+# handlers, which get the signal in rdi and trash what they like
+count:
+    inc qword ptr [rip + caught]
+    mov [rip + last], rdi
+    xor ebx, ebx
+    xor r12d, r12d
+    xor r11d, r11d
+    ret
+
+exit_with_signal:
+    add edi, 100
+    jmp exit
+
+restorer:
+    # sigreturn()
+    mov eax, 13
+    syscall
+    ud2
+
+.data
+action:
+    .quad 0, 0, 0
+set:
+    .quad 0
+caught:
+    .quad 0
+last:
+    .quad 0
+ready:
+    .ascii "ready"
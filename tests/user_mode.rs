@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use mooos::{exit_qemu, gdt, serial_print, serial_println, usermode, QemuExitCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::{PrivilegeLevel, VirtAddr};
+
+const ENTRY: u64 = usermode::USER_START;
+const STACK_TOP: u64 = usermode::USER_START + 0x10_0000;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::instructions::port::Port;
+
+    serial_print!("user_mode::int3_from_ring_3...\t");
+
+    gdt::init();
+    TEST_IDT.load();
+    // user code runs with interrupts enabled, and nothing here handles IRQs
+    unsafe {
+        Port::<u8>::new(0x21).write(0xff);
+        Port::<u8>::new(0xa1).write(0xff);
+    }
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    // writable, so the kernel can put the code there
+    usermode::map_user(VirtAddr::new(ENTRY), 1, PageTableFlags::WRITABLE, &mut mapper, &mut frame_allocator)
+        .expect("mapping user code failed");
+    usermode::map_user(
+        VirtAddr::new(STACK_TOP - 4096),
+        4096,
+        PageTableFlags::WRITABLE,
+        &mut mapper,
+        &mut frame_allocator,
+    )
+    .expect("mapping user stack failed");
+
+    // the whole program: int3
+    unsafe { (ENTRY as *mut u8).write(0xcc) };
+    unsafe { usermode::enter(VirtAddr::new(ENTRY), VirtAddr::new(STACK_TOP)) }
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.breakpoint
+            .set_handler_fn(test_breakpoint_handler)
+            .set_privilege_level(PrivilegeLevel::Ring3);
+        idt.general_protection_fault.set_handler_fn(test_general_protection_handler);
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_breakpoint_handler(stack_frame: InterruptStackFrame) {
+    assert_eq!(stack_frame.code_segment, u64::from(gdt::selectors().user_code.0));
+    assert_eq!(stack_frame.stack_segment, u64::from(gdt::selectors().user_data.0));
+    // int3 is one byte long and pushes nothing on the user stack
+    assert_eq!(stack_frame.instruction_pointer.as_u64(), ENTRY + 1);
+    assert_eq!(stack_frame.stack_pointer.as_u64(), STACK_TOP);
+
+    // and we're on the kernel stack from the TSS
+    let here = VirtAddr::from_ptr(&stack_frame);
+    let top = gdt::boot_kernel_stack();
+    assert!(here < top && here > top - 4096 * 5u64);
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+extern "x86-interrupt" fn test_general_protection_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    panic!("general protection fault {:#x}\n{:#?}", error_code, stack_frame);
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: x86_64::structures::idt::PageFaultErrorCode,
+) {
+    panic!("page fault {:?}\n{:#?}", error_code, stack_frame);
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    panic!("double fault\n{:#?}", stack_frame);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
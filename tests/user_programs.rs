@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use mooos::file::{self, File, FileTable};
+use mooos::process::{self, ExitStatus};
+use mooos::sync::Mutex;
+use mooos::syscall::Error;
+use mooos::{fs, memory, programs, thread};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::allocator;
+    use mooos::memory::BootInfoFrameAllocator;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    allocator::init_heap(&mut mapper, &mut frame_allocator)
+        .expect("heap initialization failed");
+    thread::init();
+    memory::init_global(mapper, frame_allocator);
+    programs::install();
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+/// Standard input: hands out its bytes, then reports the end of the file.
+struct Input(Mutex<Vec<u8>>);
+
+impl File for Input {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut input = self.0.lock();
+        let len = buf.len().min(input.len());
+        buf[..len].copy_from_slice(&input[..len]);
+        input.drain(..len);
+        Ok(len)
+    }
+}
+
+/// Standard output: keeps whatever is written.
+struct Output(Mutex<Vec<u8>>);
+
+impl File for Output {
+    fn write(&self, buf: &[u8]) -> Result<usize, Error> {
+        self.0.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+/// Runs `/bin/<name>` to the end with `input` on standard input, and
+/// returns how it exited and what it printed.
+fn run(name: &str, argv: &[&str], input: &[u8]) -> (ExitStatus, String) {
+    let image = fs::lookup(&alloc::format!("/bin/{}", name)).expect("program isn't installed");
+    let output = Arc::new(Output(Mutex::new(Vec::new())));
+    let mut files = FileTable::new();
+    files.open(Arc::new(Input(Mutex::new(Vec::from(input))))).unwrap();
+    files.open(output.clone()).unwrap();
+    files.open(Arc::new(file::Serial)).unwrap();
+
+    let pid = process::spawn_with_files(image, argv, &[], files).expect("spawn failed");
+    let (_, status) = process::waitpid(Some(pid)).expect("wait failed");
+    let printed = String::from_utf8(output.0.lock().clone()).expect("output isn't UTF-8");
+    (status, printed)
+}
+
+#[test_case]
+fn hello() {
+    let (status, output) = run("hello", &["hello"], b"");
+    assert_eq!(status, ExitStatus::Exited(0));
+    assert!(output.starts_with("hello from process "), "{:?}", output);
+}
+
+#[test_case]
+fn echo() {
+    let (status, output) = run("echo", &["echo", "moo", "oos"], b"");
+    assert_eq!(status, ExitStatus::Exited(0));
+    assert_eq!(output, "moo oos\n");
+}
+
+#[test_case]
+fn cat() {
+    // more than cat reads at once
+    let input: Vec<u8> = (0..2000).map(|i| b'a' + (i % 26) as u8).collect();
+    let (status, output) = run("cat", &["cat"], &input);
+    assert_eq!(status, ExitStatus::Exited(0));
+    assert_eq!(output.as_bytes(), &input[..]);
+}
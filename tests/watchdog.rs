@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(mooos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::time::Duration;
+use mooos::time::{self, tsc};
+use mooos::{hpet, serial_println, watchdog};
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use mooos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    mooos::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+    hpet::init(&mut mapper, &mut frame_allocator).expect("hpet initialization failed");
+    watchdog::init(&mut mapper, &mut frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    mooos::test_panic_handler(info)
+}
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Burns cycles for `duration` without relying on interrupts to tell time.
+fn spin_for(duration: Duration) {
+    let cycles = (duration.as_nanos() * u128::from(tsc::frequency().unwrap()) / 1_000_000_000) as u64;
+    let start = tsc::read();
+    while tsc::read() - start < cycles {
+        core::hint::spin_loop();
+    }
+}
+
+#[test_case]
+fn quiet_while_petted() {
+    let lockups = watchdog::lockups();
+    watchdog::start(TIMEOUT);
+    let end = time::uptime() + TIMEOUT * 3;
+    while time::uptime() < end {
+        watchdog::pet();
+        x86_64::instructions::hlt();
+    }
+    watchdog::stop();
+    assert_eq!(watchdog::lockups(), lockups);
+}
+
+#[test_case]
+fn detects_busy_loop() {
+    let lockups = watchdog::lockups();
+    watchdog::start(TIMEOUT);
+    spin_for(TIMEOUT * 3);
+    watchdog::stop();
+    assert!(watchdog::lockups() > lockups);
+}
+
+#[test_case]
+fn detects_hang_with_interrupts_disabled() {
+    if watchdog::source() != watchdog::Source::Nmi {
+        serial_println!("(no NMI source, skipped)");
+        return;
+    }
+    let lockups = watchdog::lockups();
+    watchdog::start(TIMEOUT);
+    interrupts::without_interrupts(|| spin_for(TIMEOUT * 3));
+    watchdog::stop();
+    assert!(watchdog::lockups() > lockups);
+}
@@ -0,0 +1,9 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let script = dir.join("link.ld");
+    println!("cargo:rustc-link-arg-bins=-T{}", script.display());
+    println!("cargo:rerun-if-changed={}", script.display());
+}
@@ -0,0 +1,27 @@
+//! Copies standard input to standard output until the end of the input.
+
+#![no_std]
+#![no_main]
+
+use mooos_user::io::{self, STDIN, STDOUT};
+use mooos_user::{entry, eprintln, syscall, Args};
+
+entry!(main);
+
+fn main(_args: Args) -> u8 {
+    let mut buf = [0u8; 512];
+    loop {
+        let read = match syscall::read(STDIN, &mut buf) {
+            Ok(0) => return 0,
+            Ok(read) => read,
+            Err(err) => {
+                eprintln!("cat: read failed: {:?}", err);
+                return 1;
+            }
+        };
+        if let Err(err) = io::write_all(STDOUT, &buf[..read]) {
+            eprintln!("cat: write failed: {:?}", err);
+            return 1;
+        }
+    }
+}
@@ -0,0 +1,17 @@
+//! Prints its arguments, separated by spaces.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use mooos_user::{entry, println, Args};
+
+entry!(main);
+
+fn main(args: Args) -> u8 {
+    let words: Vec<&str> = args.iter().skip(1).collect();
+    println!("{}", words.join(" "));
+    0
+}
@@ -0,0 +1,13 @@
+//! Says hello.
+
+#![no_std]
+#![no_main]
+
+use mooos_user::{entry, println, syscall, Args};
+
+entry!(main);
+
+fn main(_args: Args) -> u8 {
+    println!("hello from process {}", syscall::getpid());
+    0
+}
@@ -0,0 +1,73 @@
+//! The global allocator: a linked list heap that grows with `mmap`.
+//!
+//! The heap starts out empty at `HEAP_START` and, whenever an allocation
+//! doesn't fit, maps more memory right after its end, so it stays one
+//! contiguous block.
+
+use crate::syscall::{self, MMAP_WRITE};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+/// Where the heap starts, well away from programs and their stacks.
+pub const HEAP_START: u64 = 0x0000_2000_0000_0000;
+/// The heap grows by at least this much at a time.
+const GROW_BY: u64 = 64 * 1024;
+
+pub struct GrowingHeap {
+    heap: Mutex<Heap>,
+    /// End of the memory mapped for the heap so far.
+    end: Mutex<u64>,
+}
+
+impl GrowingHeap {
+    pub const fn new() -> Self {
+        GrowingHeap {
+            heap: Mutex::new(Heap::empty()),
+            end: Mutex::new(HEAP_START),
+        }
+    }
+
+    /// Maps at least `size` more bytes and hands them to the heap.
+    fn grow(&self, heap: &mut Heap, size: u64) -> bool {
+        let mut end = self.end.lock();
+        let size = (size.max(GROW_BY) + 4095) & !4095;
+        if syscall::mmap(*end, size, MMAP_WRITE).is_err() {
+            return false;
+        }
+        if *end == HEAP_START {
+            unsafe { heap.init(HEAP_START as usize, size as usize) };
+        } else {
+            unsafe { heap.extend(size as usize) };
+        }
+        *end += size;
+        true
+    }
+}
+
+impl Default for GrowingHeap {
+    fn default() -> Self {
+        GrowingHeap::new()
+    }
+}
+
+unsafe impl GlobalAlloc for GrowingHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        loop {
+            if let Ok(allocation) = heap.allocate_first_fit(layout) {
+                return allocation.as_ptr();
+            }
+            // room for the block, its alignment and the list's bookkeeping
+            let needed = (layout.size() + layout.align() + 64) as u64;
+            if !self.grow(&mut heap, needed) {
+                return ptr::null_mut();
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.lock().deallocate(NonNull::new_unchecked(ptr), layout);
+    }
+}
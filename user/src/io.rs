@@ -0,0 +1,59 @@
+//! Printing to standard output and error.
+
+use crate::syscall;
+use core::fmt;
+
+pub const STDIN: u64 = 0;
+pub const STDOUT: u64 = 1;
+pub const STDERR: u64 = 2;
+
+/// Writes everything in `buf` to `fd`, retrying short writes.
+pub fn write_all(fd: u64, mut buf: &[u8]) -> Result<(), syscall::Error> {
+    while !buf.is_empty() {
+        let written = syscall::write(fd, buf)?;
+        buf = &buf[written..];
+    }
+    Ok(())
+}
+
+/// `fmt::Write` for a file descriptor.
+pub struct Fd(pub u64);
+
+impl fmt::Write for Fd {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write_all(self.0, s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(fd: u64, args: fmt::Arguments) {
+    use fmt::Write;
+    // nowhere to report a failure to print
+    let _ = Fd(fd).write_fmt(args);
+}
+
+/// Prints to standard output.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDOUT, format_args!($($arg)*)));
+}
+
+/// Prints to standard output, with a newline.
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Prints to standard error.
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::io::_print($crate::io::STDERR, format_args!($($arg)*)));
+}
+
+/// Prints to standard error, with a newline.
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,123 @@
+//! A tiny runtime for mooos user programs.
+//!
+//! It provides `_start`, which collects the arguments the kernel put on the
+//! stack and calls the function named with `entry!`, exiting with whatever
+//! that returns; system call wrappers; signal handlers; `print!` and
+//! friends; a heap; and a panic handler that reports to standard error and
+//! exits with 101.
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! use mooos_user::{entry, println, Args};
+//!
+//! entry!(main);
+//!
+//! fn main(args: Args) -> u8 {
+//!     println!("{} arguments", args.len());
+//!     0
+//! }
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+
+pub mod heap;
+pub mod io;
+pub mod signal;
+pub mod syscall;
+
+#[global_allocator]
+static ALLOCATOR: heap::GrowingHeap = heap::GrowingHeap::new();
+
+// the kernel starts us with rsp at argc, see the kernel's `loader`
+global_asm!(
+    ".global _start",
+    "_start:",
+    "mov rdi, rsp",
+    "call mooos_user_start",
+    "ud2",
+);
+
+extern "Rust" {
+    /// Defined by `entry!`.
+    fn mooos_user_main(args: Args) -> u8;
+}
+
+#[no_mangle]
+extern "C" fn mooos_user_start(stack: *const u64) -> ! {
+    let args = unsafe { Args::from_stack(stack) };
+    let status = unsafe { mooos_user_main(args) };
+    syscall::exit(status)
+}
+
+/// Makes `$path`, a `fn(Args) -> u8`, the program's main function.
+#[macro_export]
+macro_rules! entry {
+    ($path:path) => {
+        #[export_name = "mooos_user_main"]
+        pub fn __mooos_user_main(args: $crate::Args) -> u8 {
+            let main: fn($crate::Args) -> u8 = $path;
+            main(args)
+        }
+    };
+}
+
+/// The program's arguments, starting with its name.
+#[derive(Clone, Copy)]
+pub struct Args {
+    argc: usize,
+    argv: *const *const u8,
+}
+
+impl Args {
+    unsafe fn from_stack(stack: *const u64) -> Self {
+        Args {
+            argc: *stack as usize,
+            argv: stack.add(1) as *const *const u8,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.argc
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.argc == 0
+    }
+
+    /// Argument `i`, if there is one. Arguments that aren't UTF-8 read as
+    /// empty.
+    pub fn get(&self, i: usize) -> Option<&'static str> {
+        if i >= self.argc {
+            return None;
+        }
+        let bytes = unsafe { c_string(*self.argv.add(i)) };
+        Some(core::str::from_utf8(bytes).unwrap_or(""))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'static str> {
+        let args = *self;
+        (0..args.argc).filter_map(move |i| args.get(i))
+    }
+}
+
+/// The bytes of the null-terminated string at `ptr`, without the null.
+unsafe fn c_string(ptr: *const u8) -> &'static [u8] {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    core::slice::from_raw_parts(ptr, len)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    eprintln!("panicked: {}", info);
+    syscall::exit(101)
+}
@@ -0,0 +1,76 @@
+//! Catching, ignoring and blocking signals.
+//!
+//! Handlers are plain `extern "C" fn(i32)` functions, called with the
+//! signal number. They return through `restorer`, which asks the kernel to
+//! resume whatever the signal interrupted.
+
+use crate::syscall::{self, Error, SigAction};
+use core::arch::global_asm;
+
+pub const SIGINT: u8 = 2;
+pub const SIGILL: u8 = 4;
+pub const SIGFPE: u8 = 8;
+pub const SIGKILL: u8 = 9;
+pub const SIGUSR1: u8 = 10;
+pub const SIGSEGV: u8 = 11;
+pub const SIGUSR2: u8 = 12;
+pub const SIGPIPE: u8 = 13;
+pub const SIGTERM: u8 = 15;
+pub const SIGCHLD: u8 = 17;
+
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+pub const SIG_BLOCK: u64 = 0;
+pub const SIG_UNBLOCK: u64 = 1;
+pub const SIG_SETMASK: u64 = 2;
+
+global_asm!(
+    ".global mooos_user_restorer",
+    "mooos_user_restorer:",
+    // syscall::number::SIGRETURN
+    "mov eax, 13",
+    "syscall",
+    "ud2",
+);
+
+extern "C" {
+    /// Where handlers return to: makes the `sigreturn` call.
+    fn mooos_user_restorer();
+}
+
+/// The mask with just `signal` in it.
+pub fn mask(signal: u8) -> u64 {
+    1 << (signal - 1)
+}
+
+/// Runs `handler` whenever `signal` arrives, with `signal` blocked until it
+/// returns.
+pub fn handle(signal: u8, handler: extern "C" fn(i32)) -> Result<(), Error> {
+    let action = SigAction {
+        handler: handler as usize as u64,
+        mask: 0,
+        restorer: mooos_user_restorer as *const () as u64,
+    };
+    syscall::sigaction(signal, Some(&action)).map(|_| ())
+}
+
+pub fn ignore(signal: u8) -> Result<(), Error> {
+    let action = SigAction { handler: SIG_IGN, ..SigAction::default() };
+    syscall::sigaction(signal, Some(&action)).map(|_| ())
+}
+
+/// Goes back to the default action: ignore SIGCHLD, exit for the others.
+pub fn reset(signal: u8) -> Result<(), Error> {
+    syscall::sigaction(signal, Some(&SigAction::default())).map(|_| ())
+}
+
+/// Holds off the signals in `set` until they're unblocked.
+pub fn block(set: u64) -> Result<(), Error> {
+    syscall::sigprocmask(SIG_BLOCK, set).map(|_| ())
+}
+
+/// Lets the signals in `set` through, delivering any that are pending.
+pub fn unblock(set: u64) -> Result<(), Error> {
+    syscall::sigprocmask(SIG_UNBLOCK, set).map(|_| ())
+}
@@ -0,0 +1,255 @@
+//! The kernel's system calls, see `syscall` in the kernel for the ABI.
+
+use core::arch::asm;
+
+/// Call numbers; these must match the kernel's `syscall::number`.
+pub mod number {
+    pub const WRITE: u64 = 0;
+    pub const READ: u64 = 1;
+    pub const EXIT: u64 = 2;
+    pub const YIELD: u64 = 3;
+    pub const MMAP: u64 = 4;
+    pub const GETPID: u64 = 5;
+    pub const SLEEP: u64 = 6;
+    pub const WAIT: u64 = 7;
+    pub const FORK: u64 = 8;
+    pub const EXEC: u64 = 9;
+    pub const SIGACTION: u64 = 10;
+    pub const SIGPROCMASK: u64 = 11;
+    pub const KILL: u64 = 12;
+    pub const SIGRETURN: u64 = 13;
+    pub const PIPE: u64 = 14;
+    pub const CLOSE: u64 = 15;
+    pub const DUP2: u64 = 16;
+    pub const CHANNEL: u64 = 17;
+    pub const SEND: u64 = 18;
+    pub const RECV: u64 = 19;
+}
+
+/// `mmap` flag: make the pages writable.
+pub const MMAP_WRITE: u64 = 1 << 0;
+
+/// `recv` flag: fail with `Error::TRY_AGAIN` rather than wait.
+pub const RECV_NONBLOCK: u64 = 1 << 0;
+
+/// Bytes in a channel message.
+pub const MESSAGE_SIZE: usize = 64;
+
+/// A failed call, holding the Linux errno the kernel returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error(pub i64);
+
+impl Error {
+    pub const NO_SUCH_FILE: Error = Error(2);
+    pub const NO_SUCH_PROCESS: Error = Error(3);
+    pub const INTERRUPTED: Error = Error(4);
+    pub const BAD_FILE_DESCRIPTOR: Error = Error(9);
+    pub const NO_CHILD_PROCESS: Error = Error(10);
+    pub const TRY_AGAIN: Error = Error(11);
+    pub const OUT_OF_MEMORY: Error = Error(12);
+    pub const BAD_ADDRESS: Error = Error(14);
+    pub const INVALID_ARGUMENT: Error = Error(22);
+    pub const BROKEN_PIPE: Error = Error(32);
+}
+
+/// Makes system call `number` with `args`, turning negative results into
+/// errors.
+///
+/// # Safety
+///
+/// Pointers among `args` must be valid for what the call does with them.
+pub unsafe fn syscall(number: u64, args: [u64; 6]) -> Result<u64, Error> {
+    let result: i64;
+    asm!(
+        "syscall",
+        inlateout("rax") number as i64 => result,
+        in("rdi") args[0],
+        in("rsi") args[1],
+        in("rdx") args[2],
+        in("r10") args[3],
+        in("r8") args[4],
+        in("r9") args[5],
+        out("rcx") _,
+        out("r11") _,
+        options(nostack),
+    );
+    // errors are small negative numbers, anything else is a result
+    if (-4095..0).contains(&result) {
+        Err(Error(-result))
+    } else {
+        Ok(result as u64)
+    }
+}
+
+pub fn write(fd: u64, buf: &[u8]) -> Result<usize, Error> {
+    unsafe { syscall(number::WRITE, [fd, buf.as_ptr() as u64, buf.len() as u64, 0, 0, 0]) }
+        .map(|written| written as usize)
+}
+
+/// Blocks until something can be read; 0 means the end of the file.
+pub fn read(fd: u64, buf: &mut [u8]) -> Result<usize, Error> {
+    unsafe { syscall(number::READ, [fd, buf.as_mut_ptr() as u64, buf.len() as u64, 0, 0, 0]) }
+        .map(|read| read as usize)
+}
+
+pub fn exit(status: u8) -> ! {
+    let _ = unsafe { syscall(number::EXIT, [u64::from(status), 0, 0, 0, 0, 0]) };
+    unreachable!("exit returned");
+}
+
+pub fn yield_now() {
+    let _ = unsafe { syscall(number::YIELD, [0; 6]) };
+}
+
+/// Maps `len` bytes of fresh zeroed memory at `addr`, which must be page
+/// aligned and not mapped yet.
+pub fn mmap(addr: u64, len: u64, flags: u64) -> Result<*mut u8, Error> {
+    unsafe { syscall(number::MMAP, [addr, len, flags, 0, 0, 0]) }.map(|addr| addr as *mut u8)
+}
+
+pub fn getpid() -> u64 {
+    unsafe { syscall(number::GETPID, [0; 6]) }.unwrap_or(0)
+}
+
+pub fn sleep(milliseconds: u64) {
+    let _ = unsafe { syscall(number::SLEEP, [milliseconds, 0, 0, 0, 0, 0]) };
+}
+
+/// Waits for child `pid` to exit, or any child if `None`, and returns its
+/// pid and status, encoded like Linux's.
+pub fn wait(pid: Option<u64>) -> Result<(u64, u32), Error> {
+    let mut status = 0u32;
+    let pid = pid.map_or(-1i64 as u64, |pid| pid);
+    let child = unsafe { syscall(number::WAIT, [pid, &mut status as *mut u32 as u64, 0, 0, 0, 0]) }?;
+    Ok((child, status))
+}
+
+/// Makes a pipe and returns its read end and its write end.
+pub fn pipe() -> Result<(u64, u64), Error> {
+    let mut fds = [0u32; 2];
+    unsafe { syscall(number::PIPE, [fds.as_mut_ptr() as u64, 0, 0, 0, 0, 0]) }?;
+    Ok((u64::from(fds[0]), u64::from(fds[1])))
+}
+
+pub fn close(fd: u64) -> Result<(), Error> {
+    unsafe { syscall(number::CLOSE, [fd, 0, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Makes `new_fd` refer to the same file as `fd`, closing whatever it was
+/// first.
+pub fn dup2(fd: u64, new_fd: u64) -> Result<(), Error> {
+    unsafe { syscall(number::DUP2, [fd, new_fd, 0, 0, 0, 0]) }.map(|_| ())
+}
+
+/// Makes a channel and returns its two endpoints.
+pub fn channel() -> Result<(u64, u64), Error> {
+    let mut fds = [0u32; 2];
+    unsafe { syscall(number::CHANNEL, [fds.as_mut_ptr() as u64, 0, 0, 0, 0, 0]) }?;
+    Ok((u64::from(fds[0]), u64::from(fds[1])))
+}
+
+/// A channel message as the kernel takes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Message {
+    pub data: [u8; MESSAGE_SIZE],
+    /// A file descriptor handed over with the data, or -1 for none.
+    pub handle: i64,
+}
+
+impl Message {
+    /// A message with `data` at the start and zeroes after it, and no
+    /// handle.
+    pub fn new(data: &[u8]) -> Self {
+        let mut message = Message::default();
+        message.data[..data.len()].copy_from_slice(data);
+        message
+    }
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Message { data: [0; MESSAGE_SIZE], handle: -1 }
+    }
+}
+
+/// Sends `message` through channel endpoint `fd`, blocking while the other
+/// end has a full queue. The receiver gets its own copy of the handle.
+pub fn send(fd: u64, message: &Message) -> Result<(), Error> {
+    unsafe { syscall(number::SEND, [fd, message as *const Message as u64, 0, 0, 0, 0]) }
+        .map(|_| ())
+}
+
+/// Receives a message from channel endpoint `fd`, blocking until there is
+/// one. Its handle, if any, is opened as a new file descriptor.
+pub fn recv(fd: u64) -> Result<Message, Error> {
+    receive(fd, 0)
+}
+
+/// Like `recv`, but fails with `Error::TRY_AGAIN` if nothing has arrived.
+pub fn try_recv(fd: u64) -> Result<Message, Error> {
+    receive(fd, RECV_NONBLOCK)
+}
+
+fn receive(fd: u64, flags: u64) -> Result<Message, Error> {
+    let mut message = Message::default();
+    let args = [fd, &mut message as *mut Message as u64, flags, 0, 0, 0];
+    unsafe { syscall(number::RECV, args) }?;
+    Ok(message)
+}
+
+/// Returns the child's pid in the parent, and 0 in the child.
+pub fn fork() -> Result<u64, Error> {
+    unsafe { syscall(number::FORK, [0; 6]) }
+}
+
+/// Replaces this program with the one at `path`. `path` and every argument
+/// must end in a null byte, and both lists in a null pointer. Only returns
+/// on failure.
+pub fn exec(path: &[u8], argv: &[*const u8], envp: &[*const u8]) -> Error {
+    assert!(path.ends_with(&[0]), "exec path isn't null terminated");
+    assert!(argv.last() == Some(&core::ptr::null()), "argv isn't null terminated");
+    assert!(envp.last() == Some(&core::ptr::null()), "envp isn't null terminated");
+    let args = [path.as_ptr() as u64, argv.as_ptr() as u64, envp.as_ptr() as u64, 0, 0, 0];
+    match unsafe { syscall(number::EXEC, args) } {
+        Err(err) => err,
+        Ok(_) => unreachable!("exec returned"),
+    }
+}
+
+/// A signal action as the kernel takes it, see `signal` for the friendly
+/// way to set one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct SigAction {
+    /// `signal::SIG_DFL`, `signal::SIG_IGN` or the handler's address.
+    pub handler: u64,
+    /// Blocked while the handler runs.
+    pub mask: u64,
+    /// Where the handler returns to, which must call `sigreturn`.
+    pub restorer: u64,
+}
+
+/// Sets the action for `signal` if `action` is given, and returns the old
+/// one.
+pub fn sigaction(signal: u8, action: Option<&SigAction>) -> Result<SigAction, Error> {
+    let mut old = SigAction::default();
+    let action = action.map_or(0, |action| action as *const SigAction as u64);
+    let args = [u64::from(signal), action, &mut old as *mut SigAction as u64, 0, 0, 0];
+    unsafe { syscall(number::SIGACTION, args) }?;
+    Ok(old)
+}
+
+/// Changes the blocked signals, `how` being one of `signal::SIG_BLOCK`,
+/// `SIG_UNBLOCK` and `SIG_SETMASK`, and returns the old mask.
+pub fn sigprocmask(how: u64, set: u64) -> Result<u64, Error> {
+    let mut old = 0u64;
+    let args = [how, &set as *const u64 as u64, &mut old as *mut u64 as u64, 0, 0, 0];
+    unsafe { syscall(number::SIGPROCMASK, args) }?;
+    Ok(old)
+}
+
+/// Sends `signal` to process `pid`; signal 0 only checks it exists.
+pub fn kill(pid: u64, signal: u8) -> Result<(), Error> {
+    unsafe { syscall(number::KILL, [pid, u64::from(signal), 0, 0, 0, 0]) }.map(|_| ())
+}